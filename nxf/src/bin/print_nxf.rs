@@ -1,11 +1,199 @@
 use std::fs::File;
 use std::env;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::num::ParseIntError;
+use std::process;
 
-use nxf::NxfObjGeom;
+use nxf::{NxfObjGeom, OffsetReader, TracingReader};
+
+const USAGE: &str = "usage: print_nxf [--trace | --lenient | --summary | --list] [--offset=N] [--raw=OFFSET:LEN [--raw-out=FILE]] FILE
+numbers are decimal or 0x prefixed hex";
+
+/// Prints `error` and the usage, then exits.
+fn usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+/// Parses a decimal or `0x` prefixed hex number.
+fn parse_num(s: &str) -> Result<u64, ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Parses `--raw`'s `OFFSET:LEN`.
+fn parse_raw(s: &str) -> Result<(u64, u64), String> {
+    let mut parts = s.splitn(2, ':');
+    let raw_offset = parts.next().unwrap_or("");
+    let raw_len = parts.next().ok_or_else(|| format!("--raw={}: expected OFFSET:LEN", s))?;
+    let raw_offset = parse_num(raw_offset).map_err(|err| format!("--raw={}: bad offset: {}", s, err))?;
+    let raw_len = parse_num(raw_len).map_err(|err| format!("--raw={}: bad length: {}", s, err))?;
+    Ok((raw_offset, raw_len))
+}
+
+/// Reads `--raw`'s `raw_len` bytes at `raw_offset`, relative to `offset`.
+fn read_raw<R>(mut read: R, offset: u64, raw_offset: u64, raw_len: u64) -> io::Result<Vec<u8>>
+    where R: Read + Seek
+{
+    let mut bytes = vec![0; raw_len as usize];
+    read.seek(SeekFrom::Start(offset + raw_offset))?;
+    read.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn hex_dump<W>(mut out: W, offset: u64, bytes: &[u8]) -> io::Result<()>
+    where W: Write
+{
+    for (line_idx, line) in bytes.chunks(16).enumerate() {
+        let hex = line.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let ascii = line.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect::<String>();
+        writeln!(out, "{:08x}  {:<47}  {}", offset + line_idx as u64 * 16, hex, ascii)?;
+    }
+    Ok(())
+}
+
+fn print_summary(nxf: &NxfObjGeom, len: u64) {
+    println!("size: {:#x} bytes", len);
+    println!("vertices: {}", nxf.arrays.verts.len());
+    println!("facelist sets: {}", nxf.facelist_sets.len());
+    println!("facelists: {}", nxf.facelists().count());
+    println!("materials: {}", nxf.materials.len());
+    println!("textures: {}", nxf.texture_names().join(", "));
+    println!("env mapping: {}", if nxf.uses_env_mapping() { "yes" } else { "no" });
+}
+
+fn write_list<W>(nxf: &NxfObjGeom, mut out: W) -> io::Result<()>
+    where W: Write
+{
+    for (set_idx, facelist_set) in nxf.facelist_sets.iter().enumerate() {
+        writeln!(out, "set {} (flags {:#x})", set_idx, facelist_set.flags)?;
+        for (facelist_idx, facelist) in facelist_set.facelists.iter().enumerate() {
+            let material = match &facelist.material {
+                Some(material) => material.tex_name.as_str(),
+                None => "(none)",
+            };
+            writeln!(out, "  facelist {}: {} x{} material {}",
+                facelist_idx, facelist.faces.type_name(), facelist.faces.len(), material)?;
+        }
+    }
+    Ok(())
+}
 
 fn main() {
-    let filename = env::args().skip(1).next().unwrap();
-    let f = File::open(filename).unwrap();
-    let nxf = NxfObjGeom::from_read(f).unwrap();
-    println!("{:#?}", nxf);
-}
\ No newline at end of file
+    let mut trace = false;
+    let mut lenient = false;
+    let mut summary = false;
+    let mut list = false;
+    let mut offset = 0;
+    let mut raw = None;
+    let mut raw_out = None;
+    let mut filename = None;
+    for arg in env::args().skip(1) {
+        if arg == "--trace" {
+            trace = true;
+        } else if arg == "--lenient" {
+            lenient = true;
+        } else if arg == "--summary" {
+            summary = true;
+        } else if arg == "--list" {
+            list = true;
+        } else if let Some(value) = arg.strip_prefix("--offset=") {
+            offset = parse_num(value)
+                .unwrap_or_else(|err| usage(&format!("{}: {}", arg, err)));
+        } else if let Some(value) = arg.strip_prefix("--raw=") {
+            // --raw=OFFSET:LEN, relative to --offset like the file's pointers.
+            raw = Some(parse_raw(value).unwrap_or_else(|err| usage(&err)));
+        } else if let Some(value) = arg.strip_prefix("--raw-out=") {
+            raw_out = Some(value.to_owned());
+        } else {
+            filename = Some(arg);
+        }
+    }
+
+    let filename = filename.unwrap_or_else(|| usage("no file given"));
+    let mut f = File::open(filename).unwrap();
+
+    if let Some((raw_offset, raw_len)) = raw {
+        let bytes = read_raw(&mut f, offset, raw_offset, raw_len).unwrap();
+        match raw_out {
+            Some(out_filename) => File::create(out_filename).unwrap().write_all(&bytes).unwrap(),
+            None => hex_dump(io::stdout().lock(), raw_offset, &bytes).unwrap(),
+        }
+        return;
+    }
+
+    if lenient {
+        f.seek(SeekFrom::Start(offset)).unwrap();
+        let (nxf, error) = NxfObjGeom::from_read_lenient(OffsetReader::new(f, offset)).unwrap();
+        println!("{:#?}", nxf);
+        if let Some(error) = error {
+            eprintln!("Error (partial result above): {}", error);
+        }
+        return;
+    }
+
+    if summary {
+        f.seek(SeekFrom::Start(offset)).unwrap();
+        let (nxf, len) = NxfObjGeom::from_read_with_len(OffsetReader::new(f, offset)).unwrap();
+        print_summary(&nxf, len);
+        return;
+    }
+
+    let nxf = if trace {
+        NxfObjGeom::from_read_at(TracingReader::new(f), offset).unwrap()
+    } else {
+        NxfObjGeom::from_read_at(f, offset).unwrap()
+    };
+    if list {
+        write_list(&nxf, io::stdout().lock()).unwrap();
+    } else {
+        println!("{:#?}", nxf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use nxf::fixture::{self, NxfFixture};
+
+    use super::*;
+
+    #[test]
+    fn raw_dump_matches_fixture_region() {
+        let nxf = NxfFixture::quad();
+        let base = 0x20;
+        let mut container = vec![0xff; base];
+        container.extend_from_slice(&nxf.data);
+
+        let (raw_offset, raw_len) = parse_raw("0x500:8").unwrap();
+        let bytes = read_raw(Cursor::new(&container), base as u64, raw_offset, raw_len).unwrap();
+        assert_eq!(&bytes[..], &nxf.data[fixture::STRING_DATA..fixture::STRING_DATA + 8]);
+
+        let mut dump = Vec::new();
+        hex_dump(&mut dump, raw_offset, &bytes).unwrap();
+        assert_eq!(String::from_utf8(dump).unwrap(),
+            format!("00000500  66 6f 6f 2e 74 65 78 00{:24}  foo.tex.\n", ""));
+    }
+
+    #[test]
+    fn list_has_a_line_per_facelist() {
+        let nxf = NxfObjGeom::from_read(Cursor::new(NxfFixture::quad().data)).unwrap();
+        let mut list = Vec::new();
+        write_list(&nxf, &mut list).unwrap();
+        let list = String::from_utf8(list).unwrap();
+
+        let facelists: usize = nxf.facelist_sets.iter().map(|set| set.facelists.len()).sum();
+        assert_eq!(list.lines().filter(|line| line.starts_with("  facelist ")).count(), facelists);
+        assert_eq!(list.lines().filter(|line| line.starts_with("set ")).count(), nxf.facelist_sets.len());
+        assert!(list.contains("  facelist 0: TexUnlitTri x2 material foo.tex\n"), "{}", list);
+    }
+}
@@ -0,0 +1,41 @@
+//! A logger for tests, here and in other crates', that keeps the warnings
+//! logged on each thread, so tests running in parallel only see their own.
+
+use std::cell::RefCell;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+thread_local! {
+    static WARNINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+struct CaptureLog;
+
+impl Log for CaptureLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= LevelFilter::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            WARNINGS.with(|warnings| warnings.borrow_mut().push(record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLog = CaptureLog;
+
+/// Runs `f`, returning its result along with the warnings it logged.
+pub fn capture_warnings<F, T>(f: F) -> (T, Vec<String>)
+    where F: FnOnce() -> T
+{
+    // Only the first call installs the logger; later ones find it set.
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Warn);
+    }
+    WARNINGS.with(|warnings| warnings.borrow_mut().clear());
+    let result = f();
+    (result, WARNINGS.with(|warnings| warnings.replace(Vec::new())))
+}
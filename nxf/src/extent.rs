@@ -0,0 +1,68 @@
+use std::io::{Read, Seek, SeekFrom, Error as IOError};
+
+/// A `Read + Seek` wrapper that records the furthest offset read from.
+///
+/// The parsers seek around the file, so where they stop says little about
+/// how much of the stream the structure occupies; `end` does.
+pub struct ExtentReader<R> {
+    inner: R,
+    end: u64,
+}
+
+impl<R> ExtentReader<R> {
+    pub fn new(inner: R) -> ExtentReader<R> {
+        ExtentReader {
+            inner: inner,
+            end: 0,
+        }
+    }
+
+    /// Offset just past the last byte read so far.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R> Read for ExtentReader<R>
+    where R: Read + Seek
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        let offset = self.inner.stream_position()?;
+        let size = self.inner.read(buf)?;
+        self.end = self.end.max(offset + size as u64);
+        Ok(size)
+    }
+}
+
+impl<R> Seek for ExtentReader<R>
+    where R: Seek
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IOError> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn end_is_furthest_byte_read() {
+        let mut read = ExtentReader::new(Cursor::new(vec![0; 0x20]));
+        let mut buf = [0; 4];
+        read.seek(SeekFrom::Start(0x10)).unwrap();
+        read.read_exact(&mut buf).unwrap();
+        read.seek(SeekFrom::Start(0x4)).unwrap();
+        read.read_exact(&mut buf).unwrap();
+        assert_eq!(read.end(), 0x14);
+        // Seeking without reading doesn't count.
+        read.seek(SeekFrom::End(0)).unwrap();
+        assert_eq!(read.end(), 0x14);
+    }
+}
@@ -0,0 +1,241 @@
+//! Hand laid out NXF buffers for tests. Every structure sits at a fixed
+//! offset, so a test can patch a field in place to build a broken file.
+
+use crate::{
+    Color, NxfArray, NxfFaces, NxfFacelist, NxfFacelistSet, NxfMaterial, NxfObjGeom, NxfTexUnlitTri, Uv, Vec3,
+};
+
+pub const HEADER: usize = 0x0;
+pub const STRING_TABLE: usize = 0x100;
+pub const MATERIAL: usize = 0x140;
+pub const ARRAYS: usize = 0x200;
+pub const VERTS: usize = 0x280;
+pub const COLORS: usize = 0x2c0;
+pub const UVS: usize = 0x2e0;
+pub const FACELIST_SET: usize = 0x340;
+pub const FACELIST: usize = 0x380;
+pub const FACES: usize = 0x400;
+pub const STRING_DATA: usize = 0x500;
+pub const LEN: usize = 0x600;
+
+/// Offset of the header's `arrays_offset` field.
+pub const HEADER_ARRAYS_OFFSET: usize = HEADER + 0x24;
+/// Offset of the header's `first_facelist_set` field.
+pub const HEADER_FIRST_SET: usize = HEADER + 0x28;
+
+/// A minimal NXF: one material, a textured quad of four verts with two
+/// colors and four uvs, and one facelist set holding one facelist of two
+/// TexUnlitTri faces.
+pub struct NxfFixture {
+    pub data: Vec<u8>,
+}
+
+impl NxfFixture {
+    pub fn quad() -> NxfFixture {
+        let mut fixture = NxfFixture {
+            data: vec![0; LEN],
+        };
+
+        // header
+        fixture.put(HEADER, b"NXF\0");
+        fixture.put_u32(HEADER + 0x4, 1);
+        fixture.put_f32(HEADER + 0x8, 1.0);
+        fixture.put_u32(HEADER + 0xc, 0x11);
+        fixture.put_u32(HEADER + 0x10, 2);
+        fixture.put_u32(HEADER + 0x14, 3);
+        fixture.put_u16(HEADER + 0x18, 1);
+        fixture.put_u32(HEADER + 0x1c, STRING_TABLE as u32);
+        fixture.put_u32(HEADER + 0x20, MATERIAL as u32);
+        fixture.put_u32(HEADER_ARRAYS_OFFSET, ARRAYS as u32);
+        fixture.put_u32(HEADER_FIRST_SET, FACELIST_SET as u32);
+
+        // strings
+        fixture.put_u32(STRING_TABLE, STRING_DATA as u32);
+        fixture.put(STRING_DATA, b"foo.tex\0");
+
+        // material
+        fixture.put_u32(MATERIAL, 1);
+        fixture.put_u32(MATERIAL + 0x4, 2);
+        fixture.put_u32(MATERIAL + 0x8, STRING_DATA as u32);
+        fixture.put(MATERIAL + 0x10, &[10, 20, 30, 40]);
+        fixture.put_u32(MATERIAL + 0x14, 5);
+
+        // arrays
+        for (i, &value) in [0.0, 0.0, 0.0].iter().enumerate() {
+            fixture.put_f32(ARRAYS + i * 4, value);
+        }
+        fixture.put_u32(ARRAYS + 0xc, 4);
+        for (i, &value) in [1.0, 1.0, 0.0].iter().enumerate() {
+            fixture.put_f32(ARRAYS + 0x10 + i * 4, value);
+        }
+        for (i, &value) in [0.5, 0.5, 0.0, 1.0].iter().enumerate() {
+            fixture.put_f32(ARRAYS + 0x20 + i * 4, value);
+        }
+        fixture.put_u32(ARRAYS + 0x30, 4);
+        fixture.put_u32(ARRAYS + 0x34, 2);
+        fixture.put_u32(ARRAYS + 0x38, 8);
+        fixture.put_u32(ARRAYS + 0x40, 2);
+        fixture.put_u32(ARRAYS + 0x44, 4);
+        fixture.put_u32(ARRAYS + 0x48, VERTS as u32);
+        fixture.put_u32(ARRAYS + 0x50, COLORS as u32);
+        fixture.put_u32(ARRAYS + 0x54, UVS as u32);
+        fixture.put_u32(ARRAYS + 0x58, 7);
+        for (i, vert) in verts().iter().enumerate() {
+            fixture.put_f32(VERTS + i * 12, vert.x);
+            fixture.put_f32(VERTS + i * 12 + 4, vert.y);
+            fixture.put_f32(VERTS + i * 12 + 8, vert.z);
+        }
+        for (i, color) in colors().iter().enumerate() {
+            fixture.put(COLORS + i * 4, &[color.r, color.g, color.b, color.a]);
+        }
+        for (i, uv) in uvs().iter().enumerate() {
+            fixture.put_f32(UVS + i * 8, uv.u);
+            fixture.put_f32(UVS + i * 8 + 4, uv.v);
+        }
+
+        // facelist set and facelist
+        fixture.put_u32(FACELIST_SET, 1);
+        fixture.put_u32(FACELIST_SET + 0x8, 1);
+        fixture.put_u32(FACELIST_SET + 0xc, FACELIST as u32);
+        fixture.put_u16(FACELIST, 0x100);
+        fixture.put(FACELIST + 0x2, &[10, 3]);
+        fixture.put_u32(FACELIST + 0x8, MATERIAL as u32);
+        fixture.put_u32(FACELIST + 0xc, 2);
+        fixture.put_u32(FACELIST + 0x10, FACES as u32);
+        for (i, &index) in [0, 0, 0, 1, 0, 1, 2, 1, 2, 0, 0, 0, 2, 1, 2, 3, 1, 3].iter().enumerate() {
+            fixture.put_u16(FACES + i * 2, index);
+        }
+
+        fixture
+    }
+
+    /// The geometry `data` parses to, as long as it hasn't been patched.
+    pub fn expected(&self) -> NxfObjGeom {
+        let material = NxfMaterial {
+            tex_pmi: 1,
+            ref_pmi: 2,
+            tex_name: "foo.tex".to_string(),
+            ref_map: 0,
+            ref_r: 10,
+            ref_g: 20,
+            ref_b: 30,
+            ref_a: 40,
+            flags: 5,
+            alpha_mode: 0,
+            env_map_alpha_mode: 0,
+        };
+        // Each corner's uv index matches its vertex index.
+        let face = |v: [u16; 3], c: [u16; 3]| NxfTexUnlitTri {
+            v0: v[0],
+            c0: c[0],
+            uv0: v[0],
+            v1: v[1],
+            c1: c[1],
+            uv1: v[1],
+            v2: v[2],
+            c2: c[2],
+            uv2: v[2],
+        };
+        NxfObjGeom {
+            id: *b"NXF\0",
+            endian: 1,
+            version: 1.0,
+            flags: 0x11,
+            alpha_mode: 2,
+            env_map_alpha_mode: 3,
+            strings: vec!["foo.tex".to_string()],
+            materials: vec![material.clone()],
+            arrays: NxfArray {
+                min_x: 0.0,
+                min_y: 0.0,
+                min_z: 0.0,
+                max_x: 1.0,
+                max_y: 1.0,
+                max_z: 0.0,
+                c_x: 0.5,
+                c_y: 0.5,
+                c_z: 0.0,
+                radius: 1.0,
+                max_verts: 8,
+                max_normals: 0,
+                max_cols: 2,
+                max_uvs: 4,
+                verts: verts(),
+                normals: Vec::new(),
+                colors: colors(),
+                uvs: uvs(),
+                flags: 7,
+            },
+            facelist_sets: vec![NxfFacelistSet {
+                flags: 1,
+                facelists: vec![NxfFacelist {
+                    flags: 0x100,
+                    attribs: 3,
+                    material: Some(material),
+                    faces: NxfFaces::TexUnlitTri(vec![face([0, 1, 2], [0, 0, 1]), face([0, 2, 3], [0, 1, 1])]),
+                    next_facelist: 0,
+                    display_list: 0,
+                    display_list_size: 0,
+                }],
+                mat_palette: None,
+            }],
+            display_list: 0,
+            display_list_size: 0,
+        }
+    }
+
+    pub fn put(&mut self, offset: usize, bytes: &[u8]) {
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    pub fn put_u16(&mut self, offset: usize, value: u16) {
+        self.put(offset, &value.to_be_bytes());
+    }
+
+    pub fn put_u32(&mut self, offset: usize, value: u32) {
+        self.put(offset, &value.to_be_bytes());
+    }
+
+    pub fn put_f32(&mut self, offset: usize, value: f32) {
+        self.put(offset, &value.to_be_bytes());
+    }
+}
+
+fn verts() -> Vec<Vec3> {
+    vec![
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ]
+}
+
+fn colors() -> Vec<Color> {
+    vec![
+        Color { r: 255, g: 0, b: 0, a: 255 },
+        Color { r: 0, g: 255, b: 0, a: 128 },
+    ]
+}
+
+fn uvs() -> Vec<Uv> {
+    vec![
+        Uv { u: 0.0, v: 0.0 },
+        Uv { u: 1.0, v: 0.0 },
+        Uv { u: 1.0, v: 1.0 },
+        Uv { u: 0.0, v: 1.0 },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn fixture_parses_to_expected() {
+        let fixture = NxfFixture::quad();
+        let nxf = NxfObjGeom::from_read(Cursor::new(&fixture.data)).unwrap();
+        assert_eq!(nxf, fixture.expected());
+    }
+}
@@ -1,11 +1,34 @@
-use std::io::{Read, Seek, SeekFrom, Error as IOError};
+#[cfg(any(test, feature = "fixture"))]
+pub mod capture_log;
+mod extent;
+#[cfg(any(test, feature = "fixture"))]
+pub mod fixture;
+mod limits;
+mod offset;
+mod ply;
+mod trace;
+mod triangle;
+mod validate;
+mod writer;
+
+pub use extent::ExtentReader;
+pub use limits::{ReadLimits, MAX_ELEMENTS, MAX_LIST_LEN};
+pub use offset::OffsetReader;
+pub use trace::TracingReader;
+pub use triangle::{Corner, IndexedTriangle};
+pub use validate::{Array, CountError, IndexError, PositionError};
+
+use std::io::{Read, Seek, SeekFrom, Error as IOError, ErrorKind};
 
 use byteorder::{ReadBytesExt, BE};
+use log::{debug, warn};
+
+use limits::Budget;
 
 trait ReadFileExt: Seek {
     type Err;
     fn read_at_offset<T, F>(&mut self, offset: u64, f: F) -> Result<T, Self::Err>
-        where F: Fn(&mut Self) -> Result<T, Self::Err>;
+        where F: FnOnce(&mut Self) -> Result<T, Self::Err>;
     fn read_string(&mut self) -> Result<String, Self::Err>;
 }
 
@@ -14,9 +37,10 @@ impl<R> ReadFileExt for R
 {
     type Err = IOError;
     fn read_at_offset<T, F>(&mut self, offset: u64, f: F) -> Result<T, Self::Err>
-        where F: Fn(&mut Self) -> Result<T, Self::Err>,
+        where F: FnOnce(&mut Self) -> Result<T, Self::Err>,
     {
         let saved_offset = self.seek(SeekFrom::Current(0))?;
+        debug!("resolving pointer {:#x} from {:#x}", offset, saved_offset);
         self.seek(SeekFrom::Start(offset))?;
         let result = f(self)?;
         self.seek(SeekFrom::Start(saved_offset))?;
@@ -42,9 +66,13 @@ impl<R> ReadFileExt for R
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfMaterial {
+    /// Index of the diffuse texture in the game's PMI texture container.
+    /// This, not `tex_name`, is what locates the pixel data.
     pub tex_pmi: u32,
+    /// Index of the reflection (environment map) texture in the PMI
+    /// container.
     pub ref_pmi: u32,
     pub tex_name: String,
     pub ref_map: u32,
@@ -57,7 +85,33 @@ pub struct NxfMaterial {
     pub env_map_alpha_mode: u32,
 }
 
+/// `alpha_mode` used by alpha-tested (hard cutout) textures such as foliage
+/// and fences. 0 appears to be opaque and 1 blended; these values are
+/// inferred from the files rather than documented.
+pub const ALPHA_MODE_CUTOUT: u32 = 2;
+
+/// `alpha_mode` used by alpha-blended textures, inferred like
+/// `ALPHA_MODE_CUTOUT`.
+pub const ALPHA_MODE_BLEND: u32 = 1;
+
 impl NxfMaterial {
+    /// File name of the material's extracted texture, e.g. `"foo.png"` for
+    /// extension `"png"`.
+    pub fn image_filename(&self, ext: &str) -> String {
+        format!("{}.{}", self.tex_name, ext)
+    }
+
+    /// Whether the texture's alpha is a cutout mask rather than a blend.
+    pub fn is_cutout(&self) -> bool {
+        self.alpha_mode == ALPHA_MODE_CUTOUT
+    }
+
+    /// Whether the texture's alpha is blended, so the faces using it need
+    /// to be drawn sorted.
+    pub fn is_blended(&self) -> bool {
+        self.alpha_mode == ALPHA_MODE_BLEND
+    }
+
     pub fn from_read<R>(mut read: R) -> Result<NxfMaterial, IOError>
         where R: Read + Seek
     {
@@ -98,12 +152,28 @@ impl NxfMaterial {
         })
     }
 
-    pub fn list_from_read<R>(mut read: R, mut offset: u64) -> Result<Vec<NxfMaterial>, IOError>
+    pub fn list_from_read<R>(read: R, offset: u64) -> Result<Vec<NxfMaterial>, IOError>
+        where R: Read + Seek
+    {
+        NxfMaterial::list_from_read_limited(read, offset, ReadLimits::default())
+    }
+
+    /// Like `list_from_read`, but with the given limits instead of the
+    /// defaults.
+    pub fn list_from_read_limited<R>(read: R, offset: u64, limits: ReadLimits) -> Result<Vec<NxfMaterial>, IOError>
+        where R: Read + Seek
+    {
+        NxfMaterial::list_read(read, offset, &mut Budget::new(limits))
+    }
+
+    fn list_read<R>(mut read: R, mut offset: u64, budget: &mut Budget) -> Result<Vec<NxfMaterial>, IOError>
         where R: Read + Seek
     {
         let save = read.seek(SeekFrom::Current(0))?;
         let mut materials = Vec::new();
         while offset != 0 {
+            budget.check_depth(materials.len(), "material")?;
+            budget.take(1, "material")?;
             read.seek(SeekFrom::Start(offset))?;
             materials.push(NxfMaterial::from_read(&mut read)?);
             offset = read.read_u32::<BE>()? as u64;
@@ -113,7 +183,7 @@ impl NxfMaterial {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -121,6 +191,53 @@ pub struct Vec3 {
 }
 
 impl Vec3 {
+    pub fn new(x: f32, y: f32, z: f32) -> Vec3 {
+        Vec3 {
+            x: x,
+            y: y,
+            z: z,
+        }
+    }
+
+    pub fn add(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+
+    pub fn sub(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+
+    pub fn mul(&self, factor: f32) -> Vec3 {
+        Vec3::new(self.x * factor, self.y * factor, self.z * factor)
+    }
+
+    pub fn cross(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn dot(&self, other: &Vec3) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    /// Returns the unit vector in the same direction, or the zero vector if
+    /// this one has no length.
+    pub fn normalize(&self) -> Vec3 {
+        let len = self.length();
+        if len > 0.0 {
+            Vec3::new(self.x / len, self.y / len, self.z / len)
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+    }
+
     pub fn from_read<R>(mut read: R) -> Result<Vec3, IOError>
         where R: Read
     {
@@ -136,7 +253,7 @@ impl Vec3 {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -145,6 +262,16 @@ pub struct Color {
 }
 
 impl Color {
+    /// Returns the channels as `[r, g, b, a]`, scaled to 0.0-1.0.
+    pub fn to_f32_array(&self) -> [f32; 4] {
+        [
+            self.r as f32 / 255.0,
+            self.g as f32 / 255.0,
+            self.b as f32 / 255.0,
+            self.a as f32 / 255.0,
+        ]
+    }
+
     pub fn from_read<R>(mut read: R) -> Result<Color, IOError>
         where R: Read
     {
@@ -162,13 +289,20 @@ impl Color {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Uv {
     pub u: f32,
     pub v: f32,
 }
 
 impl Uv {
+    pub fn new(u: f32, v: f32) -> Uv {
+        Uv {
+            u: u,
+            v: v,
+        }
+    }
+
     pub fn from_read<R>(mut read: R) -> Result<Uv, IOError>
         where R: Read
     {
@@ -182,7 +316,7 @@ impl Uv {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct NxfArray {
     pub min_x: f32,
     pub min_y: f32,
@@ -194,10 +328,16 @@ pub struct NxfArray {
     pub c_y: f32,
     pub c_z: f32,
     pub radius: f32,
+    /// Capacity fields kept from the file so it can be written back. They
+    /// can be larger than the arrays actually stored and aren't used to
+    /// read them; index with the lengths of `verts`, `normals`, `colors` and
+    /// `uvs` instead.
     pub max_verts: u32,
     pub max_normals: u32,
     pub max_cols: u32,
     pub max_uvs: u32,
+    /// Each array holds exactly the file's stored count of entries, or none
+    /// when its pointer is null.
     pub verts: Vec<Vec3>,
     pub normals: Vec<Vec3>,
     pub colors: Vec<Color>,
@@ -206,7 +346,13 @@ pub struct NxfArray {
 }
 
 impl NxfArray {
-    pub fn from_read<R>(mut read: R) -> Result<NxfArray, IOError>
+    pub fn from_read<R>(read: R) -> Result<NxfArray, IOError>
+        where R: Read + Seek
+    {
+        NxfArray::read(read, &mut Budget::new(ReadLimits::default()))
+    }
+
+    fn read<R>(mut read: R, budget: &mut Budget) -> Result<NxfArray, IOError>
         where R: Read + Seek
     {
         let min_x = read.read_f32::<BE>()?;
@@ -235,6 +381,7 @@ impl NxfArray {
 
         let verts_offset = read.read_u32::<BE>()?;
         let verts = if verts_offset != 0 {
+            budget.take(num_verts as u64, "vertex")?;
             read.read_at_offset(verts_offset as u64, |mut read| {
                 let mut verts = Vec::new();
                 for _ in 0..num_verts {
@@ -249,6 +396,7 @@ impl NxfArray {
 
         let normals_offset = read.read_u32::<BE>()?;
         let normals = if normals_offset != 0 {
+            budget.take(num_normals as u64, "normal")?;
             read.read_at_offset(normals_offset as u64, |mut read| {
                 let mut normals = Vec::new();
                 for _ in 0..num_normals {
@@ -263,6 +411,7 @@ impl NxfArray {
 
         let colors_offset = read.read_u32::<BE>()?;
         let colors = if colors_offset != 0 {
+            budget.take(num_cols as u64, "color")?;
             read.read_at_offset(colors_offset as u64, |mut read| {
                 let mut colors = Vec::new();
                 for _ in 0..num_cols {
@@ -277,6 +426,7 @@ impl NxfArray {
 
         let uvs_offset = read.read_u32::<BE>()?;
         let uvs = if uvs_offset != 0 {
+            budget.take(num_uvs as u64, "uv")?;
             read.read_at_offset(uvs_offset as u64, |mut read| {
                 let mut uvs = Vec::new();
                 for _ in 0..num_uvs {
@@ -316,9 +466,82 @@ impl NxfArray {
             flags: flags,
         })
     }
+
+    /// Computes smooth per-vertex normals from the triangles in `faces`,
+    /// weighting each face's contribution by its area. Vertices no triangle
+    /// uses get a zero normal.
+    pub fn recompute_normals<'a, I>(&self, faces: I) -> Vec<Vec3>
+        where I: IntoIterator<Item = &'a NxfFaces>
+    {
+        let mut normals = vec![Vec3::new(0.0, 0.0, 0.0); self.verts.len()];
+        for faces in faces {
+            for tri in faces.vertex_indices() {
+                let (p0, p1, p2) = match (
+                    self.verts.get(tri[0] as usize),
+                    self.verts.get(tri[1] as usize),
+                    self.verts.get(tri[2] as usize),
+                ) {
+                    (Some(p0), Some(p1), Some(p2)) => (p0, p1, p2),
+                    _ => continue,
+                };
+
+                // The cross product's length is twice the triangle's area,
+                // so summing it unnormalized area-weights the result.
+                let n = p1.sub(p0).cross(&p2.sub(p0));
+                for &v in tri.iter() {
+                    normals[v as usize] = normals[v as usize].add(&n);
+                }
+            }
+        }
+
+        normals.iter()
+            .map(|n| n.normalize())
+            .collect()
+    }
+
+    /// Whether the stored normals are missing or all zero.
+    pub fn normals_degenerate(&self) -> bool {
+        self.normals.iter().all(|n| n.x == 0.0 && n.y == 0.0 && n.z == 0.0)
+    }
+
+    /// Computes the `(min, max)` corners of the vertices' bounding box, or
+    /// `None` with no vertices. The file's own `min_*`/`max_*` fields aren't
+    /// trusted, since edited files don't always update them.
+    pub fn compute_bounds(&self) -> Option<(Vec3, Vec3)> {
+        let first = *self.verts.first()?;
+        let bounds = self.verts.iter().fold((first, first), |(min, max), v| {
+            (
+                Vec3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z)),
+                Vec3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z)),
+            )
+        });
+        Some(bounds)
+    }
+}
+
+/// Unwraps a section's result. In lenient mode a failed section is replaced
+/// by its empty default, and the first error is kept in `error`.
+fn recover<T>(result: Result<T, IOError>, lenient: bool, error: &mut Option<IOError>) -> Result<T, IOError>
+    where T: Default
+{
+    match result {
+        Ok(value) => Ok(value),
+        Err(err) if lenient => {
+            if error.is_none() {
+                *error = Some(err);
+            }
+            Ok(T::default())
+        }
+        Err(err) => Err(err),
+    }
 }
 
-#[derive(Clone, Debug)]
+/// The NXF version the face layouts are known for. All known files are 1.0,
+/// with 16 bit face indices; any other version (a newer one may widen them)
+/// is refused rather than misread.
+pub const KNOWN_VERSION: f32 = 1.0;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfColLitTri {
     pub v0: u16,
     pub n0: u16,
@@ -349,7 +572,7 @@ impl NxfColLitTri {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfTexLitTri {
     pub v0: u16,
     pub n0: u16,
@@ -386,7 +609,7 @@ impl NxfTexLitTri {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfTexUnlitTri {
     pub v0: u16,
     pub c0: u16,
@@ -417,7 +640,7 @@ impl NxfTexUnlitTri {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfColUnlitTri {
     pub v0: u16,
     pub c0: u16,
@@ -442,7 +665,7 @@ impl NxfColUnlitTri {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfTexLitEnvTri {
     pub v0: u16,
     pub n0: u16,
@@ -485,7 +708,7 @@ impl NxfTexLitEnvTri {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfColLitEnvTri {
     pub v0: u16,
     pub n0: u16,
@@ -522,7 +745,7 @@ impl NxfColLitEnvTri {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum NxfFaces {
     ColLitTri(Vec<NxfColLitTri>),
     TexLitTri(Vec<NxfTexLitTri>),
@@ -530,6 +753,8 @@ pub enum NxfFaces {
     ColUnlitTri(Vec<NxfColUnlitTri>),
     TexLitEnvTri(Vec<NxfTexLitEnvTri>),
     ColLitEnvTri(Vec<NxfColLitEnvTri>),
+    /// A facelist type the parser doesn't understand. No faces are read.
+    Unknown(u8),
 }
 
 impl NxfFaces {
@@ -579,7 +804,10 @@ impl NxfFaces {
                 }
                 Ok(NxfFaces::ColLitEnvTri(faces))
             }
-            _ => panic!("Bad face type"),
+            _ => {
+                debug!("unknown facelist type {} with {} faces", facelist_type, num);
+                Ok(NxfFaces::Unknown(facelist_type))
+            }
         }
     }
 
@@ -591,46 +819,148 @@ impl NxfFaces {
             NxfFaces::ColUnlitTri(faces) => faces.len(),
             NxfFaces::TexLitEnvTri(faces) => faces.len(),
             NxfFaces::ColLitEnvTri(faces) => faces.len(),
+            NxfFaces::Unknown(_) => 0,
+        }
+    }
+
+    pub fn has_normals(&self) -> bool {
+        matches!(self,
+            NxfFaces::ColLitTri(_) |
+            NxfFaces::TexLitTri(_) |
+            NxfFaces::TexLitEnvTri(_) |
+            NxfFaces::ColLitEnvTri(_))
+    }
+
+    pub fn has_uvs(&self) -> bool {
+        matches!(self,
+            NxfFaces::TexLitTri(_) |
+            NxfFaces::TexUnlitTri(_) |
+            NxfFaces::TexLitEnvTri(_))
+    }
+
+    pub fn has_env(&self) -> bool {
+        matches!(self, NxfFaces::TexLitEnvTri(_) | NxfFaces::ColLitEnvTri(_))
+    }
+
+    /// Number of indices stored per triangle corner.
+    pub fn stride(&self) -> usize {
+        match self {
+            NxfFaces::ColLitTri(_) => 3,
+            NxfFaces::TexLitTri(_) => 4,
+            NxfFaces::TexUnlitTri(_) => 3,
+            NxfFaces::ColUnlitTri(_) => 2,
+            NxfFaces::TexLitEnvTri(_) => 5,
+            NxfFaces::ColLitEnvTri(_) => 4,
+            NxfFaces::Unknown(_) => 0,
+        }
+    }
+
+    /// The position indices of each triangle.
+    pub fn vertex_indices(&self) -> Vec<[u16; 3]> {
+        match self {
+            NxfFaces::ColLitTri(faces) => faces.iter().map(|f| [f.v0, f.v1, f.v2]).collect(),
+            NxfFaces::TexLitTri(faces) => faces.iter().map(|f| [f.v0, f.v1, f.v2]).collect(),
+            NxfFaces::TexUnlitTri(faces) => faces.iter().map(|f| [f.v0, f.v1, f.v2]).collect(),
+            NxfFaces::ColUnlitTri(faces) => faces.iter().map(|f| [f.v0, f.v1, f.v2]).collect(),
+            NxfFaces::TexLitEnvTri(faces) => faces.iter().map(|f| [f.v0, f.v1, f.v2]).collect(),
+            NxfFaces::ColLitEnvTri(faces) => faces.iter().map(|f| [f.v0, f.v1, f.v2]).collect(),
+            NxfFaces::Unknown(_) => Vec::new(),
+        }
+    }
+
+    /// The position indices of the `face`th triangle, or `None` past the
+    /// last one.
+    pub fn face_vertex_indices(&self, face: usize) -> Option<[u16; 3]> {
+        match self {
+            NxfFaces::ColLitTri(faces) => faces.get(face).map(|f| [f.v0, f.v1, f.v2]),
+            NxfFaces::TexLitTri(faces) => faces.get(face).map(|f| [f.v0, f.v1, f.v2]),
+            NxfFaces::TexUnlitTri(faces) => faces.get(face).map(|f| [f.v0, f.v1, f.v2]),
+            NxfFaces::ColUnlitTri(faces) => faces.get(face).map(|f| [f.v0, f.v1, f.v2]),
+            NxfFaces::TexLitEnvTri(faces) => faces.get(face).map(|f| [f.v0, f.v1, f.v2]),
+            NxfFaces::ColLitEnvTri(faces) => faces.get(face).map(|f| [f.v0, f.v1, f.v2]),
+            NxfFaces::Unknown(_) => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            NxfFaces::ColLitTri(_) => "ColLitTri",
+            NxfFaces::TexLitTri(_) => "TexLitTri",
+            NxfFaces::TexUnlitTri(_) => "TexUnlitTri",
+            NxfFaces::ColUnlitTri(_) => "ColUnlitTri",
+            NxfFaces::TexLitEnvTri(_) => "TexLitEnvTri",
+            NxfFaces::ColLitEnvTri(_) => "ColLitEnvTri",
+            NxfFaces::Unknown(_) => "Unknown",
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfFacelist {
     pub flags: u16,
     pub attribs: u8,
-    pub material: NxfMaterial,
+    /// `None` when the facelist's material pointer is null.
+    pub material: Option<NxfMaterial>,
     pub faces: NxfFaces,
     next_facelist: u64, // XXX: needed (for now) so I can read a list of these
     pub display_list: u32,
     pub display_list_size: u32,
 }
 
+/// Size of a facelist record, including its display list fields.
+const FACELIST_SIZE: u64 = 0x20;
+
 impl NxfFacelist {
-    pub fn from_read<R>(mut read: R) -> Result<NxfFacelist, IOError>
+    pub fn from_read<R>(read: R) -> Result<NxfFacelist, IOError>
+        where R: Read + Seek
+    {
+        NxfFacelist::read(read, &mut Budget::new(ReadLimits::default()))
+    }
+
+    fn read<R>(mut read: R, budget: &mut Budget) -> Result<NxfFacelist, IOError>
         where R: Read + Seek
     {
+        let start = read.stream_position()?;
         let flags = read.read_u16::<BE>()?;
         let facelist_type = read.read_u8()?;
         let attribs = read.read_u8()?;
         let _pad = read.read_u32::<BE>()?;
 
         let material_offset = read.read_u32::<BE>()? as u64;
-        let material = read.read_at_offset(material_offset, |mut read| {
-            NxfMaterial::from_read(&mut read)
-        })?;
+        let material = if material_offset != 0 {
+            Some(read.read_at_offset(material_offset, |read| {
+                NxfMaterial::from_read(read)
+            })?)
+        } else {
+            None
+        };
 
         let num_faces = read.read_u32::<BE>()?;
         let faces_offset = read.read_u32::<BE>()? as u64;
-        let faces = read.read_at_offset(faces_offset, |mut read| {
-            NxfFaces::from_read(&mut read, facelist_type, num_faces)
+        budget.take(num_faces as u64, "face")?;
+        let (faces, faces_end) = read.read_at_offset(faces_offset, |mut read| {
+            let faces = NxfFaces::from_read(&mut read, facelist_type, num_faces)?;
+            Ok((faces, read.stream_position()?))
         })?;
 
         let next_facelist = read.read_u32::<BE>()? as u64;
-
         let display_list = read.read_u32::<BE>()?;
         let display_list_size = read.read_u32::<BE>()?;
 
+        // Faces are read from their own pointer, so a wrong `num_faces`
+        // doesn't throw off the rest of the parse. It does make the faces
+        // run into the data around them, which is what gets checked: the
+        // facelist records and the display list.
+        let overlaps = |offset: u64, size: u64| faces_offset < offset + size && offset < faces_end;
+        if overlaps(start, FACELIST_SIZE) || (next_facelist != 0 && overlaps(next_facelist, FACELIST_SIZE)) {
+            warn!("facelist at {:#x}: {} faces at {:#x}..{:#x} overlap a facelist record; num_faces may be wrong",
+                  start, num_faces, faces_offset, faces_end);
+        }
+        if display_list != 0 && overlaps(display_list as u64, display_list_size as u64) {
+            warn!("facelist at {:#x}: {} faces at {:#x}..{:#x} overlap its display list at {:#x}; num_faces may be wrong",
+                  start, num_faces, faces_offset, faces_end, display_list);
+        }
+
         Ok(NxfFacelist {
             flags: flags,
             attribs: attribs,
@@ -642,14 +972,30 @@ impl NxfFacelist {
         })
     }
 
-    pub fn list_from_read<R>(mut read: R, mut offset: u64) -> Result<Vec<NxfFacelist>, IOError>
+    pub fn list_from_read<R>(read: R, offset: u64) -> Result<Vec<NxfFacelist>, IOError>
+        where R: Read + Seek
+    {
+        NxfFacelist::list_from_read_limited(read, offset, ReadLimits::default())
+    }
+
+    /// Like `list_from_read`, but with the given limits instead of the
+    /// defaults.
+    pub fn list_from_read_limited<R>(read: R, offset: u64, limits: ReadLimits) -> Result<Vec<NxfFacelist>, IOError>
+        where R: Read + Seek
+    {
+        NxfFacelist::list_read(read, offset, &mut Budget::new(limits))
+    }
+
+    fn list_read<R>(mut read: R, mut offset: u64, budget: &mut Budget) -> Result<Vec<NxfFacelist>, IOError>
         where R: Read + Seek
     {
         let save = read.seek(SeekFrom::Current(0))?;
         let mut facelists = Vec::new();
         while offset != 0 {
+            budget.check_depth(facelists.len(), "facelist")?;
+            budget.take(1, "facelist")?;
             read.seek(SeekFrom::Start(offset))?;
-            let facelist = NxfFacelist::from_read(&mut read)?;
+            let facelist = NxfFacelist::read(&mut read, budget)?;
             offset = facelist.next_facelist;
             facelists.push(facelist);
         }
@@ -658,10 +1004,15 @@ impl NxfFacelist {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct NxfMatrixPalette;
+/// A facelist set's matrix palette, placing skinned parts. Its layout
+/// hasn't been worked out, so only where it is gets recorded; until it's
+/// read, parts using one can't be moved into place.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NxfMatrixPalette {
+    pub offset: u64,
+}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfFacelistSet {
     pub flags: u32,
     pub facelists: Vec<NxfFacelist>,
@@ -669,7 +1020,13 @@ pub struct NxfFacelistSet {
 }
 
 impl NxfFacelistSet {
-    pub fn from_read<R>(mut read: R) -> Result<NxfFacelistSet, IOError>
+    pub fn from_read<R>(read: R) -> Result<NxfFacelistSet, IOError>
+        where R: Read + Seek
+    {
+        NxfFacelistSet::read(read, &mut Budget::new(ReadLimits::default()))
+    }
+
+    fn read<R>(mut read: R, budget: &mut Budget) -> Result<NxfFacelistSet, IOError>
         where R: Read + Seek
     {
         let flags = read.read_u32::<BE>()?;
@@ -677,26 +1034,49 @@ impl NxfFacelistSet {
 
         let _num_lists = read.read_u32::<BE>()?;
         let first_facelist = read.read_u32::<BE>()? as u64;
-        let facelists = NxfFacelist::list_from_read(&mut read, first_facelist)?;
-
-        // TODO: read mat palettes
-        let _mat_palette_offset = read.read_u32::<BE>()?;
+        let facelists = NxfFacelist::list_read(&mut read, first_facelist, budget)?;
+
+        // TODO: read mat palettes. For now only record where one is.
+        let mat_palette_offset = read.read_u32::<BE>()?;
+        let mat_palette = if mat_palette_offset != 0 {
+            Some(NxfMatrixPalette {
+                offset: mat_palette_offset as u64,
+            })
+        } else {
+            None
+        };
 
         Ok(NxfFacelistSet {
             flags: flags,
             facelists: facelists,
-            mat_palette: None,
+            mat_palette: mat_palette,
         })
     }
 
-    pub fn list_from_read<R>(mut read: R, mut offset: u64) -> Result<Vec<NxfFacelistSet>, IOError>
+    pub fn list_from_read<R>(read: R, offset: u64) -> Result<Vec<NxfFacelistSet>, IOError>
+        where R: Read + Seek
+    {
+        NxfFacelistSet::list_from_read_limited(read, offset, ReadLimits::default())
+    }
+
+    /// Like `list_from_read`, but with the given limits instead of the
+    /// defaults. They apply to the facelist lists inside each set too.
+    pub fn list_from_read_limited<R>(read: R, offset: u64, limits: ReadLimits) -> Result<Vec<NxfFacelistSet>, IOError>
+        where R: Read + Seek
+    {
+        NxfFacelistSet::list_read(read, offset, &mut Budget::new(limits))
+    }
+
+    fn list_read<R>(mut read: R, mut offset: u64, budget: &mut Budget) -> Result<Vec<NxfFacelistSet>, IOError>
         where R: Read + Seek
     {
         let save = read.seek(SeekFrom::Current(0))?;
         let mut facelist_sets = Vec::new();
         while offset != 0 {
+            budget.check_depth(facelist_sets.len(), "facelist set")?;
+            budget.take(1, "facelist set")?;
             read.seek(SeekFrom::Start(offset))?;
-            facelist_sets.push(NxfFacelistSet::from_read(&mut read)?);
+            facelist_sets.push(NxfFacelistSet::read(&mut read, budget)?);
             offset = read.read_u32::<BE>()? as u64;
         }
         read.seek(SeekFrom::Start(save))?;
@@ -704,7 +1084,7 @@ impl NxfFacelistSet {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NxfObjGeom {
     pub id: [u8; 4],
     pub endian: u32,
@@ -721,13 +1101,60 @@ pub struct NxfObjGeom {
 }
 
 impl NxfObjGeom {
-    pub fn from_read<R>(mut read: R) -> Result<NxfObjGeom, IOError>
+    /// Parses an NXF whose header is at the reader's current position.
+    ///
+    /// This is the entry point library users should call. Pointers in the
+    /// file are absolute offsets from the start of the reader, so the reader
+    /// is seeked around while resolving them. Materials referenced by
+    /// facelists are read (and copied) at each use, so no shared state has
+    /// to be set up first.
+    ///
+    /// On success the reader is left just past the furthest byte read, the
+    /// end of the structure, whatever order its parts were read in.
+    pub fn from_read<R>(read: R) -> Result<NxfObjGeom, IOError>
+        where R: Read + Seek
+    {
+        NxfObjGeom::from_read_limited(read, ReadLimits::default())
+    }
+
+    /// Like `from_read`, but with the given limits on how much the file can
+    /// make the parser read, instead of the defaults.
+    pub fn from_read_limited<R>(read: R, limits: ReadLimits) -> Result<NxfObjGeom, IOError>
+        where R: Read + Seek
+    {
+        NxfObjGeom::read(read, false, limits).map(|(nxf, _)| nxf)
+    }
+
+    /// Like `from_read`, but recovers what it can from a damaged file.
+    ///
+    /// Only the header has to be readable. The strings, materials, arrays
+    /// and facelist sets are each read independently, and any that fail are
+    /// left empty. The first such error is returned alongside the partial
+    /// geometry.
+    pub fn from_read_lenient<R>(read: R) -> Result<(NxfObjGeom, Option<IOError>), IOError>
+        where R: Read + Seek
+    {
+        NxfObjGeom::read(read, true, ReadLimits::default())
+    }
+
+    fn read<R>(read: R, lenient: bool, limits: ReadLimits) -> Result<(NxfObjGeom, Option<IOError>), IOError>
         where R: Read + Seek
     {
+        let mut budget = Budget::new(limits);
+        let mut read = ExtentReader::new(read);
         let mut id = [0; 4];
         read.read_exact(&mut id)?;
         let endian = read.read_u32::<BE>()?;
         let version = read.read_f32::<BE>()?;
+        // Compared for equality so a NaN version is refused too.
+        if version != KNOWN_VERSION {
+            if lenient {
+                warn!("NXF version {:?} isn't {:?}; faces, read with 16 bit indices, may misparse", version, KNOWN_VERSION);
+            } else {
+                return Err(IOError::new(ErrorKind::InvalidData,
+                                        format!("unsupported NXF version {:?} (only {:?}, with 16 bit face indices, is known)", version, KNOWN_VERSION)));
+            }
+        }
         let flags = read.read_u32::<BE>()?;
         let alpha_mode = read.read_u32::<BE>()?;
         let env_map_alpha_mode = read.read_u32::<BE>()?;
@@ -735,7 +1162,29 @@ impl NxfObjGeom {
         let num_strings = read.read_u16::<BE>()?;
         let _pad = read.read_u16::<BE>()?;
         let strings_offset = read.read_u32::<BE>()?;
-        let strings = read.read_at_offset(strings_offset as u64, |read| {
+        let material_offset = read.read_u32::<BE>()?;
+        let arrays_offset = read.read_u32::<BE>()?;
+        let first_facelist_set = read.read_u32::<BE>()?;
+
+        let display_list = read.read_u32::<BE>()?;
+        let display_list_size = read.read_u32::<BE>()?;
+        // TODO: read more geoms. `_expanded` points at a chained geom, whose
+        // layout hasn't been worked out, so whether it has its own arrays or
+        // shares these (only the facelists differing) isn't known yet. Each
+        // chained geom should be read with its own `arrays_offset` and shared
+        // only when the offsets match. Like everything else here it should
+        // be read fresh rather than looked up by offset, so nothing from this
+        // geom can be handed to it by mistake.
+        let _expanded = read.read_u32::<BE>()?;
+        let _pad1 = read.read_u32::<BE>()?;
+        let _pad2 = read.read_u32::<BE>()?;
+        let _pad3 = read.read_u32::<BE>()?;
+
+        let mut error = None;
+
+        // Budget errors go through `recover` like any other, so a lenient
+        // parse keeps what it read before the limit was hit.
+        let strings = budget.take(num_strings as u64, "string").and_then(|_| read.read_at_offset(strings_offset as u64, |read| {
             let mut strings = Vec::new();
             for _ in 0..num_strings {
                 let string_offset = read.read_u32::<BE>()?;
@@ -745,27 +1194,21 @@ impl NxfObjGeom {
                 strings.push(s);
             }
             Ok(strings)
-        })?;
+        }));
+        let strings = recover(strings, lenient, &mut error)?;
 
-        let material_offset = read.read_u32::<BE>()?;
-        let materials = NxfMaterial::list_from_read(&mut read, material_offset as u64)?;
+        let materials = NxfMaterial::list_read(&mut read, material_offset as u64, &mut budget);
+        let materials = recover(materials, lenient, &mut error)?;
 
-        let arrays_offset = read.read_u32::<BE>()?;
         let arrays = read.read_at_offset(arrays_offset as u64, |read| {
-            NxfArray::from_read(read)
-        })?;
-
-        let first_facelist_set = read.read_u32::<BE>()?;
-        let facelist_sets = NxfFacelistSet::list_from_read(&mut read, first_facelist_set as u64)?;
+            NxfArray::read(read, &mut budget)
+        });
+        let arrays = recover(arrays, lenient, &mut error)?;
 
-        let display_list = read.read_u32::<BE>()?;
-        let display_list_size = read.read_u32::<BE>()?;
-        let _expanded = read.read_u32::<BE>()?; // TODO: read more geoms
-        let _pad1 = read.read_u32::<BE>()?;
-        let _pad2 = read.read_u32::<BE>()?;
-        let _pad3 = read.read_u32::<BE>()?;
+        let facelist_sets = NxfFacelistSet::list_read(&mut read, first_facelist_set as u64, &mut budget);
+        let facelist_sets = recover(facelist_sets, lenient, &mut error)?;
 
-        Ok(NxfObjGeom {
+        let nxf = NxfObjGeom {
             id: id,
             endian: endian,
             version: version,
@@ -778,6 +1221,450 @@ impl NxfObjGeom {
             facelist_sets: facelist_sets,
             display_list: display_list,
             display_list_size: display_list_size,
-        })
+        };
+        read.seek(SeekFrom::Start(read.end()))?;
+        Ok((nxf, error))
+    }
+
+    /// Like `from_read`, but also returns how many bytes the NXF occupies
+    /// from the reader's starting position, up to the furthest byte read.
+    /// For an NXF embedded in a container, pass an `OffsetReader` positioned
+    /// at its start.
+    pub fn from_read_with_len<R>(mut read: R) -> Result<(NxfObjGeom, u64), IOError>
+        where R: Read + Seek
+    {
+        let start = read.stream_position()?;
+        let mut read = ExtentReader::new(read);
+        let nxf = NxfObjGeom::from_read(&mut read)?;
+        Ok((nxf, read.end().saturating_sub(start)))
+    }
+
+    /// Parses an NXF embedded in a larger container, starting at
+    /// `base_offset`. Pointers in the file are resolved relative to
+    /// `base_offset` instead of the start of the reader.
+    ///
+    /// Nothing is cached between calls (or within one, by offset), so
+    /// several NXFs can be read from the same container one after another
+    /// without a pointer in one resolving to something read for another at
+    /// the same relative offset.
+    pub fn from_read_at<R>(mut read: R, base_offset: u64) -> Result<NxfObjGeom, IOError>
+        where R: Read + Seek
+    {
+        read.seek(SeekFrom::Start(base_offset))?;
+        NxfObjGeom::from_read(OffsetReader::new(read, base_offset))
+    }
+
+    /// Iterates over every facelist, across all facelist sets, in file order.
+    pub fn facelists(&self) -> impl Iterator<Item = &NxfFacelist> {
+        self.facelist_sets
+            .iter()
+            .flat_map(|facelist_set| facelist_set.facelists.iter())
+    }
+
+    /// Like `facelists`, but also yields the index of each facelist's set.
+    pub fn facelists_with_set(&self) -> impl Iterator<Item = (usize, &NxfFacelist)> {
+        self.facelist_sets
+            .iter()
+            .enumerate()
+            .flat_map(|(set_idx, facelist_set)| {
+                facelist_set.facelists.iter().map(move |facelist| (set_idx, facelist))
+            })
+    }
+
+    /// Lists the texture names used by the geometry's materials, without
+    /// duplicates and in the order they first appear.
+    pub fn texture_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for material in self.materials.iter() {
+            if !names.contains(&material.tex_name.as_str()) {
+                names.push(&material.tex_name);
+            }
+        }
+        names
+    }
+
+    /// Lists the materials some facelist actually uses, in material list
+    /// order. Materials left over in the list from editing are left out.
+    pub fn used_materials(&self) -> Vec<&NxfMaterial> {
+        self.materials
+            .iter()
+            .filter(|material| {
+                self.facelists().any(|facelist| facelist.material.as_ref() == Some(*material))
+            })
+            .collect()
+    }
+
+    /// Whether any facelist uses an environment mapped face type or any
+    /// material sets an env map alpha mode, meaning the model needs a
+    /// reflection texture set up.
+    pub fn uses_env_mapping(&self) -> bool {
+        self.facelists().any(|facelist| facelist.faces.has_env())
+            || self.materials.iter().any(|material| material.env_map_alpha_mode != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::capture_log::capture_warnings;
+    use crate::fixture::{self, NxfFixture};
+
+    /// The quad fixture counts 16 elements: one string, one material, ten
+    /// array entries, one facelist set, one facelist and two faces.
+    fn limits(max_elements: u64) -> ReadLimits {
+        ReadLimits {
+            max_depth: MAX_LIST_LEN,
+            max_elements: max_elements,
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn fixture_fits_exact_element_limit() {
+        let fixture = NxfFixture::quad();
+        let nxf = NxfObjGeom::from_read_limited(Cursor::new(&fixture.data), limits(16)).unwrap();
+        assert_eq!(nxf, fixture.expected());
+    }
+
+    #[test]
+    fn element_limit_fails_strict_parse() {
+        let fixture = NxfFixture::quad();
+        for &max_elements in [0, 13, 15].iter() {
+            let err = NxfObjGeom::from_read_limited(Cursor::new(&fixture.data), limits(max_elements)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn element_limit_keeps_earlier_data_when_lenient() {
+        let fixture = NxfFixture::quad();
+        let (nxf, error) = NxfObjGeom::read(Cursor::new(&fixture.data), true, limits(13)).unwrap();
+        assert!(error.is_some());
+        assert_eq!(nxf.strings, fixture.expected().strings);
+        assert_eq!(nxf.arrays, fixture.expected().arrays);
+        assert!(nxf.facelist_sets.is_empty());
+    }
+
+    #[test]
+    fn string_limit_is_recovered_when_lenient() {
+        let fixture = NxfFixture::quad();
+        let (nxf, error) = NxfObjGeom::read(Cursor::new(&fixture.data), true, limits(0)).unwrap();
+        assert!(error.is_some());
+        assert!(nxf.strings.is_empty());
+    }
+
+    #[test]
+    fn lenient_parse_keeps_materials_when_truncated_mid_facelist() {
+        let mut fixture = NxfFixture::quad();
+        // Move the string out of the way of the truncation, into the gap
+        // between the uvs and the facelist set.
+        let string = fixture::UVS + 0x40;
+        fixture.put(string, b"foo.tex\0");
+        fixture.put_u32(fixture::STRING_TABLE, string as u32);
+        fixture.put_u32(fixture::MATERIAL + 0x8, string as u32);
+        fixture.data.truncate(fixture::FACELIST + 0x8);
+
+        let err = NxfObjGeom::from_read(Cursor::new(&fixture.data)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let (nxf, error) = NxfObjGeom::from_read_lenient(Cursor::new(&fixture.data)).unwrap();
+        assert_eq!(error.map(|err| err.kind()), Some(ErrorKind::UnexpectedEof));
+        let expected = fixture.expected();
+        assert_eq!(nxf.strings, expected.strings);
+        assert_eq!(nxf.materials, expected.materials);
+        assert_eq!(nxf.arrays, expected.arrays);
+        assert!(nxf.facelist_sets.is_empty());
+    }
+
+    #[test]
+    fn len_stops_at_furthest_byte_read() {
+        let mut fixture = NxfFixture::quad();
+        fixture.data.extend_from_slice(&[0xff; 0x20]);
+        let (nxf, len) = NxfObjGeom::from_read_with_len(Cursor::new(&fixture.data)).unwrap();
+        assert_eq!(nxf, fixture.expected());
+        // The furthest thing read is the string, "foo.tex" and its NUL.
+        assert_eq!(len, fixture::STRING_DATA as u64 + 8);
+    }
+
+    #[test]
+    fn cyclic_facelist_hits_depth_limit() {
+        let mut fixture = NxfFixture::quad();
+        fixture.put_u32(fixture::FACELIST + 0x14, fixture::FACELIST as u32);
+        let limits = ReadLimits {
+            max_depth: 8,
+            max_elements: MAX_ELEMENTS,
+        };
+        let err = NxfObjGeom::from_read_limited(Cursor::new(&fixture.data), limits).unwrap_err();
+        assert!(err.to_string().contains("facelist list longer than 8"), "{}", err);
+
+        let (nxf, error) = NxfObjGeom::read(Cursor::new(&fixture.data), true, limits).unwrap();
+        assert!(error.is_some());
+        assert_eq!(nxf.arrays, fixture.expected().arrays);
+    }
+
+    /// Parses a checked in file and compares its `{:#?}` dump to the one
+    /// checked in beside it, to catch fields read in the wrong order or at
+    /// the wrong offset. Nothing in the dump varies between runs, so it's
+    /// compared as is; line endings are normalized in case of a CRLF
+    /// checkout.
+    #[test]
+    fn debug_dump_matches_golden() {
+        let nxf = NxfObjGeom::from_read(Cursor::new(&include_bytes!("../testdata/quad.nxf")[..])).unwrap();
+        let golden = include_str!("../testdata/quad.nxf.txt").replace("\r\n", "\n");
+        assert_eq!(format!("{:#?}\n", nxf), golden);
+    }
+
+    #[test]
+    fn from_read_copies_materials_and_ends_past_furthest_read() {
+        let fixture = NxfFixture::quad();
+        let mut cursor = Cursor::new(&fixture.data);
+        let nxf = NxfObjGeom::from_read(&mut cursor).unwrap();
+        assert_eq!(nxf.facelist_sets[0].facelists[0].material.as_ref(), Some(&nxf.materials[0]));
+        assert_eq!(nxf.materials[0].tex_name, "foo.tex");
+        assert_eq!(cursor.position(), fixture::STRING_DATA as u64 + 8);
+    }
+
+    #[test]
+    fn recomputed_quad_normals_face_positive_z() {
+        let mut nxf = NxfFixture::quad().expected();
+        // An extra vertex no face uses.
+        nxf.arrays.verts.push(Vec3::new(5.0, 5.0, 5.0));
+        let normals = nxf.arrays.recompute_normals(nxf.facelists().map(|facelist| &facelist.faces));
+        assert_eq!(normals.len(), 5);
+        // Both triangles wind counterclockwise seen from +z.
+        for normal in normals[..4].iter() {
+            assert_eq!(*normal, Vec3::new(0.0, 0.0, 1.0));
+        }
+        assert_eq!(normals[4], Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn capabilities_per_face_type() {
+        // (faces, has_normals, has_uvs, has_env, stride)
+        let cases = [
+            (NxfFaces::ColLitTri(Vec::new()), true, false, false, 3),
+            (NxfFaces::TexLitTri(Vec::new()), true, true, false, 4),
+            (NxfFaces::TexUnlitTri(Vec::new()), false, true, false, 3),
+            (NxfFaces::ColUnlitTri(Vec::new()), false, false, false, 2),
+            (NxfFaces::TexLitEnvTri(Vec::new()), true, true, true, 5),
+            (NxfFaces::ColLitEnvTri(Vec::new()), true, false, true, 4),
+            (NxfFaces::Unknown(0x7f), false, false, false, 0),
+        ];
+        for &(ref faces, normals, uvs, env, stride) in cases.iter() {
+            assert_eq!((faces.has_normals(), faces.has_uvs(), faces.has_env(), faces.stride()),
+                (normals, uvs, env, stride), "{:?}", faces);
+        }
+    }
+
+    #[test]
+    fn texture_names_are_deduplicated_in_order() {
+        let mut nxf = NxfFixture::quad().expected();
+        let mut bar = nxf.materials[0].clone();
+        bar.tex_name = "bar.tex".to_string();
+        let mut shared = nxf.materials[0].clone();
+        shared.ref_r = 99;
+        nxf.materials.push(bar);
+        nxf.materials.push(shared);
+        assert_eq!(nxf.texture_names(), ["foo.tex", "bar.tex"]);
+    }
+
+    #[test]
+    fn cross_follows_right_hand_rule() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        assert_eq!(x.cross(&y), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(y.cross(&x), Vec3::new(0.0, 0.0, -1.0));
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        let b = Vec3::new(4.0, 5.0, 6.0);
+        assert_eq!(a.cross(&b), Vec3::new(-3.0, 6.0, -3.0));
+        assert_eq!(a.cross(&b).dot(&a), 0.0);
+        assert_eq!(a.cross(&a), Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn normalize_gives_unit_length() {
+        assert_eq!(Vec3::new(3.0, 0.0, 4.0).normalize(), Vec3::new(0.6, 0.0, 0.8));
+        assert!((Vec3::new(1.0, -2.0, 3.0).normalize().length() - 1.0).abs() < 1e-6);
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0).normalize(), Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn from_read_at_resolves_pointers_from_base() {
+        let fixture = NxfFixture::quad();
+        let base = 0x123;
+        let mut container = vec![0xff; base];
+        container.extend_from_slice(&fixture.data);
+        container.extend_from_slice(&[0xff; 0x40]);
+        let nxf = NxfObjGeom::from_read_at(Cursor::new(&container), base as u64).unwrap();
+        assert_eq!(nxf, fixture.expected());
+    }
+
+    #[test]
+    fn facelists_visit_every_set_in_order() {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.facelist_sets[0].facelists[0].flags = 0;
+        let mut facelist = nxf.facelist_sets[0].facelists[0].clone();
+        facelist.flags = 1;
+        nxf.facelist_sets[0].facelists.push(facelist.clone());
+        let mut set = nxf.facelist_sets[0].clone();
+        facelist.flags = 2;
+        set.facelists = vec![facelist];
+        nxf.facelist_sets.push(set);
+        nxf.facelist_sets.push(NxfFacelistSet {
+            facelists: Vec::new(),
+            ..nxf.facelist_sets[0].clone()
+        });
+
+        let flags: Vec<u16> = nxf.facelists().map(|facelist| facelist.flags).collect();
+        assert_eq!(flags, [0, 1, 2]);
+        let with_set: Vec<(usize, u16)> = nxf.facelists_with_set().map(|(set_idx, facelist)| (set_idx, facelist.flags)).collect();
+        assert_eq!(with_set, [(0, 0), (0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn material_pmi_indices_are_parsed() {
+        let mut fixture = NxfFixture::quad();
+        fixture.put_u32(fixture::MATERIAL, 0x1234);
+        fixture.put_u32(fixture::MATERIAL + 0x4, 0x5678);
+        let nxf = NxfObjGeom::from_read(Cursor::new(&fixture.data)).unwrap();
+        let material = &nxf.materials[0];
+        assert_eq!((material.tex_pmi, material.ref_pmi), (0x1234, 0x5678));
+        let facelist_material = nxf.facelist_sets[0].facelists[0].material.as_ref().unwrap();
+        assert_eq!((facelist_material.tex_pmi, facelist_material.ref_pmi), (0x1234, 0x5678));
+    }
+
+    #[test]
+    fn uses_env_mapping_checks_faces_and_materials() {
+        let plain = NxfFixture::quad().expected();
+        assert!(!plain.uses_env_mapping());
+
+        let mut env_faces = plain.clone();
+        env_faces.facelist_sets[0].facelists[0].faces = NxfFaces::TexLitEnvTri(Vec::new());
+        assert!(env_faces.uses_env_mapping());
+
+        let mut env_material = plain.clone();
+        env_material.materials[0].env_map_alpha_mode = 1;
+        assert!(env_material.uses_env_mapping());
+    }
+
+    #[test]
+    fn max_verts_beyond_stored_count_is_only_kept() {
+        let mut fixture = NxfFixture::quad();
+        fixture.put_u32(fixture::ARRAYS + 0x38, 0x100);
+        let nxf = NxfObjGeom::from_read(Cursor::new(&fixture.data)).unwrap();
+        assert_eq!(nxf.arrays.max_verts, 0x100);
+        assert_eq!(nxf.arrays.verts.len(), 4);
+        assert_eq!(nxf.arrays.verts, fixture.expected().arrays.verts);
+    }
+
+    #[test]
+    fn unknown_versions_are_refused_unless_lenient() {
+        for &version in [2.0, f32::NAN].iter() {
+            let mut fixture = NxfFixture::quad();
+            fixture.put_f32(0x8, version);
+
+            let err = NxfObjGeom::from_read(Cursor::new(&fixture.data)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(err.to_string().contains("unsupported NXF version"), "{}", err);
+
+            let (nxf, error) = NxfObjGeom::from_read_lenient(Cursor::new(&fixture.data)).unwrap();
+            assert!(error.is_none(), "{:?}", error);
+            assert_eq!(nxf.version.to_bits(), version.to_bits());
+            assert_eq!(nxf.facelist_sets, fixture.expected().facelist_sets);
+        }
+    }
+
+    #[test]
+    fn used_materials_leave_out_orphans() {
+        let mut nxf = NxfFixture::quad().expected();
+        let mut orphan = nxf.materials[0].clone();
+        orphan.tex_name = "orphan.tex".to_string();
+        nxf.materials.insert(0, orphan);
+        let used = nxf.used_materials();
+        assert_eq!(used.len(), 1);
+        assert_eq!(used[0].tex_name, "foo.tex");
+    }
+
+    #[test]
+    fn reparsing_from_the_start_is_identical() {
+        let mut fixture = NxfFixture::quad();
+        fixture.data.extend_from_slice(&[0xff; 0x20]);
+        let mut cursor = Cursor::new(&fixture.data);
+        let first = NxfObjGeom::from_read(&mut cursor).unwrap();
+        // Left past the string, the furthest thing read, whatever came last.
+        assert_eq!(cursor.position(), fixture::STRING_DATA as u64 + 8);
+
+        cursor.seek(SeekFrom::Start(0)).unwrap();
+        let second = NxfObjGeom::from_read(&mut cursor).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(cursor.position(), fixture::STRING_DATA as u64 + 8);
+    }
+
+    #[test]
+    fn expanded_pointer_is_not_followed() {
+        // How chained geoms lay out their arrays isn't known, so `_expanded`
+        // is left unread; a pointer off the end of the file mustn't matter.
+        let mut fixture = NxfFixture::quad();
+        fixture.put_u32(fixture::HEADER + 0x34, 0xdead_0000);
+        let (nxf, len) = NxfObjGeom::from_read_with_len(Cursor::new(&fixture.data)).unwrap();
+        assert_eq!(nxf, fixture.expected());
+        assert_eq!(len, fixture::STRING_DATA as u64 + 8);
+    }
+
+    #[test]
+    fn compute_bounds_ignores_stored_bounds() {
+        let mut arrays = NxfFixture::quad().expected().arrays;
+        arrays.verts.push(Vec3::new(-2.0, 0.5, 3.0));
+        assert_eq!(arrays.compute_bounds(), Some((Vec3::new(-2.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 3.0))));
+
+        arrays.verts.clear();
+        assert_eq!(arrays.compute_bounds(), None);
+    }
+
+    #[test]
+    fn wrong_num_faces_is_warned_about() {
+        // The two faces take 0x24 bytes at FACES; the display list follows.
+        let mut fixture = NxfFixture::quad();
+        fixture.put_u32(fixture::FACELIST + 0x18, fixture::FACES as u32 + 0x30);
+        fixture.put_u32(fixture::FACELIST + 0x1c, 0x20);
+        let (nxf, warnings) = capture_warnings(|| NxfObjGeom::from_read(Cursor::new(&fixture.data)));
+        nxf.unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        fixture.put_u32(fixture::FACELIST + 0xc, 5);
+        let (nxf, warnings) = capture_warnings(|| NxfObjGeom::from_read(Cursor::new(&fixture.data)));
+        assert_eq!(nxf.unwrap().facelist_sets[0].facelists[0].faces.len(), 5);
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].contains("overlap its display list"), "{:?}", warnings);
+
+        // Faces running into the facelist's own record.
+        let mut fixture = NxfFixture::quad();
+        fixture.put_u32(fixture::FACELIST + 0x10, fixture::FACELIST as u32 - 0x20);
+        let (nxf, warnings) = capture_warnings(|| NxfObjGeom::from_read(Cursor::new(&fixture.data)));
+        nxf.unwrap();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].contains("overlap a facelist record"), "{:?}", warnings);
+    }
+
+    #[test]
+    fn back_to_back_geoms_resolve_their_own_pointers() {
+        // Both geoms point at their material and string from the same
+        // offsets, so a cache keyed on offset would hand the second geom
+        // the first one's texture.
+        let first = NxfFixture::quad();
+        let mut second = NxfFixture::quad();
+        second.put(fixture::STRING_DATA, b"bar.tex\0");
+        let mut container = first.data.clone();
+        container.extend_from_slice(&second.data);
+
+        let mut cursor = Cursor::new(&container);
+        let first_nxf = NxfObjGeom::from_read_at(&mut cursor, 0).unwrap();
+        let second_nxf = NxfObjGeom::from_read_at(&mut cursor, fixture::LEN as u64).unwrap();
+        assert_eq!(first_nxf, first.expected());
+        assert_eq!(second_nxf.strings, ["bar.tex"]);
+        assert_eq!(second_nxf.materials[0].tex_name, "bar.tex");
+        let facelist = &second_nxf.facelist_sets[0].facelists[0];
+        assert_eq!(facelist.material.as_ref().unwrap().tex_name, "bar.tex");
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::{Error as IOError, ErrorKind};
+
+/// Default cap on the number of entries read from one of the file's linked
+/// lists. A corrupt or cyclic `next` pointer would otherwise never end.
+pub const MAX_LIST_LEN: usize = 0x4000;
+
+/// Default cap on the elements read from one file, counting list entries,
+/// strings, array entries and faces together. Well above any known file.
+pub const MAX_ELEMENTS: u64 = 0x100_0000;
+
+/// How much a single file is trusted to make the parser read, so a corrupt
+/// or malicious one fails instead of exhausting memory.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadLimits {
+    /// Most entries followed in any one linked list (materials, facelists
+    /// or facelist sets).
+    pub max_depth: usize,
+    /// Most elements read from the whole file, as in `MAX_ELEMENTS`.
+    pub max_elements: u64,
+}
+
+impl Default for ReadLimits {
+    fn default() -> ReadLimits {
+        ReadLimits {
+            max_depth: MAX_LIST_LEN,
+            max_elements: MAX_ELEMENTS,
+        }
+    }
+}
+
+/// What one parse has read so far, checked against its `ReadLimits`.
+pub(crate) struct Budget {
+    limits: ReadLimits,
+    elements: u64,
+}
+
+impl Budget {
+    pub fn new(limits: ReadLimits) -> Budget {
+        Budget {
+            limits: limits,
+            elements: 0,
+        }
+    }
+
+    /// Errors out once a linked list has grown past `max_depth` entries.
+    pub fn check_depth(&self, len: usize, what: &str) -> Result<(), IOError> {
+        if len >= self.limits.max_depth {
+            Err(IOError::new(ErrorKind::InvalidData,
+                             format!("{} list longer than {} entries (cyclic next pointer?)", what, self.limits.max_depth)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Counts `count` more elements, erroring out if that goes past
+    /// `max_elements`.
+    pub fn take(&mut self, count: u64, what: &str) -> Result<(), IOError> {
+        self.elements = self.elements.saturating_add(count);
+        if self.elements > self.limits.max_elements {
+            Err(IOError::new(ErrorKind::InvalidData,
+                             format!("reading {} {} entries goes past the limit of {} elements per file (corrupt count?)",
+                                     count, what, self.limits.max_elements)))
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{Read, Seek, SeekFrom, Error as IOError, ErrorKind};
+
+/// A `Read + Seek` wrapper that treats `base` in the inner reader as
+/// position zero, so absolute pointers in an embedded file resolve against
+/// the file's own start.
+pub struct OffsetReader<R> {
+    inner: R,
+    base: u64,
+}
+
+impl<R> OffsetReader<R> {
+    pub fn new(inner: R, base: u64) -> OffsetReader<R> {
+        OffsetReader {
+            inner: inner,
+            base: base,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R> Read for OffsetReader<R>
+    where R: Read
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R> Seek for OffsetReader<R>
+    where R: Seek
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IOError> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(self.base + offset),
+            pos => pos,
+        };
+        let offset = self.inner.seek(pos)?;
+        offset.checked_sub(self.base).ok_or_else(|| {
+            IOError::new(ErrorKind::InvalidInput, "seek before the start of the embedded file")
+        })
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{Write, Error as IOError};
+
+use crate::NxfObjGeom;
+
+impl NxfObjGeom {
+    /// Writes the geometry as an ASCII PLY with a position and RGBA color
+    /// per vertex.
+    ///
+    /// NXF colors are indexed per face corner rather than per position, so a
+    /// position shared by corners with different colors has no single
+    /// color. Each vertex takes the color of the first corner that
+    /// references it; vertices no corner references are white.
+    pub fn write_ply<W>(&self, mut write: W) -> Result<(), IOError>
+        where W: Write
+    {
+        let mut triangles = Vec::new();
+        for facelist in self.facelists() {
+            triangles.extend(facelist.faces.triangles());
+        }
+
+        let mut vertex_colors = vec![None; self.arrays.verts.len()];
+        for corner in triangles.iter().flat_map(|triangle| triangle.corners.iter()) {
+            if let (Some(slot), Some(c)) = (vertex_colors.get_mut(corner.vertex as usize), corner.color) {
+                if slot.is_none() {
+                    *slot = self.arrays.colors.get(c as usize);
+                }
+            }
+        }
+
+        writeln!(write, "ply")?;
+        writeln!(write, "format ascii 1.0")?;
+        writeln!(write, "element vertex {}", self.arrays.verts.len())?;
+        writeln!(write, "property float x")?;
+        writeln!(write, "property float y")?;
+        writeln!(write, "property float z")?;
+        writeln!(write, "property uchar red")?;
+        writeln!(write, "property uchar green")?;
+        writeln!(write, "property uchar blue")?;
+        writeln!(write, "property uchar alpha")?;
+        writeln!(write, "element face {}", triangles.len())?;
+        writeln!(write, "property list uchar int vertex_indices")?;
+        writeln!(write, "end_header")?;
+
+        for (vert, color) in self.arrays.verts.iter().zip(vertex_colors.iter()) {
+            match color {
+                Some(color) => writeln!(write, "{} {} {} {} {} {} {}",
+                    vert.x, vert.y, vert.z, color.r, color.g, color.b, color.a)?,
+                None => writeln!(write, "{} {} {} 255 255 255 255",
+                    vert.x, vert.y, vert.z)?,
+            }
+        }
+
+        for triangle in triangles.iter() {
+            let [a, b, c] = triangle.corners;
+            writeln!(write, "3 {} {} {}", a.vertex, b.vertex, c.vertex)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::NxfFixture;
+
+    #[test]
+    fn header_counts_match_quad() {
+        let mut ply = Vec::new();
+        NxfFixture::quad().expected().write_ply(&mut ply).unwrap();
+        let ply = String::from_utf8(ply).unwrap();
+        assert!(ply.contains("element vertex 4\n"), "{}", ply);
+        assert!(ply.contains("element face 2\n"), "{}", ply);
+        let body: Vec<&str> = ply.split("end_header\n").nth(1).unwrap().lines().collect();
+        assert_eq!(body.len(), 4 + 2, "{}", ply);
+        assert_eq!(&body[4..], &["3 0 1 2", "3 0 2 3"]);
+        // Vertex 2's first corner is the first face's last, with color 1.
+        assert_eq!(body[2], "1 1 0 0 255 0 128");
+    }
+}
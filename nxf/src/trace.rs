@@ -0,0 +1,102 @@
+use std::io::{self, Read, Seek, SeekFrom, Stderr, Write, Error as IOError};
+
+/// A `Read + Seek` wrapper that logs every seek and read to stderr.
+///
+/// Useful for working out why the parser ended up at a given offset when
+/// reverse engineering a file. It's a debugging helper for tools like
+/// `print_nxf --trace`: output goes straight to stderr rather than through
+/// `log`, so it shows up without a logger set up, and it shouldn't be left
+/// wrapped around a reader in library code.
+pub struct TracingReader<R, W = Stderr> {
+    inner: R,
+    log: W,
+}
+
+impl<R> TracingReader<R> {
+    pub fn new(inner: R) -> TracingReader<R> {
+        TracingReader::with_log(inner, io::stderr())
+    }
+}
+
+impl<R, W> TracingReader<R, W> {
+    /// Like `new`, but writes the trace to `log` instead of stderr.
+    pub fn with_log(inner: R, log: W) -> TracingReader<R, W> {
+        TracingReader {
+            inner: inner,
+            log: log,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R, W> Read for TracingReader<R, W>
+    where R: Read + Seek,
+          W: Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        let offset = self.inner.stream_position()?;
+        let size = self.inner.read(buf)?;
+        writeln!(self.log, "read {:#x} bytes at {:#x}", size, offset)?;
+        Ok(size)
+    }
+}
+
+impl<R, W> Seek for TracingReader<R, W>
+    where R: Seek,
+          W: Write,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IOError> {
+        let offset = self.inner.seek(pos)?;
+        match pos {
+            SeekFrom::Current(0) => {}
+            _ => writeln!(self.log, "seek {:?} -> {:#x}", pos, offset)?,
+        }
+        Ok(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::NxfObjGeom;
+    use crate::fixture::{self, NxfFixture};
+
+    #[test]
+    fn logs_seeks_through_the_quad() {
+        let mut log = Vec::new();
+        NxfObjGeom::from_read(TracingReader::with_log(Cursor::new(NxfFixture::quad().data), &mut log)).unwrap();
+        let log = String::from_utf8(log).unwrap();
+        let seeks: Vec<usize> = log.lines()
+            .filter(|line| line.starts_with("seek "))
+            .map(|line| usize::from_str_radix(line.rsplit("0x").next().unwrap(), 16).unwrap())
+            .collect();
+        // Pointer targets in the order the parser follows them; the seeks
+        // back to where it left off in between are left out.
+        let sections = [
+            fixture::STRING_TABLE,
+            fixture::STRING_DATA,
+            fixture::MATERIAL,
+            fixture::STRING_DATA,
+            fixture::ARRAYS,
+            fixture::VERTS,
+            fixture::COLORS,
+            fixture::UVS,
+            fixture::FACELIST_SET,
+            fixture::FACELIST,
+            fixture::MATERIAL,
+            fixture::STRING_DATA,
+            fixture::FACES,
+        ];
+        let targets: Vec<usize> = seeks.iter()
+            .cloned()
+            .filter(|offset| sections.contains(offset))
+            .collect();
+        assert_eq!(targets, sections, "{}", log);
+        assert!(log.contains(&format!("read 0x4 bytes at {:#x}", fixture::VERTS)), "{}", log);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    NxfFaces, NxfColLitTri, NxfTexLitTri, NxfTexUnlitTri, NxfColUnlitTri, NxfTexLitEnvTri,
+    NxfColLitEnvTri,
+};
+
+/// The indices of one triangle corner, with `None` for attributes the face
+/// type doesn't store.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Corner {
+    pub vertex: u16,
+    pub normal: Option<u16>,
+    pub color: Option<u16>,
+    pub uv: Option<u16>,
+    /// The env map (m) index. What it indexes isn't known yet.
+    pub mat: Option<u16>,
+}
+
+/// A triangle of any face type, so exporters don't need a case per type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndexedTriangle {
+    pub corners: [Corner; 3],
+}
+
+fn corner(vertex: u16, normal: Option<u16>, color: Option<u16>, uv: Option<u16>, mat: Option<u16>) -> Corner {
+    Corner {
+        vertex: vertex,
+        normal: normal,
+        color: color,
+        uv: uv,
+        mat: mat,
+    }
+}
+
+impl From<&NxfColLitTri> for IndexedTriangle {
+    fn from(f: &NxfColLitTri) -> IndexedTriangle {
+        IndexedTriangle {
+            corners: [
+                corner(f.v0, Some(f.n0), Some(f.c0), None, None),
+                corner(f.v1, Some(f.n1), Some(f.c1), None, None),
+                corner(f.v2, Some(f.n2), Some(f.c2), None, None),
+            ],
+        }
+    }
+}
+
+impl From<&NxfTexLitTri> for IndexedTriangle {
+    fn from(f: &NxfTexLitTri) -> IndexedTriangle {
+        IndexedTriangle {
+            corners: [
+                corner(f.v0, Some(f.n0), Some(f.c0), Some(f.uv0), None),
+                corner(f.v1, Some(f.n1), Some(f.c1), Some(f.uv1), None),
+                corner(f.v2, Some(f.n2), Some(f.c2), Some(f.uv2), None),
+            ],
+        }
+    }
+}
+
+impl From<&NxfTexUnlitTri> for IndexedTriangle {
+    fn from(f: &NxfTexUnlitTri) -> IndexedTriangle {
+        IndexedTriangle {
+            corners: [
+                corner(f.v0, None, Some(f.c0), Some(f.uv0), None),
+                corner(f.v1, None, Some(f.c1), Some(f.uv1), None),
+                corner(f.v2, None, Some(f.c2), Some(f.uv2), None),
+            ],
+        }
+    }
+}
+
+impl From<&NxfColUnlitTri> for IndexedTriangle {
+    fn from(f: &NxfColUnlitTri) -> IndexedTriangle {
+        IndexedTriangle {
+            corners: [
+                corner(f.v0, None, Some(f.c0), None, None),
+                corner(f.v1, None, Some(f.c1), None, None),
+                corner(f.v2, None, Some(f.c2), None, None),
+            ],
+        }
+    }
+}
+
+impl From<&NxfTexLitEnvTri> for IndexedTriangle {
+    fn from(f: &NxfTexLitEnvTri) -> IndexedTriangle {
+        IndexedTriangle {
+            corners: [
+                corner(f.v0, Some(f.n0), Some(f.c0), Some(f.uv0), Some(f.m0)),
+                corner(f.v1, Some(f.n1), Some(f.c1), Some(f.uv1), Some(f.m1)),
+                corner(f.v2, Some(f.n2), Some(f.c2), Some(f.uv2), Some(f.m2)),
+            ],
+        }
+    }
+}
+
+impl From<&NxfColLitEnvTri> for IndexedTriangle {
+    fn from(f: &NxfColLitEnvTri) -> IndexedTriangle {
+        IndexedTriangle {
+            corners: [
+                corner(f.v0, Some(f.n0), Some(f.c0), None, Some(f.m0)),
+                corner(f.v1, Some(f.n1), Some(f.c1), None, Some(f.m1)),
+                corner(f.v2, Some(f.n2), Some(f.c2), None, Some(f.m2)),
+            ],
+        }
+    }
+}
+
+impl NxfFaces {
+    /// Converts every face to an `IndexedTriangle`. Unknown face types have
+    /// none.
+    pub fn triangles(&self) -> Vec<IndexedTriangle> {
+        match self {
+            NxfFaces::ColLitTri(faces) => faces.iter().map(IndexedTriangle::from).collect(),
+            NxfFaces::TexLitTri(faces) => faces.iter().map(IndexedTriangle::from).collect(),
+            NxfFaces::TexUnlitTri(faces) => faces.iter().map(IndexedTriangle::from).collect(),
+            NxfFaces::ColUnlitTri(faces) => faces.iter().map(IndexedTriangle::from).collect(),
+            NxfFaces::TexLitEnvTri(faces) => faces.iter().map(IndexedTriangle::from).collect(),
+            NxfFaces::ColLitEnvTri(faces) => faces.iter().map(IndexedTriangle::from).collect(),
+            NxfFaces::Unknown(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The corner `i` of a triangle whose indices are 10 + i for vertices,
+    /// 20 + i for normals, 30 + i for colors, 40 + i for uvs and 50 + i for
+    /// env map indices, keeping the attributes the face type has.
+    fn expected(normal: bool, uv: bool, mat: bool) -> IndexedTriangle {
+        let corner_at = |i: u16| corner(
+            10 + i,
+            if normal { Some(20 + i) } else { None },
+            Some(30 + i),
+            if uv { Some(40 + i) } else { None },
+            if mat { Some(50 + i) } else { None },
+        );
+        IndexedTriangle {
+            corners: [corner_at(0), corner_at(1), corner_at(2)],
+        }
+    }
+
+    #[test]
+    fn each_face_type_keeps_its_own_attributes() {
+        let col_lit = NxfColLitTri {
+            v0: 10, n0: 20, c0: 30,
+            v1: 11, n1: 21, c1: 31,
+            v2: 12, n2: 22, c2: 32,
+        };
+        assert_eq!(IndexedTriangle::from(&col_lit), expected(true, false, false));
+
+        let tex_lit = NxfTexLitTri {
+            v0: 10, n0: 20, c0: 30, uv0: 40,
+            v1: 11, n1: 21, c1: 31, uv1: 41,
+            v2: 12, n2: 22, c2: 32, uv2: 42,
+        };
+        assert_eq!(IndexedTriangle::from(&tex_lit), expected(true, true, false));
+
+        let tex_unlit = NxfTexUnlitTri {
+            v0: 10, c0: 30, uv0: 40,
+            v1: 11, c1: 31, uv1: 41,
+            v2: 12, c2: 32, uv2: 42,
+        };
+        assert_eq!(IndexedTriangle::from(&tex_unlit), expected(false, true, false));
+
+        let col_unlit = NxfColUnlitTri {
+            v0: 10, c0: 30,
+            v1: 11, c1: 31,
+            v2: 12, c2: 32,
+        };
+        assert_eq!(IndexedTriangle::from(&col_unlit), expected(false, false, false));
+
+        let tex_lit_env = NxfTexLitEnvTri {
+            v0: 10, n0: 20, c0: 30, uv0: 40, m0: 50,
+            v1: 11, n1: 21, c1: 31, uv1: 41, m1: 51,
+            v2: 12, n2: 22, c2: 32, uv2: 42, m2: 52,
+        };
+        assert_eq!(IndexedTriangle::from(&tex_lit_env), expected(true, true, true));
+
+        let col_lit_env = NxfColLitEnvTri {
+            v0: 10, n0: 20, c0: 30, m0: 50,
+            v1: 11, n1: 21, c1: 31, m1: 51,
+            v2: 12, n2: 22, c2: 32, m2: 52,
+        };
+        assert_eq!(IndexedTriangle::from(&col_lit_env), expected(true, false, true));
+    }
+
+    #[test]
+    fn triangles_converts_every_face() {
+        let faces = NxfFaces::ColUnlitTri(vec![
+            NxfColUnlitTri { v0: 10, c0: 30, v1: 11, c1: 31, v2: 12, c2: 32 },
+            NxfColUnlitTri { v0: 12, c0: 32, v1: 11, c1: 31, v2: 10, c2: 30 },
+        ]);
+        let triangles = faces.triangles();
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(triangles[0], expected(false, false, false));
+        assert_eq!(triangles[1].corners[0].vertex, 12);
+
+        assert!(NxfFaces::Unknown(9).triangles().is_empty());
+    }
+}
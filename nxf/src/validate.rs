@@ -0,0 +1,341 @@
+use std::fmt;
+
+use crate::{NxfObjGeom, NxfFaces, Vec3};
+
+/// One of the arrays in `NxfArray` that face corners index into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Array {
+    Vertex,
+    Normal,
+    Color,
+    Uv,
+}
+
+impl Array {
+    fn len(self, geom: &NxfObjGeom) -> usize {
+        match self {
+            Array::Vertex => geom.arrays.verts.len(),
+            Array::Normal => geom.arrays.normals.len(),
+            Array::Color => geom.arrays.colors.len(),
+            Array::Uv => geom.arrays.uvs.len(),
+        }
+    }
+}
+
+impl fmt::Display for Array {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Array::Vertex => "vertex",
+            Array::Normal => "normal",
+            Array::Color => "color",
+            Array::Uv => "uv",
+        })
+    }
+}
+
+/// A face corner index past the end of the array it refers to.
+#[derive(Clone, Debug)]
+pub struct IndexError {
+    pub set: usize,
+    pub facelist: usize,
+    pub face: usize,
+    /// Which array the index refers to.
+    pub array: Array,
+    pub index: u16,
+    pub len: usize,
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "set {} facelist {} face {}: {} index {} out of range (length {})",
+            self.set, self.facelist, self.face, self.array, self.index, self.len)
+    }
+}
+
+/// Why `NxfObjGeom::triangle_positions` couldn't resolve a face.
+#[derive(Clone, Debug)]
+pub enum PositionError {
+    /// The set, facelist or face asked for doesn't exist.
+    NoFace {
+        set: usize,
+        facelist: usize,
+        face: usize,
+    },
+    /// The face's vertex index is past the end of the vertex array.
+    Index(IndexError),
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PositionError::NoFace { set, facelist, face } =>
+                write!(f, "set {} facelist {} face {} doesn't exist", set, facelist, face),
+            PositionError::Index(err) => err.fmt(f),
+        }
+    }
+}
+
+/// A discrepancy found by `NxfObjGeom::validate_counts`.
+#[derive(Clone, Debug)]
+pub enum CountError {
+    /// An array holds more entries than its stored capacity.
+    Capacity {
+        array: Array,
+        capacity: u32,
+        len: usize,
+    },
+    /// A face corner index past the end of its array.
+    Index(IndexError),
+    /// The facelists' display lists add up to more than the object's,
+    /// which is assumed to hold them all.
+    DisplayListSize {
+        facelists: u64,
+        object: u32,
+    },
+}
+
+impl fmt::Display for CountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CountError::Capacity { array, capacity, len } =>
+                write!(f, "{} array has {} entries but a capacity of {}", array, len, capacity),
+            CountError::Index(err) => err.fmt(f),
+            CountError::DisplayListSize { facelists, object } =>
+                write!(f, "facelist display lists total {:#x} bytes, more than the object's {:#x}", facelists, object),
+        }
+    }
+}
+
+/// Flattens each face into the (array, index) references of its corners.
+/// The env map (m) indices aren't checked, since it's not known what they
+/// index.
+fn face_references(faces: &NxfFaces) -> Vec<Vec<(Array, u16)>> {
+    faces.triangles().iter()
+        .map(|triangle| {
+            let mut references = Vec::new();
+            for corner in triangle.corners.iter() {
+                references.push((Array::Vertex, corner.vertex));
+                references.extend(corner.normal.map(|n| (Array::Normal, n)));
+                references.extend(corner.color.map(|c| (Array::Color, c)));
+                references.extend(corner.uv.map(|uv| (Array::Uv, uv)));
+            }
+            references
+        })
+        .collect()
+}
+
+impl NxfObjGeom {
+    /// Resolves a face's vertex indices into positions.
+    ///
+    /// A `set`, `facelist` or `face` past the end of the geometry is a
+    /// `NoFace` error, and a vertex index from the file past the end of the
+    /// vertex array an `Index` error.
+    pub fn triangle_positions(&self, set: usize, facelist: usize, face: usize) -> Result<[Vec3; 3], PositionError> {
+        let indices = self.facelist_sets.get(set)
+            .and_then(|facelist_set| facelist_set.facelists.get(facelist))
+            .and_then(|list| list.faces.face_vertex_indices(face))
+            .ok_or(PositionError::NoFace {
+                set: set,
+                facelist: facelist,
+                face: face,
+            })?;
+        let verts = &self.arrays.verts;
+        let position = |index: u16| {
+            verts.get(index as usize).cloned().ok_or(PositionError::Index(IndexError {
+                set: set,
+                facelist: facelist,
+                face: face,
+                array: Array::Vertex,
+                index: index,
+                len: verts.len(),
+            }))
+        };
+        Ok([position(indices[0])?, position(indices[1])?, position(indices[2])?])
+    }
+
+    /// Checks every face corner's indices against the lengths of the arrays
+    /// they refer to, returning all out of range references.
+    ///
+    /// Only the arrays a face type stores indices for are checked, but those
+    /// are checked even when empty: a face indexing into an empty array is
+    /// as broken as one indexing past the end of a full one.
+    pub fn validate(&self) -> Result<(), Vec<IndexError>> {
+        let mut errors = Vec::new();
+        for (set_idx, facelist_set) in self.facelist_sets.iter().enumerate() {
+            for (facelist_idx, facelist) in facelist_set.facelists.iter().enumerate() {
+                for (face_idx, references) in face_references(&facelist.faces).into_iter().enumerate() {
+                    for (array, index) in references {
+                        let len = array.len(self);
+                        if index as usize >= len {
+                            errors.push(IndexError {
+                                set: set_idx,
+                                facelist: facelist_idx,
+                                face: face_idx,
+                                array: array,
+                                index: index,
+                                len: len,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks the stored counts against what was read: each array's
+    /// capacity against its length, every face index (as `validate`), and
+    /// the facelists' display list sizes against the object's. Returns every
+    /// discrepancy found, whether from a corrupt file or a parser bug.
+    pub fn validate_counts(&self) -> Result<(), Vec<CountError>> {
+        let mut errors = Vec::new();
+        let arrays = &self.arrays;
+        let capacities = [
+            (Array::Vertex, arrays.max_verts, arrays.verts.len()),
+            (Array::Normal, arrays.max_normals, arrays.normals.len()),
+            (Array::Color, arrays.max_cols, arrays.colors.len()),
+            (Array::Uv, arrays.max_uvs, arrays.uvs.len()),
+        ];
+        for &(array, capacity, len) in capacities.iter() {
+            if len > capacity as usize {
+                errors.push(CountError::Capacity {
+                    array: array,
+                    capacity: capacity,
+                    len: len,
+                });
+            }
+        }
+
+        if let Err(index_errors) = self.validate() {
+            errors.extend(index_errors.into_iter().map(CountError::Index));
+        }
+
+        let facelists = self.facelists()
+            .map(|facelist| facelist.display_list_size as u64)
+            .sum::<u64>();
+        if facelists > self.display_list_size as u64 {
+            errors.push(CountError::DisplayListSize {
+                facelists: facelists,
+                object: self.display_list_size,
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fixture::{self, NxfFixture};
+
+    #[test]
+    fn triangle_positions_resolves_known_face() {
+        let nxf = NxfFixture::quad().expected();
+        let positions = nxf.triangle_positions(0, 0, 1).unwrap();
+        assert_eq!(positions, [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)]);
+    }
+
+    #[test]
+    fn triangle_positions_rejects_missing_face() {
+        let nxf = NxfFixture::quad().expected();
+        for &(set, facelist, face) in [(1, 0, 0), (0, 1, 0), (0, 0, 2)].iter() {
+            match nxf.triangle_positions(set, facelist, face) {
+                Err(PositionError::NoFace { .. }) => {}
+                result => panic!("({}, {}, {}) gave {:?}", set, facelist, face, result),
+            }
+        }
+    }
+
+    #[test]
+    fn triangle_positions_rejects_bad_vertex_index() {
+        let mut fixture = NxfFixture::quad();
+        fixture.put_u16(fixture::FACES + 15 * 2, 9);
+        let nxf = NxfObjGeom::from_read(Cursor::new(&fixture.data)).unwrap();
+        match nxf.triangle_positions(0, 0, 1) {
+            Err(PositionError::Index(error)) => assert_eq!((error.index, error.len), (9, 4)),
+            result => panic!("gave {:?}", result),
+        }
+    }
+
+    #[test]
+    fn fixture_validates() {
+        let nxf = NxfFixture::quad().expected();
+        assert!(nxf.validate().is_ok());
+        assert!(nxf.validate_counts().is_ok());
+    }
+
+    #[test]
+    fn out_of_range_index_is_reported() {
+        let mut fixture = NxfFixture::quad();
+        // The second face's third corner is vertex 3, color 1, uv 3.
+        fixture.put_u16(fixture::FACES + 15 * 2, 9);
+        let nxf = NxfObjGeom::from_read(Cursor::new(&fixture.data)).unwrap();
+        let errors = nxf.validate().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        assert_eq!((error.set, error.facelist, error.face), (0, 0, 1));
+        assert_eq!((error.array, error.index, error.len), (Array::Vertex, 9, 4));
+    }
+
+    #[test]
+    fn index_into_empty_array_is_reported() {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.arrays.verts.clear();
+        nxf.arrays.uvs.clear();
+        let errors = nxf.validate().unwrap_err();
+        // Three vertex and three uv references for each of the two faces.
+        assert_eq!(errors.iter().filter(|error| error.array == Array::Vertex).count(), 6);
+        assert_eq!(errors.iter().filter(|error| error.array == Array::Uv).count(), 6);
+        assert!(errors.iter().all(|error| error.len == 0));
+    }
+
+    #[test]
+    fn unused_arrays_are_not_checked() {
+        // TexUnlitTri faces have no normal indices, so the fixture's empty
+        // normal array is fine.
+        let nxf = NxfFixture::quad().expected();
+        assert!(nxf.arrays.normals.is_empty());
+        assert!(nxf.validate().is_ok());
+    }
+
+    #[test]
+    fn capacity_below_length_is_reported() {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.arrays.max_cols = 1;
+        nxf.arrays.max_uvs = 3;
+        let errors = nxf.validate_counts().unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        match &errors[0] {
+            CountError::Capacity { array: Array::Color, capacity: 1, len: 2 } => {}
+            error => panic!("expected a color capacity error, got {:?}", error),
+        }
+        match &errors[1] {
+            CountError::Capacity { array: Array::Uv, capacity: 3, len: 4 } => {}
+            error => panic!("expected a uv capacity error, got {:?}", error),
+        }
+        assert_eq!(errors[0].to_string(), "color array has 2 entries but a capacity of 1");
+    }
+
+    #[test]
+    fn oversized_facelist_display_lists_are_reported() {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.display_list_size = 0x10;
+        nxf.facelist_sets[0].facelists[0].display_list_size = 0x20;
+        match &nxf.validate_counts().unwrap_err()[..] {
+            [CountError::DisplayListSize { facelists: 0x20, object: 0x10 }] => {}
+            errors => panic!("expected a display list size error, got {:?}", errors),
+        }
+    }
+}
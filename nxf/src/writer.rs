@@ -0,0 +1,384 @@
+use std::io::{Cursor, Write, Error as IOError};
+
+use byteorder::{WriteBytesExt, BE};
+use log::warn;
+
+use crate::{NxfObjGeom, NxfMaterial, NxfArray, NxfFacelistSet, NxfFacelist, NxfFaces};
+
+/// Lays out an NXF in memory. Pointers are written as zeroed placeholder
+/// slots and backfilled once the structure they point to has been placed,
+/// so structures can be emitted in a single forward pass.
+struct NxfWriter {
+    out: Cursor<Vec<u8>>,
+    strings: Vec<(u64, String)>,
+    materials: Vec<(NxfMaterial, u64)>,
+}
+
+impl NxfWriter {
+    fn new() -> NxfWriter {
+        NxfWriter {
+            out: Cursor::new(Vec::new()),
+            strings: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    fn reserve(&mut self) -> Result<u64, IOError> {
+        let slot = self.out.position();
+        self.out.write_u32::<BE>(0)?;
+        Ok(slot)
+    }
+
+    fn backfill(&mut self, slot: u64, target: u64) -> Result<(), IOError> {
+        let save = self.out.position();
+        self.out.set_position(slot);
+        self.out.write_u32::<BE>(target as u32)?;
+        self.out.set_position(save);
+        Ok(())
+    }
+
+    /// Reserves a pointer to a string. The string data itself is written
+    /// out at the end by `write_strings`.
+    fn reserve_string(&mut self, s: &str) -> Result<(), IOError> {
+        let slot = self.reserve()?;
+        self.strings.push((slot, s.to_owned()));
+        Ok(())
+    }
+
+    fn write_strings(&mut self) -> Result<(), IOError> {
+        let strings = std::mem::take(&mut self.strings);
+        for (slot, s) in strings {
+            let offset = self.out.position();
+            self.out.write_all(s.as_bytes())?;
+            self.out.write_u8(0)?;
+            self.backfill(slot, offset)?;
+        }
+        Ok(())
+    }
+
+    fn write_geom(&mut self, nxf: &NxfObjGeom) -> Result<(), IOError> {
+        self.out.write_all(&nxf.id)?;
+        self.out.write_u32::<BE>(nxf.endian)?;
+        self.out.write_f32::<BE>(nxf.version)?;
+        self.out.write_u32::<BE>(nxf.flags)?;
+        self.out.write_u32::<BE>(nxf.alpha_mode)?;
+        self.out.write_u32::<BE>(nxf.env_map_alpha_mode)?;
+
+        self.out.write_u16::<BE>(nxf.strings.len() as u16)?;
+        self.out.write_u16::<BE>(0)?;
+        let strings_slot = self.reserve()?;
+        let materials_slot = self.reserve()?;
+        let arrays_slot = self.reserve()?;
+        let facelist_sets_slot = self.reserve()?;
+
+        // Display lists are not written (yet), so they are always empty.
+        self.out.write_u32::<BE>(0)?;
+        self.out.write_u32::<BE>(0)?;
+        self.out.write_u32::<BE>(0)?; // expanded
+        self.out.write_u32::<BE>(0)?;
+        self.out.write_u32::<BE>(0)?;
+        self.out.write_u32::<BE>(0)?;
+
+        let strings_offset = self.out.position();
+        self.backfill(strings_slot, strings_offset)?;
+        for s in nxf.strings.iter() {
+            self.reserve_string(s)?;
+        }
+
+        let mut next_slot = materials_slot;
+        for material in nxf.materials.iter() {
+            let offset = self.write_material(material)?;
+            self.backfill(next_slot, offset)?;
+            next_slot = self.reserve()?;
+        }
+
+        let arrays_offset = self.out.position();
+        self.backfill(arrays_slot, arrays_offset)?;
+        self.write_arrays(&nxf.arrays)?;
+
+        let mut next_slot = facelist_sets_slot;
+        for facelist_set in nxf.facelist_sets.iter() {
+            let offset = self.out.position();
+            self.backfill(next_slot, offset)?;
+            next_slot = self.write_facelist_set(facelist_set)?;
+        }
+
+        self.write_strings()
+    }
+
+    /// Writes a material record and returns its offset. The caller is
+    /// responsible for the trailing next-material pointer.
+    fn write_material(&mut self, material: &NxfMaterial) -> Result<u64, IOError> {
+        let offset = self.out.position();
+        self.out.write_u32::<BE>(material.tex_pmi)?;
+        self.out.write_u32::<BE>(material.ref_pmi)?;
+        self.reserve_string(&material.tex_name)?;
+        self.out.write_u32::<BE>(material.ref_map)?;
+        self.out.write_u8(material.ref_r)?;
+        self.out.write_u8(material.ref_g)?;
+        self.out.write_u8(material.ref_b)?;
+        self.out.write_u8(material.ref_a)?;
+        self.out.write_u32::<BE>(material.flags)?;
+        self.out.write_u32::<BE>(material.alpha_mode)?;
+        self.out.write_u32::<BE>(material.env_map_alpha_mode)?;
+        self.out.write_u32::<BE>(0)?;
+        self.out.write_u32::<BE>(0)?;
+        self.materials.push((material.clone(), offset));
+        Ok(offset)
+    }
+
+    fn write_arrays(&mut self, arrays: &NxfArray) -> Result<(), IOError> {
+        self.out.write_f32::<BE>(arrays.min_x)?;
+        self.out.write_f32::<BE>(arrays.min_y)?;
+        self.out.write_f32::<BE>(arrays.min_z)?;
+        self.out.write_u32::<BE>(arrays.uvs.len() as u32)?;
+        self.out.write_f32::<BE>(arrays.max_x)?;
+        self.out.write_f32::<BE>(arrays.max_y)?;
+        self.out.write_f32::<BE>(arrays.max_z)?;
+        self.out.write_u32::<BE>(arrays.normals.len() as u32)?;
+        self.out.write_f32::<BE>(arrays.c_x)?;
+        self.out.write_f32::<BE>(arrays.c_y)?;
+        self.out.write_f32::<BE>(arrays.c_z)?;
+        self.out.write_f32::<BE>(arrays.radius)?;
+        self.out.write_u32::<BE>(arrays.verts.len() as u32)?;
+        self.out.write_u32::<BE>(arrays.colors.len() as u32)?;
+        self.out.write_u32::<BE>(arrays.max_verts)?;
+        self.out.write_u32::<BE>(arrays.max_normals)?;
+        self.out.write_u32::<BE>(arrays.max_cols)?;
+        self.out.write_u32::<BE>(arrays.max_uvs)?;
+        let verts_slot = self.reserve()?;
+        let normals_slot = self.reserve()?;
+        let colors_slot = self.reserve()?;
+        let uvs_slot = self.reserve()?;
+        self.out.write_u32::<BE>(arrays.flags)?;
+        self.out.write_u32::<BE>(0)?;
+        self.out.write_u32::<BE>(0)?;
+
+        if !arrays.verts.is_empty() {
+            let offset = self.out.position();
+            self.backfill(verts_slot, offset)?;
+            for vert in arrays.verts.iter() {
+                self.out.write_f32::<BE>(vert.x)?;
+                self.out.write_f32::<BE>(vert.y)?;
+                self.out.write_f32::<BE>(vert.z)?;
+            }
+        }
+
+        if !arrays.normals.is_empty() {
+            let offset = self.out.position();
+            self.backfill(normals_slot, offset)?;
+            for normal in arrays.normals.iter() {
+                self.out.write_f32::<BE>(normal.x)?;
+                self.out.write_f32::<BE>(normal.y)?;
+                self.out.write_f32::<BE>(normal.z)?;
+            }
+        }
+
+        if !arrays.colors.is_empty() {
+            let offset = self.out.position();
+            self.backfill(colors_slot, offset)?;
+            for color in arrays.colors.iter() {
+                self.out.write_u8(color.r)?;
+                self.out.write_u8(color.g)?;
+                self.out.write_u8(color.b)?;
+                self.out.write_u8(color.a)?;
+            }
+        }
+
+        if !arrays.uvs.is_empty() {
+            let offset = self.out.position();
+            self.backfill(uvs_slot, offset)?;
+            for uv in arrays.uvs.iter() {
+                self.out.write_f32::<BE>(uv.u)?;
+                self.out.write_f32::<BE>(uv.v)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a facelist set and its facelists, returning the slot of the
+    /// set's next-set pointer.
+    fn write_facelist_set(&mut self, facelist_set: &NxfFacelistSet) -> Result<u64, IOError> {
+        self.out.write_u32::<BE>(facelist_set.flags)?;
+        self.out.write_u32::<BE>(0)?;
+        self.out.write_u32::<BE>(facelist_set.facelists.len() as u32)?;
+        let facelists_slot = self.reserve()?;
+        // Matrix palettes are not parsed, so none are written.
+        self.out.write_u32::<BE>(0)?;
+        let next_set_slot = self.reserve()?;
+
+        let mut next_slot = facelists_slot;
+        for facelist in facelist_set.facelists.iter() {
+            let offset = self.out.position();
+            self.backfill(next_slot, offset)?;
+            next_slot = self.write_facelist(facelist)?;
+        }
+
+        Ok(next_set_slot)
+    }
+
+    /// Writes a facelist, its faces and (if not already written) its
+    /// material, returning the slot of the next-facelist pointer.
+    fn write_facelist(&mut self, facelist: &NxfFacelist) -> Result<u64, IOError> {
+        self.out.write_u16::<BE>(facelist.flags)?;
+        self.out.write_u8(facelist_type(&facelist.faces))?;
+        self.out.write_u8(facelist.attribs)?;
+        self.out.write_u32::<BE>(0)?;
+        let material_slot = self.reserve()?;
+        self.out.write_u32::<BE>(facelist.faces.len() as u32)?;
+        let faces_slot = self.reserve()?;
+        let next_slot = self.reserve()?;
+        self.out.write_u32::<BE>(0)?;
+        self.out.write_u32::<BE>(0)?;
+
+        // A missing material stays a null pointer.
+        if let Some(facelist_material) = &facelist.material {
+            let existing = self.materials
+                .iter()
+                .find(|(material, _)| material == facelist_material)
+                .map(|(_, offset)| *offset);
+            let material_offset = match existing {
+                Some(offset) => offset,
+                None => {
+                    let offset = self.write_material(facelist_material)?;
+                    self.out.write_u32::<BE>(0)?;
+                    offset
+                }
+            };
+            self.backfill(material_slot, material_offset)?;
+        }
+
+        let faces_offset = self.out.position();
+        self.backfill(faces_slot, faces_offset)?;
+        self.write_faces(&facelist.faces)?;
+
+        Ok(next_slot)
+    }
+
+    fn write_faces(&mut self, faces: &NxfFaces) -> Result<(), IOError> {
+        let mut indices = Vec::new();
+        match faces {
+            NxfFaces::ColLitTri(faces) => {
+                for f in faces {
+                    indices.extend_from_slice(&[f.v0, f.n0, f.c0, f.v1, f.n1, f.c1, f.v2, f.n2, f.c2]);
+                }
+            }
+            NxfFaces::TexLitTri(faces) => {
+                for f in faces {
+                    indices.extend_from_slice(&[
+                        f.v0, f.n0, f.c0, f.uv0,
+                        f.v1, f.n1, f.c1, f.uv1,
+                        f.v2, f.n2, f.c2, f.uv2,
+                    ]);
+                }
+            }
+            NxfFaces::TexUnlitTri(faces) => {
+                for f in faces {
+                    indices.extend_from_slice(&[f.v0, f.c0, f.uv0, f.v1, f.c1, f.uv1, f.v2, f.c2, f.uv2]);
+                }
+            }
+            NxfFaces::ColUnlitTri(faces) => {
+                for f in faces {
+                    indices.extend_from_slice(&[f.v0, f.c0, f.v1, f.c1, f.v2, f.c2]);
+                }
+            }
+            NxfFaces::TexLitEnvTri(faces) => {
+                for f in faces {
+                    indices.extend_from_slice(&[
+                        f.v0, f.n0, f.c0, f.uv0, f.m0,
+                        f.v1, f.n1, f.c1, f.uv1, f.m1,
+                        f.v2, f.n2, f.c2, f.uv2, f.m2,
+                    ]);
+                }
+            }
+            NxfFaces::ColLitEnvTri(faces) => {
+                for f in faces {
+                    indices.extend_from_slice(&[
+                        f.v0, f.n0, f.c0, f.m0,
+                        f.v1, f.n1, f.c1, f.m1,
+                        f.v2, f.n2, f.c2, f.m2,
+                    ]);
+                }
+            }
+            NxfFaces::Unknown(_) => {}
+        }
+
+        for index in indices {
+            self.out.write_u16::<BE>(index)?;
+        }
+        Ok(())
+    }
+}
+
+fn facelist_type(faces: &NxfFaces) -> u8 {
+    match faces {
+        NxfFaces::ColLitTri(_) => 6,
+        NxfFaces::TexLitTri(_) => 8,
+        NxfFaces::TexUnlitTri(_) => 10,
+        NxfFaces::ColUnlitTri(_) => 11,
+        NxfFaces::TexLitEnvTri(_) => 20,
+        NxfFaces::ColLitEnvTri(_) => 21,
+        NxfFaces::Unknown(facelist_type) => *facelist_type,
+    }
+}
+
+impl NxfObjGeom {
+    /// Serializes the geometry back into the NXF layout read by `from_read`.
+    ///
+    /// Only the parts the parser understands are written: display lists and
+    /// matrix palettes are emitted as null pointers, with a warning when the
+    /// geometry has any, and every offset is relative to the start of the
+    /// written data.
+    pub fn write_to<W>(&self, mut write: W) -> Result<(), IOError>
+        where W: Write
+    {
+        let display_lists = self.facelists()
+            .filter(|facelist| facelist.display_list != 0 || facelist.display_list_size != 0)
+            .count();
+        if self.display_list != 0 || self.display_list_size != 0 {
+            warn!("the object's display list ({:#x} bytes) isn't written", self.display_list_size);
+        }
+        if display_lists != 0 {
+            warn!("{} facelist display lists aren't written", display_lists);
+        }
+        let mat_palettes = self.facelist_sets
+            .iter()
+            .filter(|facelist_set| facelist_set.mat_palette.is_some())
+            .count();
+        if mat_palettes != 0 {
+            warn!("{} facelist set matrix palettes aren't written", mat_palettes);
+        }
+
+        let mut writer = NxfWriter::new();
+        writer.write_geom(self)?;
+        write.write_all(writer.out.get_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::fixture::NxfFixture;
+    use crate::NxfObjGeom;
+
+    #[test]
+    fn write_then_read_round_trips() {
+        let original = NxfObjGeom::from_read(Cursor::new(&NxfFixture::quad().data)).unwrap();
+        let mut written = Vec::new();
+        original.write_to(&mut written).unwrap();
+        let reparsed = NxfObjGeom::from_read(Cursor::new(&written)).unwrap();
+        assert_eq!(reparsed, original);
+    }
+
+    #[test]
+    fn null_material_stays_null() {
+        let mut original = NxfFixture::quad().expected();
+        original.facelist_sets[0].facelists[0].material = None;
+        let mut written = Vec::new();
+        original.write_to(&mut written).unwrap();
+        let reparsed = NxfObjGeom::from_read(Cursor::new(&written)).unwrap();
+        assert_eq!(reparsed, original);
+    }
+}
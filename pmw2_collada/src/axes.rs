@@ -0,0 +1,70 @@
+use sf::matrix::Matrix;
+
+/// Which axes get negated when converting from the game's coordinate space
+/// into COLLADA's. This is independent of any `up_axis` in the asset block,
+/// since importers disagree on whether they honor it.
+#[derive(Clone, Copy, Debug)]
+pub struct FlipAxes {
+    pub x: bool,
+    pub y: bool,
+    pub z: bool,
+}
+
+impl Default for FlipAxes {
+    fn default() -> FlipAxes {
+        FlipAxes {
+            x: false,
+            y: true,
+            z: true,
+        }
+    }
+}
+
+impl FlipAxes {
+    /// Parses a comma separated list of axes (e.g. `"y,z"`, `"x"`) or
+    /// `"none"`.
+    pub fn parse(s: &str) -> Option<FlipAxes> {
+        let mut flip = FlipAxes {
+            x: false,
+            y: false,
+            z: false,
+        };
+        if s == "none" {
+            return Some(flip);
+        }
+        for axis in s.split(',') {
+            match axis.trim() {
+                "x" => flip.x = true,
+                "y" => flip.y = true,
+                "z" => flip.z = true,
+                _ => return None,
+            }
+        }
+        Some(flip)
+    }
+
+    fn signs(&self) -> [f32; 3] {
+        [
+            if self.x { -1.0 } else { 1.0 },
+            if self.y { -1.0 } else { 1.0 },
+            if self.z { -1.0 } else { 1.0 },
+        ]
+    }
+
+    pub fn apply(&self, val: (f32, f32, f32)) -> (f32, f32, f32) {
+        let signs = self.signs();
+        (val.0 * signs[0], val.1 * signs[1], val.2 * signs[2])
+    }
+
+    /// Converts a transform by flipping on both sides (F * M * F).
+    pub fn apply_matrix(&self, mut mat: Matrix) -> Matrix {
+        let signs = self.signs();
+        let signs = [signs[0], signs[1], signs[2], 1.0];
+        for row in 0..4 {
+            for col in 0..4 {
+                mat.0[row * 4 + col] *= signs[row] * signs[col];
+            }
+        }
+        mat
+    }
+}
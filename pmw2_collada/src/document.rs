@@ -0,0 +1,281 @@
+use std::io::Write;
+
+use xml::EmitterConfig;
+use xml::common::XmlVersion;
+use xml::writer::{EventWriter, Error as EmitterError};
+use xml::writer::events::XmlEvent;
+
+/// Length of one game unit, declared in the document's `<unit>` so
+/// importers size the model correctly without touching the vertex data.
+#[derive(Clone, Copy, Debug)]
+pub struct Unit {
+    /// Name written in `<unit>`, e.g. `"centimeter"`.
+    pub name: &'static str,
+    /// The unit's length in meters.
+    pub meter: f32,
+}
+
+impl Unit {
+    /// Parses a unit name, e.g. `"cm"`, `"centimeter"` or `"inch"`.
+    pub fn parse(s: &str) -> Option<Unit> {
+        let (name, meter) = match s {
+            "km" | "kilometer" => ("kilometer", 1000.0),
+            "m" | "meter" => ("meter", 1.0),
+            "cm" | "centimeter" => ("centimeter", 0.01),
+            "mm" | "millimeter" => ("millimeter", 0.001),
+            "ft" | "foot" => ("foot", 0.3048),
+            "in" | "inch" => ("inch", 0.0254),
+            _ => return None,
+        };
+        Some(Unit {
+            name: name,
+            meter: meter,
+        })
+    }
+}
+
+/// The XML declaration and `<asset>` settings shared by both converters.
+pub struct DocumentOptions<'a> {
+    /// Encoding named in the XML declaration. The document is always
+    /// written as UTF-8, so this should be a spelling of it.
+    pub xml_encoding: &'a str,
+    /// `standalone` value of the XML declaration, left out when `None`.
+    pub xml_standalone: Option<bool>,
+    /// Factor the output was scaled by, recorded as the document's unit.
+    pub scale: f32,
+    /// Length of a game unit. `None` leaves it undeclared, which importers
+    /// take as a meter.
+    pub unit: Option<Unit>,
+    /// String used for one level of indentation in the output.
+    pub indent_string: &'a str,
+    /// String written between lines of output.
+    pub line_separator: &'a str,
+}
+
+/// A COLLADA document being written: an `EventWriter` plus the scaffolding
+/// every document has.
+pub struct ColladaDocument<W> {
+    writer: EventWriter<W>,
+    xml_encoding: String,
+    xml_standalone: Option<bool>,
+    scale: f32,
+    unit: Option<Unit>,
+}
+
+impl<W> ColladaDocument<W>
+    where W: Write,
+{
+    pub fn new(write: W, options: DocumentOptions) -> ColladaDocument<W> {
+        ColladaDocument {
+            writer: EventWriter::new_with_config(write, EmitterConfig::new()
+                .perform_indent(true)
+                .indent_string(options.indent_string.to_string())
+                .line_separator(options.line_separator.to_string())),
+            xml_encoding: options.xml_encoding.to_string(),
+            xml_standalone: options.xml_standalone,
+            scale: options.scale,
+            unit: options.unit,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    pub fn write<'a, E>(&mut self, event: E) -> Result<(), EmitterError>
+        where E: Into<XmlEvent<'a>>
+    {
+        self.writer.write(event)
+    }
+
+    /// Writes the XML declaration, opens `<COLLADA>` and writes `<asset>`.
+    pub fn write_start(&mut self) -> Result<(), EmitterError> {
+        self.writer.write(XmlEvent::StartDocument {
+            version: XmlVersion::Version10,
+            encoding: Some(&self.xml_encoding),
+            standalone: self.xml_standalone,
+        })?;
+        self.writer.write(
+            XmlEvent::start_element("COLLADA")
+                .attr("xmlns", "http://www.collada.org/2005/11/COLLADASchema")
+                .attr("version", "1.4.1")
+        )?;
+        self.writer.write(XmlEvent::start_element("asset"))?;
+        self.writer.write(XmlEvent::start_element("contributor"))?;
+        self.writer.write(XmlEvent::start_element("authoring_tool"))?;
+        self.writer.write(concat!("pmw2_collada ", env!("CARGO_PKG_VERSION")))?;
+        self.writer.write(XmlEvent::end_element())?;
+        self.writer.write(XmlEvent::end_element())?;
+        self.writer.write(XmlEvent::start_element("created"))?;
+        self.writer.write("2020-04-18T17:41:28")?;
+        self.writer.write(XmlEvent::end_element())?;
+        self.writer.write(XmlEvent::start_element("modified"))?;
+        self.writer.write("2020-04-18T17:41:28")?;
+        self.writer.write(XmlEvent::end_element())?;
+        // Scaled output units are 1 / scale game units each. The name only
+        // fits while they're the same size.
+        if self.unit.is_some() || self.scale != 1.0 {
+            let (name, meter) = match self.unit {
+                Some(unit) => (unit.name, unit.meter),
+                None => ("meter", 1.0),
+            };
+            let meter = (meter / self.scale).to_string();
+            let mut unit = XmlEvent::start_element("unit");
+            if self.scale == 1.0 {
+                unit = unit.attr("name", name);
+            }
+            self.writer.write(unit.attr("meter", meter.as_str()))?;
+            self.writer.write(XmlEvent::end_element())?;
+        }
+        self.writer.write(XmlEvent::end_element())
+    }
+
+    /// Closes `<COLLADA>`.
+    pub fn write_end(&mut self) -> Result<(), EmitterError> {
+        self.writer.write(XmlEvent::end_element())
+    }
+
+    /// Opens a library section, e.g. `"library_geometries"`. Close it with
+    /// `end_library`.
+    pub fn start_library(&mut self, element: &str) -> Result<(), EmitterError> {
+        self.writer.write(XmlEvent::start_element(element))
+    }
+
+    pub fn end_library(&mut self) -> Result<(), EmitterError> {
+        self.writer.write(XmlEvent::end_element())
+    }
+
+    /// Writes a `<source>` holding a float array, with one accessor param
+    /// per name in `params` (so `values` is read in groups of that many).
+    pub fn write_float_source(&mut self, source_id: &str, array_id: &str, values: &[f32], params: &[&str]) -> Result<(), EmitterError> {
+        self.writer.write(
+            XmlEvent::start_element("source")
+                .attr("id", source_id)
+        )?;
+
+        self.writer.write(
+            XmlEvent::start_element("float_array")
+                .attr("id", array_id)
+                .attr("count", values.len().to_string().as_str())
+        )?;
+        let mut data = String::new();
+        for value in values.iter() {
+            data += &format!("{} ", value);
+        }
+        self.writer.write(data.as_str())?;
+        self.writer.write(XmlEvent::end_element())?;
+
+        self.writer.write(XmlEvent::start_element("technique_common"))?;
+        self.writer.write(
+            XmlEvent::start_element("accessor")
+                .attr("source", format!("#{}", array_id).as_str())
+                .attr("count", (values.len() / params.len()).to_string().as_str())
+                .attr("stride", params.len().to_string().as_str())
+        )?;
+        for param in params.iter() {
+            self.writer.write(
+                XmlEvent::start_element("param")
+                    .attr("name", param)
+                    .attr("type", "float")
+            )?;
+            self.writer.write(XmlEvent::end_element())?;
+        }
+        self.writer.write(XmlEvent::end_element())?;
+        self.writer.write(XmlEvent::end_element())?;
+
+        self.writer.write(XmlEvent::end_element())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use nxf::fixture::NxfFixture;
+    use sf::SceneTemplate;
+    use sf::fixture::{self, ClumpFixture, PlacementFixture};
+
+    use super::*;
+    use crate::nxf2collada::{Nxf2Collada, Nxf2ColladaOptions};
+    use crate::sf2collada::{Sf2Collada, Sf2ColladaOptions};
+
+    fn default_options() -> DocumentOptions<'static> {
+        DocumentOptions {
+            xml_encoding: "utf-8",
+            xml_standalone: None,
+            scale: 1.0,
+            unit: None,
+            indent_string: "  ",
+            line_separator: "\n",
+        }
+    }
+
+    /// An empty document written with `options`.
+    fn empty_document(options: DocumentOptions) -> String {
+        let mut document = ColladaDocument::new(Vec::new(), options);
+        document.write_start().unwrap();
+        document.write_end().unwrap();
+        String::from_utf8(document.into_inner()).unwrap()
+    }
+
+    /// The `<asset>` element of a document.
+    fn asset(dae: &str) -> &str {
+        let start = dae.find("<asset>").unwrap_or_else(|| panic!("no asset in {}", dae));
+        &dae[start..start + dae[start..].find("</asset>").unwrap() + "</asset>".len()]
+    }
+
+    #[test]
+    fn asset_block_matches_the_converters_own() {
+        let expected = concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+            "<COLLADA xmlns=\"http://www.collada.org/2005/11/COLLADASchema\" version=\"1.4.1\">\n",
+            "  <asset>\n",
+            "    <contributor>\n",
+            "      <authoring_tool>pmw2_collada ", env!("CARGO_PKG_VERSION"), "</authoring_tool>\n",
+            "    </contributor>\n",
+            "    <created>2020-04-18T17:41:28</created>\n",
+            "    <modified>2020-04-18T17:41:28</modified>\n",
+            "  </asset>\n",
+            "</COLLADA>",
+        );
+        let dae = empty_document(default_options());
+        assert_eq!(dae, expected);
+
+        let mut nxf = Nxf2Collada::new("quad".to_string(), NxfFixture::quad().expected(), Vec::new(),
+                                       Nxf2ColladaOptions::default());
+        nxf.write_collada().unwrap();
+        let nxf_dae = String::from_utf8(nxf.into_inner()).unwrap();
+        assert_eq!(asset(&nxf_dae), asset(&dae));
+
+        let data = fixture::build("town", &[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![PlacementFixture::new(0, 9, "house")],
+        }]);
+        let sf = SceneTemplate::from_read(Cursor::new(data)).unwrap();
+        let mut sf = Sf2Collada::new(sf, Vec::new(), Sf2ColladaOptions::default());
+        sf.write_collada().unwrap();
+        let sf_dae = String::from_utf8(sf.into_inner()).unwrap();
+        assert_eq!(asset(&sf_dae), asset(&dae));
+    }
+
+    #[test]
+    fn unit_cm_is_declared_in_the_asset() {
+        let dae = empty_document(DocumentOptions {
+            unit: Unit::parse("cm"),
+            ..default_options()
+        });
+        assert!(asset(&dae).contains("<unit name=\"centimeter\" meter=\"0.01\" />"), "{}", dae);
+
+        // Scaled output units no longer match the name, so only the length
+        // is given.
+        let dae = empty_document(DocumentOptions {
+            unit: Unit::parse("cm"),
+            scale: 2.0,
+            ..default_options()
+        });
+        assert!(asset(&dae).contains("<unit meter=\"0.005\" />"), "{}", dae);
+
+        assert!(!empty_document(default_options()).contains("<unit"));
+        assert!(Unit::parse("furlong").is_none());
+    }
+}
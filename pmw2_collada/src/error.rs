@@ -0,0 +1,102 @@
+use std::error::Error;
+use std::fmt;
+use std::io::Error as IOError;
+
+use xml::writer::Error as EmitterError;
+
+/// Everything that can go wrong converting a file, so reading the input,
+/// writing the document and finishing the output can share one `?` chain.
+#[derive(Debug)]
+pub enum ConvertError {
+    /// Writing the document failed.
+    Xml(EmitterError),
+    /// Strict mode refused a construct the converter can't handle.
+    Strict(String),
+    /// Opening, creating or finishing a file failed.
+    Io(IOError),
+    /// The NXF input didn't parse.
+    Nxf(IOError),
+    /// The SF input didn't parse.
+    Sf(IOError),
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertError::Xml(err) => write!(f, "{}", err),
+            ConvertError::Strict(msg) => write!(f, "{} (strict mode)", msg),
+            ConvertError::Io(err) => write!(f, "{}", err),
+            ConvertError::Nxf(err) => write!(f, "bad NXF file: {}", err),
+            ConvertError::Sf(err) => write!(f, "bad SF file: {}", err),
+        }
+    }
+}
+
+impl Error for ConvertError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConvertError::Xml(err) => Some(err),
+            ConvertError::Strict(_) => None,
+            ConvertError::Io(err) | ConvertError::Nxf(err) | ConvertError::Sf(err) => Some(err),
+        }
+    }
+}
+
+impl From<EmitterError> for ConvertError {
+    fn from(err: EmitterError) -> ConvertError {
+        ConvertError::Xml(err)
+    }
+}
+
+impl From<IOError> for ConvertError {
+    fn from(err: IOError) -> ConvertError {
+        ConvertError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Write};
+
+    use nxf::fixture::NxfFixture;
+    use sf::SceneTemplate;
+    use sf::fixture::{self, ClumpFixture, PlacementFixture};
+
+    use super::*;
+    use crate::nxf2collada::{Nxf2Collada, Nxf2ColladaOptions};
+    use crate::sf2collada::{Sf2Collada, Sf2ColladaOptions};
+
+    /// A writer whose every write fails, like a full disk.
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(IOError::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failing_writer_is_an_xml_error() {
+        let options = Nxf2ColladaOptions::default();
+        let mut converter = Nxf2Collada::new("quad".to_string(), NxfFixture::quad().expected(), FailingWriter, options);
+        match converter.write_collada() {
+            Err(err @ ConvertError::Xml(_)) => assert!(err.to_string().contains("disk full"), "{}", err),
+            other => panic!("expected an XML error, got {:?}", other),
+        }
+
+        let data = fixture::build("town", &[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![PlacementFixture::new(0, 9, "house")],
+        }]);
+        let sf = SceneTemplate::from_read(Cursor::new(data)).unwrap();
+        let mut converter = Sf2Collada::new(sf, FailingWriter, Sf2ColladaOptions::default());
+        match converter.write_collada() {
+            Err(ConvertError::Xml(_)) => {}
+            other => panic!("expected an XML error, got {:?}", other),
+        }
+    }
+}
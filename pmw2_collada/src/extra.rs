@@ -0,0 +1,25 @@
+use std::io::Write;
+
+use xml::writer::Error as EmitterError;
+use xml::writer::events::XmlEvent;
+
+use crate::document::ColladaDocument;
+
+/// Writes raw values that have no COLLADA equivalent into an
+/// `<extra><technique profile="PMW2">` block.
+pub fn write_pmw2_extra<W>(writer: &mut ColladaDocument<W>, values: &[(&str, String)]) -> Result<(), EmitterError>
+    where W: Write
+{
+    writer.write(XmlEvent::start_element("extra"))?;
+    writer.write(
+        XmlEvent::start_element("technique")
+            .attr("profile", "PMW2")
+    )?;
+    for (name, value) in values.iter() {
+        writer.write(XmlEvent::start_element(*name))?;
+        writer.write(value.as_str())?;
+        writer.write(XmlEvent::end_element())?;
+    }
+    writer.write(XmlEvent::end_element())?;
+    writer.write(XmlEvent::end_element())
+}
@@ -1,10 +1,19 @@
+mod axes;
+mod document;
+mod error;
+mod extra;
+mod names;
 mod nxf2collada;
+mod output;
+mod progress;
 mod sf2collada;
-mod matrix;
+mod strict;
 
+use std::collections::HashSet;
 use std::env;
 use std::error::Error;
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::process;
 
@@ -12,8 +21,14 @@ use nxf::NxfObjGeom;
 use sf::SceneTemplate;
 use getopts::Options;
 
-use nxf2collada::Nxf2Collada;
-use sf2collada::Sf2Collada;
+use axes::FlipAxes;
+use document::Unit;
+use error::ConvertError;
+use names::file_name_part;
+use nxf2collada::{split_by_material, AlphaTreatment, Nxf2Collada, Nxf2ColladaOptions};
+use output::Output;
+use progress::stderr_progress;
+use sf2collada::{PlacementName, Sf2Collada, Sf2ColladaOptions};
 
 trait UnwrapOrBarfExt<T> {
     fn unwrap_or_barf(self, err_str: &str) -> T;
@@ -52,20 +67,169 @@ fn print_help(program: &str, opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
+fn read_nxf(in_filename: &str) -> Result<NxfObjGeom, ConvertError> {
+    let fin = File::open(in_filename)?;
+    NxfObjGeom::from_read(fin).map_err(ConvertError::Nxf)
+}
+
+fn read_sf(in_filename: &str) -> Result<SceneTemplate, ConvertError> {
+    let fin = File::open(in_filename)?;
+    SceneTemplate::from_read(fin).map_err(ConvertError::Sf)
+}
+
+/// Converts one or more NXF models into a single document.
+fn write_nxf(models: Vec<(String, NxfObjGeom)>, out_filename: &Path, mut options: Nxf2ColladaOptions, progress: bool, gzip: bool) -> Result<(), ConvertError> {
+    // Merged models need their ids namespaced to keep them apart, and so
+    // distinct names: inputs from different directories can share a stem.
+    let models = if models.len() > 1 {
+        options.prefix_ids = true;
+        let (names, geoms): (Vec<String>, Vec<NxfObjGeom>) = models.into_iter().unzip();
+        unique_names(names).into_iter().zip(geoms).collect()
+    } else {
+        models
+    };
+
+    let fout = Output::create(out_filename, gzip)?;
+    let mut models = models.into_iter();
+    let (name, nxf) = models.next().unwrap();
+    let mut converter = Nxf2Collada::new(name, nxf, fout, options);
+    for (name, nxf) in models {
+        converter.add_model(name, nxf);
+    }
+    if progress {
+        converter.set_progress(stderr_progress());
+    }
+    converter.write_collada()?;
+    converter.into_inner().finish()?;
+    Ok(())
+}
+
+fn write_sf(sf: SceneTemplate, out_filename: &Path, options: Sf2ColladaOptions, progress: bool, gzip: bool) -> Result<(), ConvertError> {
+    let fout = Output::create(out_filename, gzip)?;
+    let mut converter = Sf2Collada::new(sf, fout, options);
+    if progress {
+        converter.set_progress(stderr_progress());
+    }
+    converter.write_collada()?;
+    converter.into_inner().finish()?;
+    Ok(())
+}
+
+/// Names for `--split-by-material`'s files, one per material. Material
+/// names come from the file, so they're cleaned up with `file_name_part`
+/// ("material" if that leaves nothing), and names that come out the same
+/// are made unique with `unique_names`.
+fn split_part_names<'a, I>(materials: I) -> Vec<String>
+    where I: IntoIterator<Item = &'a str>
+{
+    unique_names(materials.into_iter().map(|material| {
+        let base = file_name_part(material);
+        if base.is_empty() { "material".to_string() } else { base }
+    }))
+}
+
+/// Appends `_2`, `_3`, ... to names already taken by an earlier one.
+fn unique_names<I>(names: I) -> Vec<String>
+    where I: IntoIterator<Item = String>
+{
+    let mut used = HashSet::new();
+    let mut unique = Vec::new();
+    for base in names {
+        let mut name = base.clone();
+        let mut n = 2;
+        while !used.insert(name.clone()) {
+            name = format!("{}_{}", base, n);
+            n += 1;
+        }
+        unique.push(name);
+    }
+    unique
+}
+
+/// `--check`: reads the input and, for NXF, checks its counts and indices
+/// add up, writing nothing. Returns the message to print on success, or
+/// why the input is bad.
+fn check_input(operation: &Operation) -> Result<&'static str, String> {
+    match operation {
+        Operation::SfDecode(in_filename) => {
+            read_sf(in_filename).map_err(|err| format!("Could not read SF file: {}", err))?;
+            Ok("Successfully parsed SF file.")
+        }
+        Operation::NxfDecode(in_filenames) => {
+            for in_filename in in_filenames.iter() {
+                let nxf = read_nxf(in_filename).map_err(|err| format!("Could not read NXF file: {}", err))?;
+                if let Err(errors) = nxf.validate_counts() {
+                    let mut msg = format!("{} has {} count or index errors", in_filename, errors.len());
+                    for error in errors.iter() {
+                        msg += &format!("\n{}", error);
+                    }
+                    return Err(msg);
+                }
+            }
+            Ok("Successfully parsed NXF file.")
+        }
+    }
+}
+
 enum Operation {
     SfDecode(String),
-    NxfDecode(String),
+    NxfDecode(Vec<String>),
 }
 
 fn main() {
+    // Warnings show by default; RUST_LOG raises or lowers the level.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .format(|buf, record| writeln!(buf, "{}: {}", record.level(), record.args()))
+        .init();
+
     let args: Vec<String> = env::args().collect();
     let program = args[0].as_str();
 
     let mut opts = Options::new();
     opts.optopt("", "sf", "SF input file", "FILE").long_only(true);
-    opts.optopt("", "nxf", "NXF input file", "FILE").long_only(true);
+    opts.optmulti("", "nxf", "NXF input file; repeat to merge several into one scene", "FILE").long_only(true);
     opts.optflag("h", "help", "print this help menu");
+    opts.optflag("", "check", "only check that the input parses and its NXF counts and indices add up; no output is written");
     opts.optflag("p", "placements", "include placements (bounding boxes and points)");
+    opts.optflag("", "lights", "include SF lights and cameras");
+    opts.optflag("", "show-bounds", "draw boxes around the SF scene and clump bounds");
+    opts.optmulti("", "exclude-type", "leave out SF placements of type NAME, e.g. \"Point\"", "NAME");
+    opts.optflag("", "group-by-clump", "group SF placements under a node per clump");
+    opts.optflag("", "no-vertex-colors", "omit per-vertex colors from NXF output");
+    opts.optflag("", "split-color-alpha", "write NXF vertex alpha as a separate color set");
+    opts.optflag("", "recompute-normals", "compute normals when the NXF's are missing or zero");
+    opts.optmulti("", "tangents", "write NXF tangents and binormals for normal mapping on material NAME; repeatable", "NAME");
+    opts.optflag("", "double-sided", "mark NXF materials as double sided");
+    opts.optopt("", "force-alpha", "alpha treatment for all NXF materials: \"opaque\", \"blend\" or \"cutout\"", "MODE");
+    opts.optflag("", "split-by-material", "write each NXF material's faces to their own OUT_FILE_<material> file");
+    opts.optflag("", "merge-by-material", "write one triangle list per NXF material");
+    opts.optflag("", "prefix-ids", "prefix NXF document ids with the model name");
+    opts.optflag("", "split-lods", "write each NXF facelist set as a separate LOD node");
+    opts.optflag("", "tristrips", "write NXF faces as reconstructed triangle strips");
+    opts.optflag("", "geometry-only", "write only NXF geometry, without materials");
+    opts.optflag("", "no-default-material", "leave NXF faces without a material unbound instead of using a default one");
+    opts.optflag("", "used-materials-only", "write only the NXF materials some facelist uses");
+    opts.optflag("", "polylist", "write NXF faces as <polylist> instead of <triangles>");
+    opts.optflag("", "keep-degenerate", "keep NXF triangles with a repeated vertex, which are dropped by default");
+    opts.optflag("", "single-index", "index every NXF input with one shared index per corner");
+    opts.optflag("", "progress", "report conversion progress on stderr");
+    opts.optflag("", "strict", "fail on any construct that can't be converted");
+    opts.optflag("", "center", "move NXF geometry so its bounding box is centered on the origin");
+    opts.optopt("", "scale", "scale exported positions by FACTOR", "FACTOR");
+    opts.optopt("", "unit", "declare a game unit's length, e.g. \"cm\", \"m\" or \"inch\"", "UNIT");
+    opts.optopt("", "geom-file", "file pattern for SF geometry, default \"{name}.dae\"", "PATTERN");
+    opts.optopt("", "geom-node", "node id instanced from SF geometry files, default \"main_node\"", "PATTERN");
+    opts.optopt("", "indent", "indent output with STRING instead of two spaces", "STRING");
+    opts.optflag("", "crlf", "end output lines with CRLF");
+    opts.optflag("", "gzip", "gzip-compress the output file");
+    opts.optopt("", "xml-encoding", "spelling of UTF-8 named in the XML declaration, e.g. \"UTF-8\"; default \"utf-8\"", "NAME");
+    opts.optopt("", "xml-standalone", "standalone value of the XML declaration: \"yes\" or \"no\"", "VALUE");
+    opts.optopt("", "file-name-from", "SF placement name used for geometry files: \"geom\" (default) or \"model\"", "NAME");
+    opts.optopt("", "node-name-from", "SF placement name used for node names: \"geom\" (default) or \"model\"", "NAME");
+    opts.optopt("", "tex-ext", "extension of NXF texture files, default \"png\"", "EXT");
+    opts.optflag("", "blender-names", "make material and node names safe from Blender's renaming");
+    opts.optopt("", "tex-dir", "look up NXF texture files in DIR, ignoring case; found ones are referenced relative to OUT_FILE", "DIR");
+    opts.optopt("", "flip-axis", "axes to negate, e.g. \"y,z\" (default), \"x\" or \"none\"", "AXES");
     let matches = opts.parse(&args[1..])
         .map_err(|err| barf(&err.to_string()))
         .unwrap();
@@ -75,18 +239,117 @@ fn main() {
         return;
     }
 
-    let include_placments = matches.opt_present("p");
+    let flip_axes = matches.opt_str("flip-axis")
+        .map(|axes| FlipAxes::parse(&axes).unwrap_or_barf("Invalid --flip-axis value"))
+        .unwrap_or_default();
 
-    let out_filename = if !matches.free.is_empty() {
-        matches.free[0].clone()
-    } else {
+    let scale = matches.opt_str("scale")
+        .map(|scale| scale.parse::<f32>().unwrap_or_barf("Invalid --scale value"))
+        // Placements and the `<unit>` divide by the scale.
+        .map(|scale| if scale.is_finite() && scale > 0.0 { scale } else { barf("Invalid --scale value: must be finite and positive") })
+        .unwrap_or(1.0);
+    let unit = matches.opt_str("unit")
+        .map(|unit| Unit::parse(&unit).unwrap_or_barf("Invalid --unit value"));
+
+    let indent_string = matches.opt_str("indent").unwrap_or_else(|| "  ".to_string());
+    let line_separator = if matches.opt_present("crlf") { "\r\n" } else { "\n" }.to_string();
+
+    // The output is always written as UTF-8, so only the name's spelling can
+    // change.
+    let xml_encoding = matches.opt_str("xml-encoding").unwrap_or_else(|| "utf-8".to_string());
+    if !["utf-8", "utf8"].contains(&xml_encoding.to_ascii_lowercase().as_str()) {
+        barf("Invalid --xml-encoding value: output is always UTF-8");
+    }
+    let xml_standalone = matches.opt_str("xml-standalone")
+        .map(|standalone| match standalone.as_str() {
+            "yes" => true,
+            "no" => false,
+            _ => barf("Invalid --xml-standalone value"),
+        });
+
+    let file_name = matches.opt_str("file-name-from")
+        .map(|name| PlacementName::parse(&name).unwrap_or_barf("Invalid --file-name-from value"))
+        .unwrap_or(PlacementName::Geom);
+    let node_name = matches.opt_str("node-name-from")
+        .map(|name| PlacementName::parse(&name).unwrap_or_barf("Invalid --node-name-from value"))
+        .unwrap_or(PlacementName::Geom);
+
+    let force_alpha = matches.opt_str("force-alpha")
+        .map(|alpha| AlphaTreatment::parse(&alpha).unwrap_or_barf("Invalid --force-alpha value"));
+
+    let sf_defaults = Sf2ColladaOptions::default();
+    let sf_options = Sf2ColladaOptions {
+        include_placements: matches.opt_present("p"),
+        group_by_clump: matches.opt_present("group-by-clump"),
+        flip_axes: flip_axes,
+        strict: matches.opt_present("strict"),
+        scale: scale,
+        unit: unit,
+        geom_file_template: matches.opt_str("geom-file").unwrap_or(sf_defaults.geom_file_template),
+        geom_node_id: matches.opt_str("geom-node").unwrap_or(sf_defaults.geom_node_id),
+        include_lights: matches.opt_present("lights"),
+        show_bounds: matches.opt_present("show-bounds"),
+        file_name: file_name,
+        node_name: node_name,
+        exclude_types: matches.opt_strs("exclude-type"),
+        blender_names: matches.opt_present("blender-names"),
+        xml_encoding: xml_encoding.clone(),
+        xml_standalone: xml_standalone,
+        indent_string: indent_string.clone(),
+        line_separator: line_separator.clone(),
+    };
+    let nxf_options = Nxf2ColladaOptions {
+        vertex_colors: !matches.opt_present("no-vertex-colors"),
+        split_color_alpha: matches.opt_present("split-color-alpha"),
+        flip_axes: flip_axes,
+        strict: matches.opt_present("strict"),
+        recompute_normals: matches.opt_present("recompute-normals"),
+        tangents: matches.opt_strs("tangents"),
+        double_sided: matches.opt_present("double-sided"),
+        force_alpha: force_alpha,
+        scale: scale,
+        unit: unit,
+        center: matches.opt_present("center"),
+        merge_by_material: matches.opt_present("merge-by-material"),
+        prefix_ids: matches.opt_present("prefix-ids"),
+        split_lods: matches.opt_present("split-lods"),
+        tristrips: matches.opt_present("tristrips"),
+        polylist: matches.opt_present("polylist"),
+        single_index: matches.opt_present("single-index"),
+        drop_degenerate: !matches.opt_present("keep-degenerate"),
+        geometry_only: matches.opt_present("geometry-only"),
+        used_materials_only: matches.opt_present("used-materials-only"),
+        no_default_material: matches.opt_present("no-default-material"),
+        tex_ext: matches.opt_str("tex-ext").unwrap_or_else(|| "png".to_string()),
+        tex_dir: matches.opt_str("tex-dir"),
+        // Textures are referenced relative to where the output goes.
+        out_dir: matches.free.get(0)
+            .and_then(|out_filename| Path::new(out_filename).parent())
+            .filter(|out_dir| !out_dir.as_os_str().is_empty())
+            .map(|out_dir| out_dir.to_string_lossy().into_owned()),
+        blender_names: matches.opt_present("blender-names"),
+        xml_encoding: xml_encoding,
+        xml_standalone: xml_standalone,
+        indent_string: indent_string,
+        line_separator: line_separator,
+    };
+
+    let check = matches.opt_present("check");
+    let progress = matches.opt_present("progress");
+    let gzip = matches.opt_present("gzip");
+    let split_material = matches.opt_present("split-by-material");
+
+    let out_filename = matches.free.get(0).cloned();
+    if out_filename.is_none() && !check {
         print_help(program, opts);
         return;
-    };
+    }
 
     let operations: Vec<Operation> = vec![
         matches.opt_str("sf").map(|v| Operation::SfDecode(v)),
-        matches.opt_str("nxf").map(|v| Operation::NxfDecode(v)),
+        Some(matches.opt_strs("nxf"))
+            .filter(|v| !v.is_empty())
+            .map(|v| Operation::NxfDecode(v)),
     ]
         .into_iter()
         .filter_map(|v| v)
@@ -102,30 +365,187 @@ fn main() {
 
     let operation = operations.into_iter().next().unwrap();
 
+    if check {
+        match check_input(&operation) {
+            Ok(msg) => println!("{}", msg),
+            Err(msg) => barf(&msg),
+        }
+        return;
+    }
+
     match operation {
         Operation::SfDecode(in_filename) => {
-            let fin = File::open(&in_filename).unwrap();
-            let fout = File::create(out_filename).unwrap();
-
-            let sf = SceneTemplate::from_read(fin).unwrap();
-            let mut converter = Sf2Collada::new(sf, fout, include_placments);
-            converter.write_collada().unwrap();
+            let sf = read_sf(&in_filename).unwrap_or_barf("Could not read SF file");
+            write_sf(sf, Path::new(&out_filename.unwrap()), sf_options, progress, gzip)
+                .unwrap_or_barf("Could not convert SF file");
             println!("Successfully converted SF file to collada.");
         }
-        Operation::NxfDecode(in_filename) => {
-            let fin = File::open(&in_filename).unwrap();
-            let fout = File::create(out_filename).unwrap();
-
-            let in_file = Path::new(&in_filename)
-                .file_name()
-                .and_then(|f| Path::new(f).file_stem())
-                .and_then(|f| f.to_str())
-                .unwrap_or_else(|| barf("Could not get base file name"));
-
-            let nxf = NxfObjGeom::from_read(fin).unwrap();
-            let mut converter = Nxf2Collada::new(in_file.into(), nxf, fout);
-            converter.write_collada().unwrap();
+        Operation::NxfDecode(in_filenames) => {
+            let mut models = Vec::new();
+            for in_filename in in_filenames.iter() {
+                let nxf = read_nxf(in_filename).unwrap_or_barf("Could not read NXF file");
+                let in_file = Path::new(in_filename)
+                    .file_name()
+                    .and_then(|f| Path::new(f).file_stem())
+                    .and_then(|f| f.to_str())
+                    .unwrap_or_else(|| barf("Could not get base file name"));
+                models.push((in_file.to_string(), nxf));
+            }
+
+            let out_filename = out_filename.unwrap();
+            let out_path = Path::new(&out_filename);
+            if split_material {
+                if models.len() > 1 {
+                    barf("--split-by-material takes a single NXF file");
+                }
+                // OUT_FILE names the set: out.dae becomes out_<material>.dae.
+                let stem = out_path.file_stem().and_then(|f| f.to_str()).unwrap_or("");
+                let ext = out_path.extension().and_then(|f| f.to_str())
+                    .map_or(String::new(), |ext| format!(".{}", ext));
+                let (name, nxf) = models.pop().unwrap();
+                let parts = split_by_material(&nxf);
+                let part_names = split_part_names(parts.iter().map(|(material, _)| material.as_str()));
+                for ((_, part), part_name) in parts.into_iter().zip(part_names) {
+                    let part_path = out_path.with_file_name(format!("{}_{}{}", stem, part_name, ext));
+                    write_nxf(vec![(name.clone(), part)], &part_path, nxf_options.clone(), progress, gzip)
+                        .unwrap_or_barf("Could not convert NXF file");
+                    println!("Wrote {}.", part_path.display());
+                }
+            } else {
+                write_nxf(models, out_path, nxf_options, progress, gzip)
+                    .unwrap_or_barf("Could not convert NXF file");
+            }
             println!("Successfully converted NXF file to collada.");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use nxf::fixture::NxfFixture;
+    use sf::fixture::{self, ClumpFixture, PlacementFixture};
+
+    use super::*;
+
+    #[test]
+    fn split_part_names_are_unique() {
+        let names = split_part_names(vec!["a/b", "a_b", "", "a.b", "c", ""]);
+        assert_eq!(names, ["a_b", "a_b_2", "material", "a_b_3", "c", "material_2"]);
+    }
+
+    /// `--check` passes a file when it reads (and, for NXF, its counts add
+    /// up) and fails, which `main` turns into a non-zero exit, when it
+    /// doesn't. Either way nothing is written.
+    #[test]
+    fn check_passes_good_files_and_fails_truncated_ones() {
+        let dir = env::temp_dir().join(format!("pmw2_collada_check_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            fs::write(&path, data).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+
+        let nxf = NxfFixture::quad();
+        let mut bad_index = NxfFixture::quad();
+        bad_index.put_u16(nxf::fixture::FACES + 15 * 2, 9);
+        let sf = fixture::build("town", &[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![PlacementFixture::new(0, 9, "house")],
+        }]);
+        let good_nxf = Operation::NxfDecode(vec![write("good.nxf", &nxf.data)]);
+        let short_nxf = Operation::NxfDecode(vec![write("short.nxf", &nxf.data[..0x300])]);
+        let bad_nxf = Operation::NxfDecode(vec![write("good_too.nxf", &nxf.data), write("bad.nxf", &bad_index.data)]);
+        let good_sf = Operation::SfDecode(write("good.sf", &sf));
+        let short_sf = Operation::SfDecode(write("short.sf", &sf[..sf.len() - 4]));
+
+        assert_eq!(check_input(&good_nxf), Ok("Successfully parsed NXF file."));
+        let err = check_input(&short_nxf).unwrap_err();
+        assert!(err.starts_with("Could not read NXF file: "), "{}", err);
+        let err = check_input(&bad_nxf).unwrap_err();
+        assert!(err.contains("bad.nxf has 1 count or index errors"), "{}", err);
+        assert!(err.contains("vertex index 9 out of range"), "{}", err);
+        assert_eq!(check_input(&good_sf), Ok("Successfully parsed SF file."));
+        let err = check_input(&short_sf).unwrap_err();
+        assert!(err.starts_with("Could not read SF file: "), "{}", err);
+
+        let mut files: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, ["bad.nxf", "good.nxf", "good.sf", "good_too.nxf", "short.nxf", "short.sf"]);
+    }
+
+    #[test]
+    fn two_nxf_inputs_write_two_geometries() {
+        let dir = env::temp_dir().join(format!("pmw2_collada_merge_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("both.dae");
+        let models = vec![
+            ("house".to_string(), NxfFixture::quad().expected()),
+            ("tree".to_string(), NxfFixture::quad().expected()),
+        ];
+        write_nxf(models, &path, Nxf2ColladaOptions::default(), false, false).unwrap();
+        let dae = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(dae.matches("<geometry ").count(), 2, "{}", dae);
+        assert!(dae.contains("<geometry id=\"house_geometry\""), "{}", dae);
+        assert!(dae.contains("<geometry id=\"tree_geometry\""), "{}", dae);
+        assert!(dae.contains("url=\"#house_main_node\""), "{}", dae);
+        assert!(dae.contains("url=\"#tree_main_node\""), "{}", dae);
+    }
+
+    #[test]
+    fn gzip_output_decompresses_to_the_plain_output() {
+        let dir = env::temp_dir().join(format!("pmw2_collada_gzip_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let models = || vec![("quad".to_string(), NxfFixture::quad().expected())];
+        let plain_path = dir.join("quad.dae");
+        let gzip_path = dir.join("quad.dae.gz");
+        write_nxf(models(), &plain_path, Nxf2ColladaOptions::default(), false, false).unwrap();
+        write_nxf(models(), &gzip_path, Nxf2ColladaOptions::default(), false, true).unwrap();
+        let plain = fs::read(&plain_path).unwrap();
+        let gzip = fs::read(&gzip_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(&gzip[..2], &[0x1f, 0x8b]);
+        let mut unzipped = Vec::new();
+        GzDecoder::new(&gzip[..]).read_to_end(&mut unzipped).unwrap();
+        assert_eq!(unzipped, plain);
+    }
+
+    #[test]
+    fn nxf_inputs_sharing_a_stem_get_unique_ids() {
+        let dir = env::temp_dir().join(format!("pmw2_collada_stems_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("both.dae");
+        // As from `--nxf a/m.nxf --nxf b/m.nxf`.
+        let models = vec![
+            ("m".to_string(), NxfFixture::quad().expected()),
+            ("m".to_string(), NxfFixture::quad().expected()),
+        ];
+        write_nxf(models, &path, Nxf2ColladaOptions::default(), false, false).unwrap();
+        let dae = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(dae.contains("<geometry id=\"m_geometry\""), "{}", dae);
+        assert!(dae.contains("<geometry id=\"m_2_geometry\""), "{}", dae);
+        let ids: Vec<&str> = dae.split(" id=\"").skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .collect();
+        let unique: HashSet<&str> = ids.iter().cloned().collect();
+        assert_eq!(unique.len(), ids.len(), "{:?}", ids);
+    }
+
+    #[test]
+    fn unique_names_number_repeats() {
+        let names = unique_names(vec!["m".to_string(), "m".to_string(), "n".to_string(), "m".to_string()]);
+        assert_eq!(names, ["m", "m_2", "n", "m_3"]);
+    }
+}
@@ -0,0 +1,40 @@
+/// Longest name, in bytes, that Blender keeps without truncating.
+const BLENDER_MAX_NAME_LEN: usize = 63;
+
+/// Makes a name that Blender's COLLADA importer keeps as is. Blender reads a
+/// trailing ".NNN" as a duplicate suffix and truncates long names, so dots
+/// become underscores and the name is cut to Blender's length limit.
+pub fn blender_name(name: &str) -> String {
+    let mut safe = String::new();
+    for c in name.chars() {
+        let c = if c == '.' { '_' } else { c };
+        if safe.len() + c.len_utf8() > BLENDER_MAX_NAME_LEN {
+            break;
+        }
+        safe.push(c);
+    }
+    safe
+}
+
+/// Makes a name safe to use as part of a file name: anything but ASCII
+/// letters, digits, `-` and `_` becomes an underscore, so path separators,
+/// `..` and characters Windows refuses can't reach the file system.
+pub fn file_name_part(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blender_name_replaces_dots_and_truncates() {
+        assert_eq!(blender_name("foo.tex"), "foo_tex");
+        let long = "é".repeat(40);
+        let safe = blender_name(&long);
+        assert_eq!(safe, "é".repeat(31));
+        assert!(safe.len() <= BLENDER_MAX_NAME_LEN);
+    }
+}
@@ -1,90 +1,569 @@
-use std::io::Write;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{Error as IOError, ErrorKind, Write};
+use std::iter;
+use std::mem::{self, Discriminant};
+use std::path::{Component, Path, PathBuf};
 
-use nxf::{NxfObjGeom, NxfFaces};
-use xml::EmitterConfig;
-use xml::writer::{EventWriter, Error as EmitterError};
+use nxf::{Array, Color, NxfObjGeom, NxfFaces, NxfMaterial, Vec3};
 use xml::writer::events::XmlEvent;
 
+use crate::axes::FlipAxes;
+use crate::document::{ColladaDocument, DocumentOptions, Unit};
+use crate::error::ConvertError;
+use crate::extra::write_pmw2_extra;
+use crate::names::blender_name;
+use crate::progress::ProgressFn;
+use crate::strict::unhandled;
+
+/// Texture extensions tried, after `tex_ext`, when resolving against
+/// `tex_dir`.
+const TEX_DIR_EXTS: &[&str] = &["png", "tga", "bmp", "dds"];
+
+/// Name of the untextured material used by facelists without one.
+const DEFAULT_MATERIAL: &str = "nxf_default";
+
+fn material_name(material: &Option<NxfMaterial>) -> &str {
+    match material {
+        Some(material) => &material.tex_name,
+        None => DEFAULT_MATERIAL,
+    }
+}
+
+/// Splits a model into one model per material, in the order the materials
+/// are first used. Each keeps only the facelists using its material (the
+/// default material's for facelists without one) and only that material.
+pub fn split_by_material(nxf: &NxfObjGeom) -> Vec<(String, NxfObjGeom)> {
+    let mut names: Vec<&str> = Vec::new();
+    for facelist in nxf.facelists() {
+        let name = material_name(&facelist.material);
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names.into_iter()
+        .map(|name| {
+            let mut part = nxf.clone();
+            for facelist_set in part.facelist_sets.iter_mut() {
+                facelist_set.facelists.retain(|facelist| material_name(&facelist.material) == name);
+            }
+            part.facelist_sets.retain(|facelist_set| !facelist_set.facelists.is_empty());
+            part.materials.retain(|material| material.tex_name == name);
+            (name.to_string(), part)
+        })
+        .collect()
+}
+
+/// Index data for one `<triangles>` element, built from one facelist or,
+/// when merging by material, several facelists sharing a material and face
+/// type.
+struct TriangleGroup<'a> {
+    material: &'a Option<NxfMaterial>,
+    kind: Discriminant<NxfFaces>,
+    has_uvs: bool,
+    triangles: Vec<[Corner; 3]>,
+    set_flags: Vec<String>,
+    flags: Vec<String>,
+    attribs: Vec<String>,
+}
+
+/// Suffix for ids belonging to one LOD level's geometry.
+fn lod_suffix(lod: Option<usize>) -> String {
+    match lod {
+        Some(lod) => format!("_lod{}", lod),
+        None => String::new(),
+    }
+}
+
+/// Vertex, color and uv index of one triangle corner. Untextured face types
+/// have no uv index.
+type Corner = (u16, u16, Option<u16>);
+
+/// Reads the corners of each triangle of a supported facelist. Both
+/// supported face types store a color per corner.
+fn face_corners(faces: &NxfFaces) -> Vec<[Corner; 3]> {
+    faces.triangles().iter()
+        .map(|triangle| triangle.corners.map(|corner| (corner.vertex, corner.color.unwrap_or(0), corner.uv)))
+        .collect()
+}
+
+/// Whether two of a triangle's corners share a vertex, leaving it without
+/// area. Strip restarts in the PS2 data leave these behind.
+fn is_degenerate(triangle: &[Corner; 3]) -> bool {
+    triangle[0].0 == triangle[1].0 || triangle[1].0 == triangle[2].0 || triangle[0].0 == triangle[2].0
+}
+
+/// Computes per-vertex tangents and binormals for normal mapping. Each
+/// textured triangle, given as position indices and its corners' uvs, adds
+/// the directions its u and v run in; the sums are then made orthogonal to
+/// the vertex normal, keeping the uv mapping's handedness. Vertices without
+/// a textured triangle get zero vectors.
+fn compute_tangents(positions: &[Vec3], normals: &[Vec3], triangles: &[([usize; 3], [(f32, f32); 3])]) -> (Vec<Vec3>, Vec<Vec3>) {
+    let zero = Vec3::new(0.0, 0.0, 0.0);
+    let mut tangents = vec![zero; positions.len()];
+    let mut binormals = vec![zero; positions.len()];
+    for (tri, uv) in triangles.iter() {
+        if tri.iter().any(|&v| v >= positions.len()) {
+            continue;
+        }
+        let e1 = positions[tri[1]].sub(&positions[tri[0]]);
+        let e2 = positions[tri[2]].sub(&positions[tri[0]]);
+        let (du1, dv1) = (uv[1].0 - uv[0].0, uv[1].1 - uv[0].1);
+        let (du2, dv2) = (uv[2].0 - uv[0].0, uv[2].1 - uv[0].1);
+        let det = du1 * dv2 - du2 * dv1;
+        // Collapsed uvs give no direction.
+        if det == 0.0 {
+            continue;
+        }
+        let t = e1.mul(dv2).sub(&e2.mul(dv1)).mul(1.0 / det);
+        let b = e2.mul(du1).sub(&e1.mul(du2)).mul(1.0 / det);
+        for &v in tri.iter() {
+            tangents[v] = tangents[v].add(&t);
+            binormals[v] = binormals[v].add(&b);
+        }
+    }
+
+    for v in 0..positions.len() {
+        let n = normals[v];
+        let t = tangents[v].sub(&n.mul(n.dot(&tangents[v]))).normalize();
+        let mut b = n.cross(&t);
+        if b.dot(&binormals[v]) < 0.0 {
+            b = b.mul(-1.0);
+        }
+        tangents[v] = t;
+        binormals[v] = b;
+    }
+    (tangents, binormals)
+}
+
+/// Formats a corner's `<p>` index data, one index per input offset.
+fn corner_indices(corner: &Corner, colors: bool) -> String {
+    match (colors, corner.2) {
+        (true, Some(uv)) => format!("{} {} {}", corner.0, corner.1, uv),
+        (false, Some(uv)) => format!("{} {}", corner.0, uv),
+        (true, None) => format!("{} {}", corner.0, corner.1),
+        (false, None) => format!("{}", corner.0),
+    }
+}
+
+/// Key identifying a corner's combined vertex. Without vertex colors the
+/// color index is ignored so it doesn't split otherwise equal vertices.
+fn combined_key(corner: &Corner, colors: bool) -> Corner {
+    if colors {
+        *corner
+    } else {
+        (corner.0, 0, corner.2)
+    }
+}
+
+/// Joins triangles that continue each other, in order, into strips.
+///
+/// Triangle k of a strip is (s[k], s[k + 1], s[k + 2]), with the first two
+/// swapped for odd k to keep the winding. A triangle continues the strip if
+/// some rotation of it matches the next expected pair of corners.
+fn build_strips(triangles: &[[String; 3]]) -> Vec<Vec<&str>> {
+    let mut strips: Vec<Vec<&str>> = Vec::new();
+    let mut triangles = triangles.iter();
+    let mut current: Vec<&str> = Vec::new();
+    while let Some(triangle) = triangles.next() {
+        if current.len() >= 3 {
+            let len = current.len();
+            let (x, y) = (current[len - 2], current[len - 1]);
+            let expected = if (len - 2) % 2 == 0 { (x, y) } else { (y, x) };
+            let next = (0..3)
+                .map(|rot| (&triangle[rot], &triangle[(rot + 1) % 3], &triangle[(rot + 2) % 3]))
+                .find(|&(a, b, _)| (a.as_str(), b.as_str()) == expected);
+            if let Some((_, _, c)) = next {
+                current.push(c);
+                continue;
+            }
+            strips.push(current);
+        }
+        current = vec![&triangle[0], &triangle[1], &triangle[2]];
+    }
+    if current.len() >= 3 {
+        strips.push(current);
+    }
+    strips
+}
+
+/// Transparency given to a material's effect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaTreatment {
+    /// The texture's alpha is ignored.
+    Opaque,
+    /// The texture's alpha blends (`<transparent opaque="A_ONE">`).
+    Blend,
+    /// Like `Blend`, and marked as alpha tested in the PMW2 extra.
+    Cutout,
+}
+
+impl AlphaTreatment {
+    pub fn parse(s: &str) -> Option<AlphaTreatment> {
+        match s {
+            "opaque" => Some(AlphaTreatment::Opaque),
+            "blend" => Some(AlphaTreatment::Blend),
+            "cutout" => Some(AlphaTreatment::Cutout),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Nxf2ColladaOptions {
+    /// Emit the per-vertex color source and COLOR inputs. Some importers
+    /// (Maya, 3ds Max) choke on COLOR inputs on triangles.
+    pub vertex_colors: bool,
+    /// Write vertex alpha as its own single channel source (a second COLOR
+    /// input, set 1) and keep the main color source RGB. The default RGBA
+    /// source suits importers that read all four COLOR params; ones that
+    /// only read RGB drop the alpha unless it's split out.
+    pub split_color_alpha: bool,
+    /// Axes negated when converting positions into COLLADA space.
+    pub flip_axes: FlipAxes,
+    /// Error out on constructs the converter can't handle instead of
+    /// warning and skipping them.
+    pub strict: bool,
+    /// Emit smooth per-vertex normals computed from the triangles when the
+    /// file's normals are missing or all zero.
+    pub recompute_normals: bool,
+    /// Texture names of normal-mapped materials, matched ignoring case.
+    /// Their `<triangles>` get per-vertex tangents and binormals
+    /// (TEXTANGENT/TEXBINORMAL), computed from the positions, uvs and
+    /// normals: the recomputed ones when written, else the file's when it
+    /// has one per vertex, else ones computed from the faces just for this.
+    /// The materials are named here because the material flags can't be
+    /// used: a material has only the one texture, with no field for a bump
+    /// or normal map, and none of the flag bits seen so far has been tied
+    /// to one.
+    pub tangents: Vec<String>,
+    /// Mark every effect as double sided, since the PS2 renders most
+    /// geometry without backface culling. This is only a hint (MAYA and
+    /// GOOGLEEARTH profiles) and importers are free to ignore it; the
+    /// alternative of duplicating every triangle with reversed winding would
+    /// double the mesh and break per-face edits.
+    pub double_sided: bool,
+    /// Alpha treatment used for every material instead of the one implied
+    /// by its `alpha_mode`, for files whose alpha modes are wrong.
+    pub force_alpha: Option<AlphaTreatment>,
+    /// Factor applied to vertex positions. Normals are unaffected.
+    pub scale: f32,
+    /// Length of a game unit declared in the output, leaving the vertex
+    /// data as is. `None` leaves it undeclared.
+    pub unit: Option<Unit>,
+    /// Move the vertices so their bounding box is centered on the origin.
+    /// The offset applied, in output units, is kept in each geometry's
+    /// extra data so it can be undone.
+    pub center: bool,
+    /// Emit one `<triangles>` per material and face type instead of one per
+    /// facelist.
+    pub merge_by_material: bool,
+    /// Prefix every document-level id (other than the already unique
+    /// geometry id) with the model name.
+    pub prefix_ids: bool,
+    /// Write each facelist set as a separate LOD level geometry, with only
+    /// the first instanced by `main_node`. Which set flag (if any) marks LOD
+    /// levels isn't known, so every set is treated as one level in file
+    /// order; the set flags are kept in each triangle list's extra data.
+    pub split_lods: bool,
+    /// Write `<tristrips>` instead of `<triangles>`, joining consecutive
+    /// triangles that continue a strip. The NXF stores plain triangle
+    /// lists, so the strips are reconstructed rather than read.
+    pub tristrips: bool,
+    /// Write `<polylist>` with a `<vcount>` of all 3s instead of
+    /// `<triangles>`, for older importers. `tristrips` takes precedence.
+    pub polylist: bool,
+    /// Leave out triangles with a repeated vertex, which have no area.
+    pub drop_degenerate: bool,
+    /// Index every input with one shared index per corner. Each distinct
+    /// combination of vertex, color and uv indices becomes a vertex of its
+    /// own, with the sources expanded to match.
+    pub single_index: bool,
+    /// Extension of the extracted texture files, without the dot.
+    pub tex_ext: String,
+    /// Directory to look up texture files in. Found files are referenced by
+    /// a URI relative to `out_dir`.
+    pub tex_dir: Option<String>,
+    /// Directory the document is written to, which `tex_dir` textures are
+    /// referenced relative to. `None` is the current directory.
+    pub out_dir: Option<String>,
+    /// Make material ids and names safe from Blender's renaming: no dots and
+    /// at most 63 bytes. The original texture name stays in each material's
+    /// extra data.
+    pub blender_names: bool,
+    /// Write only the geometry and a scene instancing it, without effects,
+    /// images, materials or material bindings. Triangles keep their material
+    /// symbols so they can still be told apart.
+    pub geometry_only: bool,
+    /// Write only the materials (with their effects and images) that some
+    /// facelist uses, dropping unused ones left in the material list.
+    pub used_materials_only: bool,
+    /// Leave the `material` attribute off triangles from facelists without
+    /// a material, instead of binding them to a generated default material.
+    pub no_default_material: bool,
+    /// Encoding named in the XML declaration, e.g. "utf-8" or "UTF-8". Only
+    /// the label changes; the output is always UTF-8.
+    pub xml_encoding: String,
+    /// `standalone` value of the XML declaration, left out when `None`.
+    pub xml_standalone: Option<bool>,
+    /// String used for one level of indentation in the output.
+    pub indent_string: String,
+    /// String written between lines of output.
+    pub line_separator: String,
+}
+
+impl Default for Nxf2ColladaOptions {
+    fn default() -> Nxf2ColladaOptions {
+        Nxf2ColladaOptions {
+            vertex_colors: true,
+            split_color_alpha: false,
+            flip_axes: FlipAxes::default(),
+            strict: false,
+            recompute_normals: false,
+            tangents: Vec::new(),
+            double_sided: false,
+            force_alpha: None,
+            scale: 1.0,
+            unit: None,
+            center: false,
+            merge_by_material: false,
+            prefix_ids: false,
+            split_lods: false,
+            tristrips: false,
+            polylist: false,
+            drop_degenerate: true,
+            single_index: false,
+            tex_ext: "png".to_string(),
+            tex_dir: None,
+            out_dir: None,
+            blender_names: false,
+            geometry_only: false,
+            used_materials_only: false,
+            no_default_material: false,
+            xml_encoding: "utf-8".to_string(),
+            xml_standalone: None,
+            indent_string: "  ".to_string(),
+            line_separator: "\n".to_string(),
+        }
+    }
+}
+
 pub struct Nxf2Collada<W> {
     name: String,
-    writer: EventWriter<W>,
+    writer: ColladaDocument<W>,
     nxf: NxfObjGeom,
+    /// Models after the current one, when converting several into one
+    /// document. `next_model` cycles through them.
+    more_models: VecDeque<(String, NxfObjGeom)>,
+    options: Nxf2ColladaOptions,
+    progress: Option<ProgressFn>,
+    /// `tex_dir`'s absolute path and file names, listed once when the
+    /// document is written.
+    tex_dir_listing: Option<(PathBuf, Vec<String>)>,
+    /// `out_dir`'s absolute path, resolved along with `tex_dir_listing`.
+    out_dir: PathBuf,
+}
+
+/// Lists a texture directory, returning its absolute path and the names of
+/// the files in it.
+fn list_tex_dir(tex_dir: &str) -> Result<(PathBuf, Vec<String>), IOError> {
+    let context = |err: IOError| IOError::new(err.kind(), format!("could not list texture directory {}: {}", tex_dir, err));
+    let path = fs::canonicalize(tex_dir).map_err(context)?;
+    let mut file_names = Vec::new();
+    for entry in fs::read_dir(&path).map_err(context)? {
+        file_names.push(entry.map_err(context)?.file_name().to_string_lossy().into_owned());
+    }
+    Ok((path, file_names))
+}
+
+/// Makes a `file://` URI for an absolute path, percent-encoding everything
+/// but unreserved characters and separators.
+fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    // Windows' canonical paths are verbatim (`\\?\C:\...`) and need a
+    // leading slash before the drive.
+    let path = path.trim_start_matches("//?/");
+    let prefix = if path.starts_with('/') { "file://" } else { "file:///" };
+    // The drive's colon is fine after the scheme.
+    prefix.to_string() + &percent_encode(path).replace("%3A", ":")
+}
+
+/// Percent-encodes a path with `/` separators for use in a URI, keeping
+/// only unreserved characters and separators as they are. Colons are
+/// encoded too, so a relative path can't be read as having a scheme.
+fn percent_encode(path: &str) -> String {
+    let mut uri = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// The path from directory `from` to `to`, both absolute, or `None` when
+/// there is none (e.g. they're on different Windows drives).
+fn relative_path(from: &Path, to: &Path) -> Option<PathBuf> {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    // Paths starting from different roots or drives have nothing in common.
+    if from.first() != to.first() {
+        return None;
+    }
+    let common = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in to[common..].iter() {
+        path.push(component);
+    }
+    Some(path)
 }
 
 impl<W> Nxf2Collada<W>
     where W: Write,
 {
-    pub fn new(name: String, nxf: NxfObjGeom, write: W) -> Nxf2Collada<W> {
+    pub fn new(name: String, nxf: NxfObjGeom, write: W, options: Nxf2ColladaOptions) -> Nxf2Collada<W> {
         Nxf2Collada {
             name: name,
-            writer: EventWriter::new_with_config(write, EmitterConfig::new().perform_indent(true)),
+            writer: ColladaDocument::new(write, DocumentOptions {
+                xml_encoding: &options.xml_encoding,
+                xml_standalone: options.xml_standalone,
+                scale: options.scale,
+                unit: options.unit,
+                indent_string: &options.indent_string,
+                line_separator: &options.line_separator,
+            }),
             nxf: nxf,
+            more_models: VecDeque::new(),
+            options: options,
+            progress: None,
+            tex_dir_listing: None,
+            out_dir: PathBuf::new(),
         }
     }
 
-    pub fn write_collada(&mut self) -> Result<(), EmitterError> {
-        self.write_start()?;
-        self.write_library_effects()?;
-        self.write_library_images()?;
-        self.write_library_materials()?;
-        self.write_library_geometries()?;
-        self.write_library_nodes()?;
+    /// Adds another model to write into the same document, with its own
+    /// geometry and node. Ids should be prefixed (`prefix_ids`) so the
+    /// models' ids don't collide.
+    pub fn add_model(&mut self, name: String, nxf: NxfObjGeom) {
+        self.more_models.push_back((name, nxf));
+    }
+
+    /// Gives back the writer, e.g. to finish a compressed stream once the
+    /// document is written.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    /// Reports progress once per facelist while writing geometry.
+    pub fn set_progress(&mut self, progress: ProgressFn) {
+        self.progress = Some(progress);
+    }
+
+    pub fn write_collada(&mut self) -> Result<(), ConvertError> {
+        if let Some(tex_dir) = &self.options.tex_dir {
+            self.tex_dir_listing = Some(list_tex_dir(tex_dir)?);
+            let out_dir = self.options.out_dir.as_deref().unwrap_or(".");
+            self.out_dir = fs::canonicalize(out_dir)
+                .map_err(|err| IOError::new(err.kind(), format!("could not find output directory {}: {}", out_dir, err)))?;
+        }
+        self.writer.write_start()?;
+        // Empty libraries are left out, since some validators reject them.
+        let mut has_images = false;
+        let mut has_materials = false;
+        for _ in 0..self.model_count() {
+            if self.options.geometry_only {
+                break;
+            }
+            let has_written = self.nxf.materials.iter().any(|material| self.is_material_written(material));
+            has_images |= has_written;
+            has_materials |= has_written || self.writes_default_material();
+            self.next_model();
+        }
+        if has_materials {
+            self.write_library("library_effects", Self::write_effects)?;
+        }
+        if has_images {
+            self.write_library("library_images", Self::write_images)?;
+        }
+        if has_materials {
+            self.write_library("library_materials", Self::write_materials)?;
+        }
+        self.write_library("library_geometries", Self::write_geometries)?;
+        self.write_library("library_nodes", Self::write_nodes)?;
         self.write_library_visual_scenes()?;
         self.write_scene()?;
-        self.write_end()
+        self.writer.write_end()?;
+        Ok(())
     }
 
-    fn write_start(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(
-            XmlEvent::start_element("COLLADA")
-                .attr("xmlns", "http://www.collada.org/2005/11/COLLADASchema")
-                .attr("version", "1.4.1")
-        )?;
-        self.writer.write(XmlEvent::start_element("asset"))?;
-        self.writer.write(XmlEvent::start_element("created"))?;
-        self.writer.write("2020-04-18T17:41:28")?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::start_element("modified"))?;
-        self.writer.write("2020-04-18T17:41:28")?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())
+    /// Writes a library element holding what `write` writes for each model.
+    fn write_library(&mut self, element: &str, write: fn(&mut Self) -> Result<(), ConvertError>) -> Result<(), ConvertError> {
+        self.writer.start_library(element)?;
+        for _ in 0..self.model_count() {
+            write(self)?;
+            self.next_model();
+        }
+        self.writer.end_library()?;
+        Ok(())
     }
 
-    fn write_library_effects(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::start_element("library_effects"))?;
+    fn model_count(&self) -> usize {
+        self.more_models.len() + 1
+    }
+
+    /// Makes the next model current, cycling back to the first after the
+    /// last one.
+    fn next_model(&mut self) {
+        if let Some((name, nxf)) = self.more_models.pop_front() {
+            let name = mem::replace(&mut self.name, name);
+            let nxf = mem::replace(&mut self.nxf, nxf);
+            self.more_models.push_back((name, nxf));
+        }
+    }
 
+    fn write_effects(&mut self) -> Result<(), ConvertError> {
         for material in self.nxf.materials.iter() {
+            if !self.is_material_written(material) {
+                continue;
+            }
+            let env_mapped = self.is_env_mapped(material);
+
             self.writer.write(
                 XmlEvent::start_element("effect")
-                    .attr("id", &(material.tex_name.clone() + "_effect"))
+                    .attr("id", self.id(&(self.material_base(material) + "_effect")).as_str())
             )?;
             self.writer.write(XmlEvent::start_element("profile_COMMON"))?;
             self.writer.write(
                 XmlEvent::start_element("technique")
-                    .attr("sid", &(material.tex_name.clone() + "_technique"))
+                    .attr("sid", &(self.material_base(material) + "_technique"))
             )?;
 
             self.writer.write(
                 XmlEvent::start_element("newparam")
-                    .attr("sid", &(material.tex_name.clone() + "_surface"))
+                    .attr("sid", &(self.material_base(material) + "_surface"))
             )?;
             self.writer.write(
                 XmlEvent::start_element("surface")
                     .attr("type", "2D")
             )?;
             self.writer.write(XmlEvent::start_element("init_from"))?;
-            self.writer.write((material.tex_name.clone() + "_image").as_str())?;
+            self.writer.write(self.id(&(self.material_base(material) + "_image")).as_str())?;
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
 
             self.writer.write(
                 XmlEvent::start_element("newparam")
-                    .attr("sid", &(material.tex_name.clone() + "_sampler"))
+                    .attr("sid", &(self.material_base(material) + "_sampler"))
             )?;
             self.writer.write(XmlEvent::start_element("sampler2D"))?;
             self.writer.write(XmlEvent::start_element("source"))?;
-            self.writer.write((material.tex_name.clone() + "_surface").as_str())?;
+            self.writer.write((self.material_base(material) + "_surface").as_str())?;
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
@@ -93,339 +572,916 @@ impl<W> Nxf2Collada<W>
             self.writer.write(XmlEvent::start_element("diffuse"))?;
             self.writer.write(
                 XmlEvent::start_element("texture")
-                    .attr("texture", &(material.tex_name.clone() + "_sampler"))
+                    .attr("texture", &(self.material_base(material) + "_sampler"))
                     .attr("texcoord", "nxf_uvs")
             )?;
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
+
+            // Only the reflection's tint and strength are written, as a
+            // `<reflective>` color: the reflection texture is only known by
+            // its `ref_pmi`/`ref_map` indices, with no file name to point a
+            // `<texture>` at, so those are kept in the material's extra
+            // instead. The tint is the material's ref color. The meaning of
+            // env_map_alpha_mode isn't fully known: 0 appears to be an
+            // opaque reflection, anything else is treated as blending the
+            // reflection by the ref color's alpha.
+            if env_mapped {
+                self.writer.write(XmlEvent::start_element("reflective"))?;
+                self.writer.write(XmlEvent::start_element("color"))?;
+                self.writer.write(format!("{} {} {} {}",
+                    material.ref_r as f32 / 255.0,
+                    material.ref_g as f32 / 255.0,
+                    material.ref_b as f32 / 255.0,
+                    material.ref_a as f32 / 255.0,
+                ).as_str())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::end_element())?;
+
+                let reflectivity = if material.env_map_alpha_mode == 0 {
+                    1.0
+                } else {
+                    material.ref_a as f32 / 255.0
+                };
+                self.writer.write(XmlEvent::start_element("reflectivity"))?;
+                self.writer.write(XmlEvent::start_element("float"))?;
+                self.writer.write(reflectivity.to_string().as_str())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::end_element())?;
+            }
+
+            // Cutout textures use the texture's alpha as a mask. COLLADA has
+            // no alpha test, so the PMW2 extra below marks it as one.
+            let alpha = self.alpha_treatment(material);
+            if alpha != AlphaTreatment::Opaque {
+                self.writer.write(
+                    XmlEvent::start_element("transparent")
+                        .attr("opaque", "A_ONE")
+                )?;
+                self.writer.write(
+                    XmlEvent::start_element("texture")
+                        .attr("texture", &(self.material_base(material) + "_sampler"))
+                        .attr("texcoord", "nxf_uvs")
+                )?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::end_element())?;
+            }
+
             self.writer.write(XmlEvent::end_element())?;
 
             self.writer.write(XmlEvent::end_element())?;
+            if self.options.double_sided {
+                self.writer.write(XmlEvent::start_element("extra"))?;
+                for profile in ["MAYA", "GOOGLEEARTH"].iter() {
+                    self.writer.write(
+                        XmlEvent::start_element("technique")
+                            .attr("profile", profile)
+                    )?;
+                    self.writer.write(XmlEvent::start_element("double_sided"))?;
+                    self.writer.write("1")?;
+                    self.writer.write(XmlEvent::end_element())?;
+                    self.writer.write(XmlEvent::end_element())?;
+                }
+                self.writer.write(XmlEvent::end_element())?;
+            }
+            if alpha == AlphaTreatment::Cutout {
+                write_pmw2_extra(&mut self.writer, &[
+                    ("alpha_test", "1".to_string()),
+                ])?;
+            }
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
         }
 
-        self.writer.write(XmlEvent::end_element())
-    }
+        if self.writes_default_material() {
+            self.writer.write(
+                XmlEvent::start_element("effect")
+                    .attr("id", self.id(&(DEFAULT_MATERIAL.to_string() + "_effect")).as_str())
+            )?;
+            self.writer.write(XmlEvent::start_element("profile_COMMON"))?;
+            self.writer.write(
+                XmlEvent::start_element("technique")
+                    .attr("sid", &(DEFAULT_MATERIAL.to_string() + "_technique"))
+            )?;
+            self.writer.write(XmlEvent::start_element("lambert"))?;
+            self.writer.write(XmlEvent::start_element("diffuse"))?;
+            self.writer.write(XmlEvent::start_element("color"))?;
+            self.writer.write("0.8 0.8 0.8 1")?;
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+        }
 
-    fn write_library_images(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::start_element("library_images"))?;
+        Ok(())
+    }
 
+    fn write_images(&mut self) -> Result<(), ConvertError> {
         for material in self.nxf.materials.iter() {
+            if !self.is_material_written(material) {
+                continue;
+            }
             self.writer.write(
                 XmlEvent::start_element("image")
-                    .attr("id", &(material.tex_name.clone() + "_image"))
+                    .attr("id", self.id(&(self.material_base(material) + "_image")).as_str())
             )?;
             self.writer.write(XmlEvent::start_element("init_from"))?;
-            self.writer.write((material.tex_name.clone() + ".png").as_str())?;
+            let image_path = self.image_path(material)?;
+            self.writer.write(image_path.as_str())?;
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
         }
 
-        self.writer.write(XmlEvent::end_element())
+        Ok(())
     }
 
-    fn write_library_materials(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::start_element("library_materials"))?;
-
+    fn write_materials(&mut self) -> Result<(), ConvertError> {
         for material in self.nxf.materials.iter() {
+            if !self.is_material_written(material) {
+                continue;
+            }
+            let base = self.material_base(material);
+            let id = self.id(&(base.clone() + "_material"));
+            let mut element = XmlEvent::start_element("material")
+                .attr("id", id.as_str());
+            if self.options.blender_names {
+                element = element.attr("name", base.as_str());
+            }
+            self.writer.write(element)?;
+            self.writer.write(
+                XmlEvent::start_element("instance_effect")
+                    .attr("url", self.url(&(self.material_base(material) + "_effect")).as_str())
+            )?;
+            self.writer.write(XmlEvent::end_element())?;
+            let mut extra = vec![
+                ("tex_pmi", material.tex_pmi.to_string()),
+                ("ref_pmi", material.ref_pmi.to_string()),
+                ("ref_map", material.ref_map.to_string()),
+                ("flags", material.flags.to_string()),
+                ("alpha_mode", material.alpha_mode.to_string()),
+                ("env_map_alpha_mode", material.env_map_alpha_mode.to_string()),
+            ];
+            // The Blender safe name can't always be mapped back.
+            if self.options.blender_names {
+                extra.push(("tex_name", material.tex_name.clone()));
+            }
+            write_pmw2_extra(&mut self.writer, &extra)?;
+            self.writer.write(XmlEvent::end_element())?;
+        }
+
+        if self.writes_default_material() {
             self.writer.write(
                 XmlEvent::start_element("material")
-                    .attr("id", &(material.tex_name.clone() + "_material"))
+                    .attr("id", self.id(&(DEFAULT_MATERIAL.to_string() + "_material")).as_str())
             )?;
             self.writer.write(
                 XmlEvent::start_element("instance_effect")
-                    .attr("url", (String::from("#") + &material.tex_name + "_effect").as_str())
+                    .attr("url", self.url(&(DEFAULT_MATERIAL.to_string() + "_effect")).as_str())
             )?;
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
         }
 
-        self.writer.write(XmlEvent::end_element())
+        Ok(())
     }
 
-    fn write_library_geometries(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::start_element("library_geometries"))?;
-        self.writer.write(
-            XmlEvent::start_element("geometry")
-                .attr("id", (self.name.clone() + "_geometry").as_str())
-                .attr("name", (self.name.clone() + "_geometry").as_str())
-        )?;
-        self.writer.write(XmlEvent::start_element("mesh"))?;
+    fn write_geometries(&mut self) -> Result<(), ConvertError> {
+        if self.options.split_lods {
+            for set_idx in 0..self.nxf.facelist_sets.len() {
+                self.write_geometry(Some(set_idx))?;
+            }
+        } else {
+            self.write_geometry(None)?;
+        }
+        Ok(())
+    }
 
-        // vertex source
-        self.writer.write(
-            XmlEvent::start_element("source")
-                .attr("id", "vertex_source")
-        )?;
+    /// Writes the geometry for every facelist set, or for a single set when
+    /// `lod` is given.
+    fn write_geometry(&mut self, lod: Option<usize>) -> Result<(), ConvertError> {
+        let suffix = lod_suffix(lod);
+        let total_facelists = self.nxf.facelists().count();
+        let mut groups: Vec<TriangleGroup> = Vec::new();
+        let mut facelist_idx = 0;
+        for (set_idx, facelist_set) in self.nxf.facelist_sets.iter().enumerate() {
+            if lod.map_or(false, |lod| lod != set_idx) {
+                facelist_idx += facelist_set.facelists.len();
+                continue;
+            }
 
-        self.writer.write(
-            XmlEvent::start_element("float_array")
-                .attr("id", "vertex_array")
-                .attr("count", (self.nxf.arrays.verts.len() * 3).to_string().as_str())
-        )?;
-        let mut vertex_data = String::new();
-        for vertex in self.nxf.arrays.verts.iter() {
-            vertex_data += &format!("{} {} {} ", vertex.x, -vertex.y, -vertex.z);
+            // The palette's transforms aren't known, so its parts stay
+            // where their vertices put them.
+            if let Some(mat_palette) = &facelist_set.mat_palette {
+                let msg = format!("facelist set {} has a matrix palette at {:#x}, which is ignored",
+                    set_idx, mat_palette.offset);
+                unhandled(self.options.strict, &msg)?;
+            }
+
+            for facelist in facelist_set.facelists.iter() {
+                facelist_idx += 1;
+                if let Some(progress) = self.progress.as_mut() {
+                    progress(facelist_idx, total_facelists, material_name(&facelist.material));
+                }
+
+                match facelist.faces {
+                    NxfFaces::TexUnlitTri(_) | NxfFaces::ColUnlitTri(_) => {}
+                    NxfFaces::Unknown(facelist_type) => {
+                        let msg = format!("skipping facelist with unknown face type {}", facelist_type);
+                        unhandled(self.options.strict, &msg)?;
+                        continue;
+                    }
+                    _ => {
+                        let msg = format!("skipping {} facelist, which isn't implemented", facelist.faces.type_name());
+                        unhandled(self.options.strict, &msg)?;
+                        continue;
+                    }
+                }
+
+                let kind = mem::discriminant(&facelist.faces);
+                let existing = if self.options.merge_by_material {
+                    groups.iter().position(|group| group.kind == kind && *group.material == facelist.material)
+                } else {
+                    None
+                };
+                let group_idx = match existing {
+                    Some(group_idx) => group_idx,
+                    None => {
+                        groups.push(TriangleGroup {
+                            material: &facelist.material,
+                            kind: kind,
+                            has_uvs: facelist.faces.has_uvs(),
+                            triangles: Vec::new(),
+                            set_flags: Vec::new(),
+                            flags: Vec::new(),
+                            attribs: Vec::new(),
+                        });
+                        groups.len() - 1
+                    }
+                };
+                let drop_degenerate = self.options.drop_degenerate;
+                let group = &mut groups[group_idx];
+                group.triangles.extend(
+                    face_corners(&facelist.faces)
+                        .into_iter()
+                        .filter(|triangle| !(drop_degenerate && is_degenerate(triangle)))
+                );
+                group.set_flags.push(facelist_set.flags.to_string());
+                group.flags.push(facelist.flags.to_string());
+                group.attribs.push(facelist.attribs.to_string());
+            }
         }
-        self.writer.write(vertex_data.as_str())?;
-        self.writer.write(XmlEvent::end_element())?;
 
-        self.writer.write(XmlEvent::start_element("technique_common"))?;
-        self.writer.write(
-            XmlEvent::start_element("accessor")
-                .attr("source", "#vertex_array")
-                .attr("count", (self.nxf.arrays.verts.len()).to_string().as_str())
-                .attr("stride", "3")
-        )?;
-        self.writer.write(
-            XmlEvent::start_element("param")
-                .attr("name", "X")
-                .attr("type", "float")
-        )?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(
-            XmlEvent::start_element("param")
-                .attr("name", "Y")
-                .attr("type", "float")
-        )?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(
-            XmlEvent::start_element("param")
-                .attr("name", "Z")
-                .attr("type", "float")
-        )?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())?;
+        let nxf = &self.nxf;
+        let lod_faces = || nxf.facelists_with_set()
+            .filter(move |&(set_idx, _)| lod.map_or(true, |lod| lod == set_idx))
+            .map(|(_, facelist)| &facelist.faces);
+        let recomputed_normals = if self.options.recompute_normals && self.nxf.arrays.normals_degenerate() {
+            Some(self.nxf.arrays.recompute_normals(lod_faces()))
+        } else {
+            None
+        };
 
-        self.writer.write(XmlEvent::end_element())?;
+        // Facelists can reference uvs even when the uv array is empty. In
+        // that case use zeroes so TEXCOORD inputs don't point at a missing
+        // source.
+        let nxf_uvs = &self.nxf.arrays.uvs;
+        let uv_at = |uv: u16| nxf_uvs.get(uv as usize)
+            .map_or((0.0, 0.0), |uv| (uv.u, uv.v));
 
-        // color source
-        self.writer.write(
-            XmlEvent::start_element("source")
-                .attr("id", "color_source")
-        )?;
+        // With a single index every distinct corner becomes a vertex of its
+        // own, and the sources hold one entry per combined vertex.
+        let mut combined: Vec<Corner> = Vec::new();
+        let mut combined_indices: HashMap<Corner, usize> = HashMap::new();
+        if self.options.single_index {
+            let colors = self.options.vertex_colors;
+            for group in groups.iter() {
+                for corner in group.triangles.iter().flat_map(|triangle| triangle.iter()) {
+                    let key = combined_key(corner, colors);
+                    combined_indices.entry(key).or_insert_with(|| {
+                        combined.push(key);
+                        combined.len() - 1
+                    });
+                }
+            }
+        }
+        let (positions, vertex_colors, uvs, normals): (Vec<Vec3>, Vec<Color>, Vec<_>, _) = if self.options.single_index {
+            let arrays = &self.nxf.arrays;
+            let uvs = if combined.iter().any(|corner| corner.2.is_some()) {
+                combined.iter().map(|corner| uv_at(corner.2.unwrap_or(0))).collect()
+            } else {
+                Vec::new()
+            };
+            let index_error = |array: Array, index: u16, len: usize| ConvertError::Nxf(IOError::new(
+                ErrorKind::InvalidData,
+                format!("{} index {} out of range (length {})", array, index, len),
+            ));
+            let mut positions = Vec::new();
+            let mut colors = Vec::new();
+            for corner in combined.iter() {
+                let position = arrays.verts.get(corner.0 as usize)
+                    .ok_or_else(|| index_error(Array::Vertex, corner.0, arrays.verts.len()))?;
+                positions.push(*position);
+                // Like uvs, a file can leave out colors its faces index, so
+                // an empty array gives white instead of an error.
+                if self.options.vertex_colors {
+                    let color = if arrays.colors.is_empty() {
+                        Color { r: 0xff, g: 0xff, b: 0xff, a: 0xff }
+                    } else {
+                        *arrays.colors.get(corner.1 as usize)
+                            .ok_or_else(|| index_error(Array::Color, corner.1, arrays.colors.len()))?
+                    };
+                    colors.push(color);
+                }
+            }
+            (
+                positions,
+                colors,
+                uvs,
+                // Recomputed normals have one entry per vertex, and every
+                // vertex index was checked above.
+                recomputed_normals.map(|normals| combined.iter().map(|corner| normals[corner.0 as usize]).collect()),
+            )
+        } else {
+            let uv_count = if !self.nxf.arrays.uvs.is_empty() {
+                self.nxf.arrays.uvs.len()
+            } else {
+                self.referenced_uv_count()
+            };
+            // As with single indices, missing colors are white, with enough
+            // of them for every color index the triangles use.
+            let colors = if !self.nxf.arrays.colors.is_empty() {
+                self.nxf.arrays.colors.clone()
+            } else {
+                let color_count = groups.iter()
+                    .flat_map(|group| group.triangles.iter().flat_map(|triangle| triangle.iter()))
+                    .map(|corner| corner.1 as usize + 1)
+                    .max()
+                    .unwrap_or(0);
+                vec![Color { r: 0xff, g: 0xff, b: 0xff, a: 0xff }; color_count]
+            };
+            (
+                self.nxf.arrays.verts.clone(),
+                colors,
+                (0..uv_count).map(|uv| uv_at(uv as u16)).collect(),
+                recomputed_normals,
+            )
+        };
 
         self.writer.write(
-            XmlEvent::start_element("float_array")
-                .attr("id", "color_array")
-                .attr("count", (self.nxf.arrays.colors.len() * 4).to_string().as_str())
+            XmlEvent::start_element("geometry")
+                .attr("id", self.geometry_id(lod).as_str())
+                .attr("name", self.geometry_id(lod).as_str())
         )?;
-        let mut color_data = String::new();
-        for color in self.nxf.arrays.colors.iter() {
-            color_data += &format!("{} {} {} {} ",
-                color.r as f32 / 255.0,
-                color.g as f32 / 255.0,
-                color.b as f32 / 255.0,
-                color.a as f32 / 255.0
-            );
-        }
-        self.writer.write(color_data.as_str())?;
-        self.writer.write(XmlEvent::end_element())?;
+        self.writer.write(XmlEvent::start_element("mesh"))?;
 
-        self.writer.write(XmlEvent::start_element("technique_common"))?;
-        self.writer.write(
-            XmlEvent::start_element("accessor")
-                .attr("source", "#color_array")
-                .attr("count", (self.nxf.arrays.colors.len()).to_string().as_str())
-                .attr("stride", "4")
-        )?;
-        self.writer.write(
-            XmlEvent::start_element("param")
-                .attr("name", "R")
-                .attr("type", "float")
-        )?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(
-            XmlEvent::start_element("param")
-                .attr("name", "G")
-                .attr("type", "float")
-        )?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(
-            XmlEvent::start_element("param")
-                .attr("name", "B")
-                .attr("type", "float")
-        )?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(
-            XmlEvent::start_element("param")
-                .attr("name", "A")
-                .attr("type", "float")
+        // vertex source
+        let center = self.center();
+        let scale = self.options.scale;
+        let mut vertex_data = Vec::new();
+        for vertex in positions.iter() {
+            let (x, y, z) = (vertex.x - center.x, vertex.y - center.y, vertex.z - center.z);
+            let (x, y, z) = self.options.flip_axes.apply((x * scale, y * scale, z * scale));
+            vertex_data.extend_from_slice(&[x, y, z]);
+        }
+        self.writer.write_float_source(
+            &self.id(&format!("vertex_source{}", suffix)),
+            &self.id(&format!("vertex_array{}", suffix)),
+            &vertex_data,
+            &["X", "Y", "Z"],
         )?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())?;
-
-        self.writer.write(XmlEvent::end_element())?;
 
-        // uv source
-        if self.nxf.arrays.uvs.len() != 0 {
-            self.writer.write(
-                XmlEvent::start_element("source")
-                    .attr("id", "uv_source")
+        // color source
+        let split_alpha = self.options.split_color_alpha;
+        if self.options.vertex_colors {
+            let mut color_data = Vec::new();
+            for color in vertex_colors.iter() {
+                let [r, g, b, a] = color.to_f32_array();
+                if split_alpha {
+                    color_data.extend_from_slice(&[r, g, b]);
+                } else {
+                    color_data.extend_from_slice(&[r, g, b, a]);
+                }
+            }
+            let params: &[&str] = if split_alpha { &["R", "G", "B"] } else { &["R", "G", "B", "A"] };
+            self.writer.write_float_source(
+                &self.id(&format!("color_source{}", suffix)),
+                &self.id(&format!("color_array{}", suffix)),
+                &color_data,
+                params,
             )?;
+        }
 
-            self.writer.write(
-                XmlEvent::start_element("float_array")
-                    .attr("id", "uv_array")
-                    .attr("count", (self.nxf.arrays.uvs.len() * 2).to_string().as_str())
+        // alpha source
+        if self.options.vertex_colors && split_alpha {
+            let alpha_data: Vec<f32> = vertex_colors.iter()
+                .map(|color| color.to_f32_array()[3])
+                .collect();
+            self.writer.write_float_source(
+                &self.id(&format!("alpha_source{}", suffix)),
+                &self.id(&format!("alpha_array{}", suffix)),
+                &alpha_data,
+                &["A"],
             )?;
-            let mut uv_data = String::new();
-            for uv in self.nxf.arrays.uvs.iter() {
-                uv_data += &format!("{} {} ", uv.u, 1.0 - uv.v);
-            }
-            self.writer.write(uv_data.as_str())?;
-            self.writer.write(XmlEvent::end_element())?;
+        }
 
-            self.writer.write(XmlEvent::start_element("technique_common"))?;
-            self.writer.write(
-                XmlEvent::start_element("accessor")
-                    .attr("source", "#uv_array")
-                    .attr("count", (self.nxf.arrays.uvs.len()).to_string().as_str())
-                    .attr("stride", "2")
-            )?;
-            self.writer.write(
-                XmlEvent::start_element("param")
-                    .attr("name", "S")
-                    .attr("type", "float")
-            )?;
-            self.writer.write(XmlEvent::end_element())?;
-            self.writer.write(
-                XmlEvent::start_element("param")
-                    .attr("name", "T")
-                    .attr("type", "float")
+        // uv source
+        if !uvs.is_empty() {
+            let mut uv_data = Vec::new();
+            for (u, v) in uvs.iter() {
+                uv_data.extend_from_slice(&[*u, 1.0 - v]);
+            }
+            self.writer.write_float_source(
+                &self.id(&format!("uv_source{}", suffix)),
+                &self.id(&format!("uv_array{}", suffix)),
+                &uv_data,
+                &["S", "T"],
             )?;
-            self.writer.write(XmlEvent::end_element())?;
-            self.writer.write(XmlEvent::end_element())?;
-            self.writer.write(XmlEvent::end_element())?;
+        }
 
-            self.writer.write(XmlEvent::end_element())?;
+        // normal source
+        // TODO: Stored normals (once the lit face types are implemented)
+        if let Some(normals) = &normals {
+            let mut normal_data = Vec::new();
+            for normal in normals.iter() {
+                let (x, y, z) = self.options.flip_axes.apply((normal.x, normal.y, normal.z));
+                normal_data.extend_from_slice(&[x, y, z]);
+            }
+            self.writer.write_float_source(
+                &self.id(&format!("normal_source{}", suffix)),
+                &self.id(&format!("normal_array{}", suffix)),
+                &normal_data,
+                &["X", "Y", "Z"],
+            )?;
         }
 
-        // TODO: Normals
+        // tangent and binormal sources, computed in output space so axis
+        // flips and the flipped v are already accounted for
+        let wants_tangents = groups.iter().any(|group| self.wants_tangents(group));
+        let tangents = if wants_tangents {
+            // Without written normals, the file's are used when there's one
+            // per vertex (the converted face types have no normal index),
+            // else ones computed from the faces.
+            let own_normals;
+            let normals: &[Vec3] = match &normals {
+                Some(normals) => normals,
+                None => {
+                    let arrays = &self.nxf.arrays;
+                    let per_vertex = if arrays.normals.len() == arrays.verts.len() && !arrays.normals_degenerate() {
+                        arrays.normals.iter().map(|n| n.normalize()).collect()
+                    } else {
+                        arrays.recompute_normals(lod_faces())
+                    };
+                    // Every combined vertex's index was checked against the
+                    // vertex array, which `per_vertex` matches.
+                    own_normals = if self.options.single_index {
+                        combined.iter().map(|corner| per_vertex[corner.0 as usize]).collect()
+                    } else {
+                        per_vertex
+                    };
+                    &own_normals
+                }
+            };
+            let as_vec3 = |data: &[f32]| -> Vec<Vec3> {
+                data.chunks(3).map(|v| Vec3::new(v[0], v[1], v[2])).collect()
+            };
+            let out_normals: Vec<Vec3> = normals.iter()
+                .map(|n| {
+                    let (x, y, z) = self.options.flip_axes.apply((n.x, n.y, n.z));
+                    Vec3::new(x, y, z)
+                })
+                .collect();
+            let colors = self.options.vertex_colors;
+            let mut textured = Vec::new();
+            for group in groups.iter().filter(|group| self.wants_tangents(group)) {
+                for triangle in group.triangles.iter() {
+                    let mut tri = [0; 3];
+                    let mut tri_uvs = [(0.0, 0.0); 3];
+                    for (i, corner) in triangle.iter().enumerate() {
+                        let (index, (u, v)) = if self.options.single_index {
+                            let index = combined_indices[&combined_key(corner, colors)];
+                            (index, uvs[index])
+                        } else {
+                            (corner.0 as usize, uv_at(corner.2.unwrap_or(0)))
+                        };
+                        tri[i] = index;
+                        tri_uvs[i] = (u, 1.0 - v);
+                    }
+                    textured.push((tri, tri_uvs));
+                }
+            }
+            Some(compute_tangents(&as_vec3(&vertex_data), &out_normals, &textured))
+        } else {
+            None
+        };
+        if let Some((tangents, binormals)) = &tangents {
+            for (name, vectors) in [("tangent", tangents), ("binormal", binormals)].iter() {
+                let mut data = Vec::new();
+                for vector in vectors.iter() {
+                    data.extend_from_slice(&[vector.x, vector.y, vector.z]);
+                }
+                self.writer.write_float_source(
+                    &self.id(&format!("{}_source{}", name, suffix)),
+                    &self.id(&format!("{}_array{}", name, suffix)),
+                    &data,
+                    &["X", "Y", "Z"],
+                )?;
+            }
+        }
 
         self.writer.write(
             XmlEvent::start_element("vertices")
-                .attr("id", "vertices")
+                .attr("id", self.id(&format!("vertices{}", suffix)).as_str())
         )?;
         self.writer.write(
             XmlEvent::start_element("input")
                 .attr("semantic", "POSITION")
-                .attr("source", "#vertex_source")
+                .attr("source", self.url(&format!("vertex_source{}", suffix)).as_str())
         )?;
         self.writer.write(XmlEvent::end_element())?;
+        // Recomputed normals are per position, so they share its index.
+        if normals.is_some() {
+            self.writer.write(
+                XmlEvent::start_element("input")
+                    .attr("semantic", "NORMAL")
+                    .attr("source", self.url(&format!("normal_source{}", suffix)).as_str())
+            )?;
+            self.writer.write(XmlEvent::end_element())?;
+        }
         self.writer.write(XmlEvent::end_element())?;
 
-        for facelist_set in self.nxf.facelist_sets.iter() {
-            for facelist in facelist_set.facelists.iter() {
-                self.writer.write(
-                    XmlEvent::start_element("triangles")
-                        .attr("count", facelist.faces.len().to_string().as_str())
-                        .attr("material", (facelist.material.tex_name.clone() + "_symbol").as_str())
-                )?;
+        let colors = self.options.vertex_colors;
+        for group in groups.iter() {
+            let triangles: Vec<[String; 3]> = group.triangles.iter()
+                .map(|triangle| {
+                    let indices = |corner: &Corner| if self.options.single_index {
+                        combined_indices[&combined_key(corner, colors)].to_string()
+                    } else {
+                        corner_indices(corner, colors)
+                    };
+                    [indices(&triangle[0]), indices(&triangle[1]), indices(&triangle[2])]
+                })
+                .collect();
+            let strips = if self.options.tristrips {
+                Some(build_strips(&triangles))
+            } else {
+                None
+            };
+            let (element, count) = match &strips {
+                Some(strips) => ("tristrips", strips.len()),
+                None if self.options.polylist => ("polylist", triangles.len()),
+                None => ("triangles", triangles.len()),
+            };
+            let count = count.to_string();
+            let symbol = self.material_label(group.material) + "_symbol";
+            let mut start = XmlEvent::start_element(element)
+                .attr("count", count.as_str());
+            if group.material.is_some() || !self.options.no_default_material {
+                start = start.attr("material", symbol.as_str());
+            }
+            self.writer.write(start)?;
 
+            self.writer.write(
+                XmlEvent::start_element("input")
+                    .attr("offset", "0")
+                    .attr("semantic", "VERTEX")
+                    .attr("source", self.url(&format!("vertices{}", suffix)).as_str())
+            )?;
+            self.writer.write(XmlEvent::end_element())?;
+            // A single index is shared by every input, at offset 0.
+            let (color_offset, uv_offset) = match (self.options.single_index, colors) {
+                (true, _) => ("0", "0"),
+                (false, true) => ("1", "2"),
+                (false, false) => ("1", "1"),
+            };
+            if colors {
                 self.writer.write(
                     XmlEvent::start_element("input")
-                        .attr("offset", "0")
-                        .attr("semantic", "VERTEX")
-                        .attr("source", "#vertices")
+                        .attr("offset", color_offset)
+                        .attr("semantic", "COLOR")
+                        .attr("source", self.url(&format!("color_source{}", suffix)).as_str())
                 )?;
                 self.writer.write(XmlEvent::end_element())?;
+                // Alpha shares the color index, so it uses the same offset.
+                if self.options.split_color_alpha {
+                    self.writer.write(
+                        XmlEvent::start_element("input")
+                            .attr("offset", color_offset)
+                            .attr("semantic", "COLOR")
+                            .attr("source", self.url(&format!("alpha_source{}", suffix)).as_str())
+                            .attr("set", "1")
+                    )?;
+                    self.writer.write(XmlEvent::end_element())?;
+                }
+            }
+            if group.has_uvs {
                 self.writer.write(
                     XmlEvent::start_element("input")
-                        .attr("offset", "1")
-                        .attr("semantic", "COLOR")
-                        .attr("source", "#color_source")
+                        .attr("offset", uv_offset)
+                        .attr("semantic", "TEXCOORD")
+                        .attr("source", self.url(&format!("uv_source{}", suffix)).as_str())
                 )?;
                 self.writer.write(XmlEvent::end_element())?;
+            }
+            // Tangents are per position (or per combined vertex), so they
+            // share the vertex index.
+            if tangents.is_some() && self.wants_tangents(group) {
+                for (semantic, name) in [("TEXTANGENT", "tangent"), ("TEXBINORMAL", "binormal")].iter() {
+                    self.writer.write(
+                        XmlEvent::start_element("input")
+                            .attr("offset", "0")
+                            .attr("semantic", semantic)
+                            .attr("source", self.url(&format!("{}_source{}", name, suffix)).as_str())
+                    )?;
+                    self.writer.write(XmlEvent::end_element())?;
+                }
+            }
 
-                match &facelist.faces {
-                    NxfFaces::ColLitTri(_faces) => {
-                        unimplemented!()
-                    },
-                    NxfFaces::TexLitTri(_faces) => {
-                        unimplemented!()
-                    },
-                    NxfFaces::TexUnlitTri(faces) => {
-                        self.writer.write(
-                            XmlEvent::start_element("input")
-                                .attr("offset", "2")
-                                .attr("semantic", "TEXCOORD")
-                                .attr("source", "#uv_source")
-                        )?;
-                        self.writer.write(XmlEvent::end_element())?;
-
+            match &strips {
+                Some(strips) => {
+                    for strip in strips.iter() {
                         self.writer.write(XmlEvent::start_element("p"))?;
-                        let mut face_data = String::new();
-                        for face in faces {
-                            face_data += &format!("{} {} {} {} {} {} {} {} {} ",
-                                face.v0,
-                                face.c0,
-                                face.uv0,
-                                face.v1,
-                                face.c1,
-                                face.uv1,
-                                face.v2,
-                                face.c2,
-                                face.uv2,
-                            );
-                        }
-                        self.writer.write(face_data.as_str())?;
+                        self.writer.write(strip.join(" ").as_str())?;
                         self.writer.write(XmlEvent::end_element())?;
-                    },
-                    NxfFaces::ColUnlitTri(faces) => {
-                        self.writer.write(XmlEvent::start_element("p"))?;
-                        let mut face_data = String::new();
-                        for face in faces {
-                            face_data += &format!("{} {} {} {} {} {} ",
-                                face.v0,
-                                face.c0,
-                                face.v1,
-                                face.c1,
-                                face.v2,
-                                face.c2,
-                            );
-                        }
-                        self.writer.write(face_data.as_str())?;
+                    }
+                }
+                None => {
+                    if self.options.polylist {
+                        self.writer.write(XmlEvent::start_element("vcount"))?;
+                        self.writer.write(vec!["3"; triangles.len()].join(" ").as_str())?;
                         self.writer.write(XmlEvent::end_element())?;
-                    },
-                    NxfFaces::TexLitEnvTri(_faces) => {
-                        unimplemented!()
-                    },
-                    NxfFaces::ColLitEnvTri(_faces) => {
-                        unimplemented!()
-                    },
+                    }
+                    let mut face_data = String::new();
+                    for triangle in triangles.iter() {
+                        for corner in triangle.iter() {
+                            face_data += corner;
+                            face_data += " ";
+                        }
+                    }
+                    self.writer.write(XmlEvent::start_element("p"))?;
+                    self.writer.write(face_data.as_str())?;
+                    self.writer.write(XmlEvent::end_element())?;
                 }
+            }
 
-                self.writer.write(XmlEvent::end_element())?;
+            // Merged facelists list their values in order, space separated.
+            let mut extra = vec![
+                ("set_flags", group.set_flags.join(" ")),
+                ("flags", group.flags.join(" ")),
+                ("attribs", group.attribs.join(" ")),
+            ];
+            // A hint for tools that sort transparent surfaces; the effect
+            // itself doesn't say whether its alpha blends.
+            if group.material.as_ref().map_or(false, |material| self.is_blended(material)) {
+                extra.push(("alpha_blend", "1".to_string()));
             }
+            write_pmw2_extra(&mut self.writer, &extra)?;
+
+            self.writer.write(XmlEvent::end_element())?;
         }
 
         self.writer.write(XmlEvent::end_element())?;
+        let mut extra = vec![
+            ("flags", self.nxf.flags.to_string()),
+            ("alpha_mode", self.nxf.alpha_mode.to_string()),
+            ("env_map_alpha_mode", self.nxf.env_map_alpha_mode.to_string()),
+            ("arrays_flags", self.nxf.arrays.flags.to_string()),
+        ];
+        if self.options.center {
+            // Translating by this puts the model back where it was.
+            let scale = self.options.scale;
+            let (x, y, z) = self.options.flip_axes.apply((center.x * scale, center.y * scale, center.z * scale));
+            extra.push(("center_offset", format!("{} {} {}", x, y, z)));
+        }
+        write_pmw2_extra(&mut self.writer, &extra)?;
         self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())
+        Ok(())
+    }
+
+    /// Id of the geometry for one LOD level, or for the whole model.
+    fn geometry_id(&self, lod: Option<usize>) -> String {
+        self.name.clone() + &lod_suffix(lod) + "_geometry"
+    }
+
+    /// Returns a document id, prefixed with the model name when
+    /// `prefix_ids` is set so several converted models can share a scene.
+    fn id(&self, id: &str) -> String {
+        if self.options.prefix_ids {
+            format!("{}_{}", self.name, id)
+        } else {
+            id.to_string()
+        }
+    }
+
+    /// Base of a material's ids, names and symbols: its texture name, made
+    /// Blender safe when `blender_names` is set.
+    fn material_base(&self, material: &NxfMaterial) -> String {
+        if self.options.blender_names {
+            blender_name(&material.tex_name)
+        } else {
+            material.tex_name.clone()
+        }
+    }
+
+    /// Like `material_base`, but gives the default material for facelists
+    /// without one.
+    fn material_label(&self, material: &Option<NxfMaterial>) -> String {
+        match material {
+            Some(material) => self.material_base(material),
+            None => DEFAULT_MATERIAL.to_string(),
+        }
+    }
+
+    /// Returns a same-document URL to the element with the given id.
+    fn url(&self, id: &str) -> String {
+        String::from("#") + &self.id(id)
+    }
+
+    /// How a material's texture alpha is written: `force_alpha` if set,
+    /// otherwise cutout for cutout materials and opaque for the rest.
+    fn alpha_treatment(&self, material: &NxfMaterial) -> AlphaTreatment {
+        match self.options.force_alpha {
+            Some(alpha) => alpha,
+            None if material.is_cutout() => AlphaTreatment::Cutout,
+            None => AlphaTreatment::Opaque,
+        }
+    }
+
+    /// Whether faces with this material are alpha blended: forced to blend,
+    /// or a blended `alpha_mode` when not forced.
+    fn is_blended(&self, material: &NxfMaterial) -> bool {
+        match self.options.force_alpha {
+            Some(alpha) => alpha == AlphaTreatment::Blend,
+            None => material.is_blended(),
+        }
+    }
+
+    /// Path written in a material's image `init_from`. With `tex_dir` set,
+    /// this is a URI, relative to `out_dir`, to the texture file found in
+    /// that directory, matched case insensitively with `tex_ext` or one of
+    /// `TEX_DIR_EXTS`; unresolved textures fall back to the plain file name.
+    /// A texture with no relative path to it (on another drive) gets an
+    /// absolute `file://` URI, which is reported as unhandled.
+    fn image_path(&self, material: &NxfMaterial) -> Result<String, ConvertError> {
+        let (tex_dir, file_names) = match &self.tex_dir_listing {
+            Some(listing) => listing,
+            None => return Ok(material.image_filename(&self.options.tex_ext)),
+        };
+
+        let wanted: Vec<String> = iter::once(self.options.tex_ext.as_str())
+            .chain(TEX_DIR_EXTS.iter().cloned())
+            .map(|ext| material.image_filename(ext).to_lowercase())
+            .collect();
+        let mut found = None;
+        for file_name in file_names.iter() {
+            let rank = wanted.iter().position(|name| *name == file_name.to_lowercase());
+            if let Some(rank) = rank {
+                // Prefer the earliest extension in the list.
+                if found.as_ref().map_or(true, |&(found_rank, _)| rank < found_rank) {
+                    found = Some((rank, file_name));
+                }
+            }
+        }
+
+        match found {
+            Some((_, file_name)) => {
+                let path = tex_dir.join(file_name);
+                match relative_path(&self.out_dir, &path) {
+                    Some(relative) => Ok(percent_encode(&relative.to_string_lossy().replace('\\', "/"))),
+                    None => {
+                        let msg = format!("texture {} has no path relative to {}; writing an absolute URI",
+                                          path.display(), self.out_dir.display());
+                        unhandled(self.options.strict, &msg)?;
+                        Ok(file_uri(&path))
+                    }
+                }
+            }
+            None => {
+                let msg = format!("texture \"{}\" not found in {}", material.tex_name, tex_dir.display());
+                unhandled(self.options.strict, &msg)?;
+                Ok(material.image_filename(&self.options.tex_ext))
+            }
+        }
+    }
+
+    /// Whether a group's material is one of `tangents`, and it has uvs to
+    /// orient them by.
+    fn wants_tangents(&self, group: &TriangleGroup) -> bool {
+        let name = match group.material {
+            Some(material) => &material.tex_name,
+            None => return false,
+        };
+        group.has_uvs && self.options.tangents.iter().any(|tangent| tangent.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the default material is written, for facelists without one.
+    fn writes_default_material(&self) -> bool {
+        !self.options.no_default_material && self.has_missing_material()
+    }
+
+    /// Whether any facelist has a null material pointer, needing the
+    /// default material.
+    fn has_missing_material(&self) -> bool {
+        self.nxf.facelists().any(|facelist| facelist.material.is_none())
+    }
+
+    /// Whether the material is written, which with `used_materials_only`
+    /// means some facelist uses it.
+    fn is_material_written(&self, material: &NxfMaterial) -> bool {
+        !self.options.used_materials_only
+            || self.nxf.facelists().any(|facelist| facelist.material.as_ref() == Some(material))
     }
 
-    fn write_library_nodes(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::start_element("library_nodes"))?;
+    /// Whether any environment mapped facelist uses this material.
+    fn is_env_mapped(&self, material: &NxfMaterial) -> bool {
+        self.nxf.facelists()
+            .any(|facelist| facelist.faces.has_env() && facelist.material.as_ref() == Some(material))
+    }
+
+    /// Point subtracted from every vertex: the bounding box center with
+    /// `center` set, otherwise the origin.
+    fn center(&self) -> Vec3 {
+        match self.nxf.arrays.compute_bounds() {
+            Some((min, max)) if self.options.center => {
+                Vec3::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0, (min.z + max.z) / 2.0)
+            }
+            _ => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Number of uv entries needed to satisfy every uv index used by the
+    /// facelists.
+    fn referenced_uv_count(&self) -> usize {
+        let mut count = 0;
+        for facelist in self.nxf.facelists() {
+            let max_uv = match &facelist.faces {
+                NxfFaces::TexLitTri(faces) => faces.iter()
+                    .map(|f| f.uv0.max(f.uv1).max(f.uv2))
+                    .max(),
+                NxfFaces::TexUnlitTri(faces) => faces.iter()
+                    .map(|f| f.uv0.max(f.uv1).max(f.uv2))
+                    .max(),
+                NxfFaces::TexLitEnvTri(faces) => faces.iter()
+                    .map(|f| f.uv0.max(f.uv1).max(f.uv2))
+                    .max(),
+                _ => None,
+            };
+            if let Some(max_uv) = max_uv {
+                count = count.max(max_uv as usize + 1);
+            }
+        }
+        count
+    }
+
+    fn write_nodes(&mut self) -> Result<(), ConvertError> {
+        // In split mode the main node shows only the first level; the others
+        // get nodes of their own that aren't in the visual scene.
+        let main_lod = if self.options.split_lods { Some(0) } else { None };
+        self.write_lod_node(&self.id("main_node"), main_lod)?;
+        if self.options.split_lods {
+            for set_idx in 1..self.nxf.facelist_sets.len() {
+                self.write_lod_node(&self.id(&format!("lod{}", set_idx)), Some(set_idx))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_lod_node(&mut self, id: &str, lod: Option<usize>) -> Result<(), ConvertError> {
         self.writer.write(
             XmlEvent::start_element("node")
-                .attr("id", "main_node")
+                .attr("id", id)
         )?;
         self.writer.write(
             XmlEvent::start_element("instance_geometry")
-                .attr("url", (String::from("#") + &self.name + "_geometry").as_str())
+                .attr("url", (String::from("#") + &self.geometry_id(lod)).as_str())
         )?;
 
+        if self.options.geometry_only {
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+            return Ok(());
+        }
+
+        // One `<bind_material>` holds every material's binding; COLLADA
+        // allows only one per instance, and it can't be empty.
+        let has_written = self.nxf.materials.iter().any(|material| self.is_material_written(material));
+        if !has_written && !self.writes_default_material() {
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+            return Ok(());
+        }
+        self.writer.write(XmlEvent::start_element("bind_material"))?;
+        self.writer.write(XmlEvent::start_element("technique_common"))?;
+
         for material in self.nxf.materials.iter() {
-            self.writer.write(XmlEvent::start_element("bind_material"))?;
-            self.writer.write(XmlEvent::start_element("technique_common"))?;
+            if !self.is_material_written(material) {
+                continue;
+            }
             self.writer.write(
                 XmlEvent::start_element("instance_material")
-                    .attr("symbol", (material.tex_name.clone() + "_symbol").as_str())
-                    .attr("target", (String::from("#") + &material.tex_name + "_material").as_str())
+                    .attr("symbol", (self.material_base(material) + "_symbol").as_str())
+                    .attr("target", self.url(&(self.material_base(material) + "_material")).as_str())
             )?;
             self.writer.write(
                 XmlEvent::start_element("bind_vertex_input")
@@ -434,46 +1490,859 @@ impl<W> Nxf2Collada<W>
             )?;
             self.writer.write(XmlEvent::end_element())?;
             self.writer.write(XmlEvent::end_element())?;
-            self.writer.write(XmlEvent::end_element())?;
+        }
+
+        if self.writes_default_material() {
+            self.writer.write(
+                XmlEvent::start_element("instance_material")
+                    .attr("symbol", (DEFAULT_MATERIAL.to_string() + "_symbol").as_str())
+                    .attr("target", self.url(&(DEFAULT_MATERIAL.to_string() + "_material")).as_str())
+            )?;
             self.writer.write(XmlEvent::end_element())?;
         }
 
         self.writer.write(XmlEvent::end_element())?;
         self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())
+        self.writer.write(XmlEvent::end_element())?;
+        self.writer.write(XmlEvent::end_element())?;
+        Ok(())
     }
 
-    fn write_library_visual_scenes(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::start_element("library_visual_scenes"))?;
+    fn write_library_visual_scenes(&mut self) -> Result<(), ConvertError> {
+        self.writer.start_library("library_visual_scenes")?;
         self.writer.write(
             XmlEvent::start_element("visual_scene")
-                .attr("id", "visual_scene")
+                .attr("id", self.id("visual_scene").as_str())
         )?;
 //        self.writer.write(
 //            XmlEvent::start_element("node")
 //                .attr("name", &self.name)
 //        )?;
-        self.writer.write(
-            XmlEvent::start_element("instance_node")
-                .attr("url", "#main_node")
-        )?;
+        for _ in 0..self.model_count() {
+            self.writer.write(
+                XmlEvent::start_element("instance_node")
+                    .attr("url", self.url("main_node").as_str())
+            )?;
+            self.writer.write(XmlEvent::end_element())?;
+            self.next_model();
+        }
 //        self.writer.write(XmlEvent::end_element())?;
         self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())
+        self.writer.end_library()?;
+        Ok(())
     }
 
-    fn write_scene(&mut self) -> Result<(), EmitterError> {
+    fn write_scene(&mut self) -> Result<(), ConvertError> {
         self.writer.write(XmlEvent::start_element("scene"))?;
         self.writer.write(
             XmlEvent::start_element("instance_visual_scene")
-                .attr("url", "#visual_scene")
+                .attr("url", self.url("visual_scene").as_str())
         )?;
         self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())
+        self.writer.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env;
+    use std::process;
+    use std::rc::Rc;
+
+    use nxf::fixture::NxfFixture;
+    use nxf::{NxfTexLitEnvTri, NxfTexUnlitTri};
+
+    use super::*;
+
+    /// A scratch directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = env::temp_dir().join(format!("pmw2_collada_{}_{}", name, process::id()));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn convert(nxf: NxfObjGeom, options: Nxf2ColladaOptions) -> Result<String, ConvertError> {
+        let mut converter = Nxf2Collada::new("quad".to_string(), nxf, Vec::new(), options);
+        converter.write_collada()?;
+        Ok(String::from_utf8(converter.into_inner()).unwrap())
+    }
+
+    /// The quad fixture with its one material's texture renamed.
+    fn quad_with_texture(tex_name: &str) -> NxfObjGeom {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.materials[0].tex_name = tex_name.to_string();
+        nxf.facelist_sets[0].facelists[0].material.as_mut().unwrap().tex_name = tex_name.to_string();
+        nxf
+    }
+
+    /// The quad fixture with its one material's `alpha_mode` set.
+    fn quad_with_alpha_mode(alpha_mode: u32) -> NxfObjGeom {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.materials[0].alpha_mode = alpha_mode;
+        nxf.facelist_sets[0].facelists[0].material.as_mut().unwrap().alpha_mode = alpha_mode;
+        nxf
+    }
+
+    /// The text of the first `<effect>` element.
+    fn first_effect(dae: &str) -> &str {
+        let start = dae.find("<effect ").unwrap_or_else(|| panic!("no effect in {}", dae));
+        &dae[start..start + dae[start..].find("</effect>").unwrap()]
+    }
+
+    /// Reads the `<float_array>` with id `id` back as `(x, y, z)` vectors.
+    fn float_array_vec3(dae: &str, id: &str) -> Vec<Vec3> {
+        let start = dae.find(&format!("id=\"{}\"", id)).unwrap_or_else(|| panic!("no {} in {}", id, dae));
+        let start = start + dae[start..].find('>').unwrap() + 1;
+        let end = start + dae[start..].find('<').unwrap();
+        let floats: Vec<f32> = dae[start..end].split_whitespace().map(|f| f.parse().unwrap()).collect();
+        floats.chunks(3).map(|v| Vec3::new(v[0], v[1], v[2])).collect()
     }
 
-    fn write_end(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::end_element())
+    /// Checks every tangent and binormal is a unit vector orthogonal to
+    /// `normal`, given in output space.
+    fn assert_tangent_frame(dae: &str, normal: Vec3) {
+        for id in ["tangent_array", "binormal_array"].iter() {
+            let vectors = float_array_vec3(dae, id);
+            assert_eq!(vectors.len(), 4);
+            for vector in vectors.iter() {
+                assert!((vector.dot(vector) - 1.0).abs() < 1e-4, "{}: {:?}", id, vector);
+                assert!(vector.dot(&normal).abs() < 1e-4, "{}: {:?} against {:?}", id, vector, normal);
+            }
+        }
+        assert!(dae.contains("semantic=\"TEXTANGENT\""));
+        assert!(dae.contains("semantic=\"TEXBINORMAL\""));
+    }
+
+    #[test]
+    fn tangents_are_orthogonal_to_file_normals() {
+        let mut nxf = NxfFixture::quad().expected();
+        // Tilted off the quad's plane, so the tangents have to be bent to
+        // be orthogonal to them.
+        nxf.arrays.normals = vec![Vec3::new(0.6, 0.0, 0.8); 4];
+        let options = Nxf2ColladaOptions {
+            tangents: vec!["FOO.tex".to_string()],
+            strict: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf, options).unwrap();
+        // The default flip negates y and z.
+        assert_tangent_frame(&dae, Vec3::new(0.6, 0.0, -0.8));
+    }
+
+    #[test]
+    fn tangents_without_file_normals_use_face_normals() {
+        for &single_index in [false, true].iter() {
+            let options = Nxf2ColladaOptions {
+                tangents: vec!["foo.tex".to_string()],
+                single_index: single_index,
+                strict: true,
+                ..Nxf2ColladaOptions::default()
+            };
+            let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+            if single_index {
+                assert_eq!(float_array_vec3(&dae, "tangent_array").len(), 4);
+            }
+            assert_tangent_frame(&dae, Vec3::new(0.0, 0.0, -1.0));
+        }
+    }
+
+    #[test]
+    fn node_binds_all_materials_in_one_bind_material() {
+        let mut nxf = NxfFixture::quad().expected();
+        // A second material, and a facelist without one for the default.
+        let mut second = nxf.materials[0].clone();
+        second.tex_name = "bar".to_string();
+        nxf.materials.push(second.clone());
+        let mut facelist = nxf.facelist_sets[0].facelists[0].clone();
+        facelist.material = Some(second);
+        nxf.facelist_sets[0].facelists.push(facelist.clone());
+        facelist.material = None;
+        nxf.facelist_sets[0].facelists.push(facelist);
+
+        let dae = convert(nxf, Nxf2ColladaOptions::default()).unwrap();
+        assert_eq!(dae.matches("<bind_material>").count(), 1, "{}", dae);
+        let bind = &dae[dae.find("<bind_material>").unwrap()..dae.find("</bind_material>").unwrap()];
+        assert_eq!(bind.matches("<technique_common>").count(), 1);
+        for symbol in ["foo.tex_symbol", "bar_symbol", "nxf_default_symbol"].iter() {
+            assert!(bind.contains(&format!("symbol=\"{}\"", symbol)), "{} in {}", symbol, bind);
+        }
+    }
+
+    #[test]
+    fn geometry_only_leaves_out_materials() {
+        let options = Nxf2ColladaOptions {
+            geometry_only: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        assert!(!dae.contains("bind_material"));
+        assert!(dae.contains("<library_geometries>"), "{}", dae);
+        for absent in ["<library_effects", "<library_images", "<library_materials"].iter() {
+            assert!(!dae.contains(absent), "{} in {}", absent, dae);
+        }
+        assert!(dae.contains("<instance_visual_scene"), "{}", dae);
+    }
+
+    #[test]
+    fn single_index_combines_equal_corners() {
+        let options = Nxf2ColladaOptions {
+            single_index: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        // The two triangles share two of the quad's four corners.
+        assert_eq!(float_array_vec3(&dae, "vertex_array").len(), 4);
+        assert!(dae.contains("<p>0 1 2 0 2 3 </p>"), "{}", dae);
+    }
+
+    #[test]
+    fn empty_colors_are_white() {
+        for &single_index in [false, true].iter() {
+            let mut nxf = NxfFixture::quad().expected();
+            nxf.arrays.colors.clear();
+            let options = Nxf2ColladaOptions {
+                single_index: single_index,
+                ..Nxf2ColladaOptions::default()
+            };
+            let dae = convert(nxf, options).unwrap();
+            let start = dae.find("id=\"color_array\"").unwrap();
+            let start = start + dae[start..].find('>').unwrap() + 1;
+            let end = start + dae[start..].find('<').unwrap();
+            let colors: Vec<f32> = dae[start..end].split_whitespace().map(|f| f.parse().unwrap()).collect();
+            // The faces use color indices 0 and 1; combined, each of the
+            // four vertices gets its own.
+            assert_eq!(colors.len(), if single_index { 16 } else { 8 });
+            assert!(colors.iter().all(|&c| c == 1.0), "{:?}", colors);
+        }
+    }
+
+    /// The quad with its facelist's faces made environment mapped, and its
+    /// material's `env_map_alpha_mode` set.
+    fn env_quad(env_map_alpha_mode: u32) -> NxfObjGeom {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.materials[0].env_map_alpha_mode = env_map_alpha_mode;
+        let facelist = &mut nxf.facelist_sets[0].facelists[0];
+        facelist.material.as_mut().unwrap().env_map_alpha_mode = env_map_alpha_mode;
+        facelist.faces = NxfFaces::TexLitEnvTri(vec![NxfTexLitEnvTri {
+            v0: 0, n0: 0, c0: 0, uv0: 0, m0: 0,
+            v1: 1, n1: 0, c1: 0, uv1: 1, m1: 0,
+            v2: 2, n2: 0, c2: 0, uv2: 2, m2: 0,
+        }]);
+        nxf
+    }
+
+    #[test]
+    fn env_material_is_reflective() {
+        let plain = convert(NxfFixture::quad().expected(), Nxf2ColladaOptions::default()).unwrap();
+        assert!(!plain.contains("<reflective>"));
+
+        // The fixture's ref color is 10, 20, 30, 40.
+        let opaque = convert(env_quad(0), Nxf2ColladaOptions::default()).unwrap();
+        let reflective = &opaque[opaque.find("<reflective>").unwrap()..opaque.find("</reflective>").unwrap()];
+        let color = format!("<color>{} {} {} {}</color>", 10.0f32 / 255.0, 20.0f32 / 255.0, 30.0f32 / 255.0, 40.0f32 / 255.0);
+        assert!(reflective.contains(&color), "{}", reflective);
+        assert!(opaque.contains("<float>1</float>"), "{}", opaque);
+
+        let blended = convert(env_quad(1), Nxf2ColladaOptions::default()).unwrap();
+        assert!(blended.contains(&format!("<float>{}</float>", 40.0f32 / 255.0)), "{}", blended);
+    }
+
+    #[test]
+    fn split_by_material_gives_one_material_per_part() {
+        let mut nxf = NxfFixture::quad().expected();
+        let mut second = nxf.materials[0].clone();
+        second.tex_name = "bar".to_string();
+        nxf.materials.push(second.clone());
+        let mut facelist = nxf.facelist_sets[0].facelists[0].clone();
+        facelist.material = Some(second);
+        nxf.facelist_sets[0].facelists.push(facelist);
+
+        let parts = split_by_material(&nxf);
+        let names: Vec<&str> = parts.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["foo.tex", "bar"]);
+        for (name, part) in parts {
+            assert_eq!(part.materials.len(), 1);
+            assert_eq!(part.facelists().count(), 1);
+            let dae = convert(part, Nxf2ColladaOptions::default()).unwrap();
+            assert_eq!(dae.matches("<material ").count(), 1, "{}", dae);
+            assert!(dae.contains(&format!("<material id=\"{}_material\"", name)), "{}", dae);
+        }
+    }
+
+    #[test]
+    fn output_is_byte_stable() {
+        let options = Nxf2ColladaOptions {
+            single_index: true,
+            merge_by_material: true,
+            tangents: vec!["foo.tex".to_string()],
+            ..Nxf2ColladaOptions::default()
+        };
+        let first = convert(NxfFixture::quad().expected(), options.clone()).unwrap();
+        let second = convert(NxfFixture::quad().expected(), options).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn tex_dir_resolves_ignoring_case() {
+        let dir = TempDir::new("tex_dir_case");
+        fs::create_dir_all(dir.0.join("tex")).unwrap();
+        fs::write(dir.0.join("tex").join("Foo.PNG"), b"").unwrap();
+        let options = Nxf2ColladaOptions {
+            tex_dir: Some(dir.0.join("tex").to_string_lossy().into_owned()),
+            out_dir: Some(dir.0.to_string_lossy().into_owned()),
+            strict: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(quad_with_texture("foo"), options).unwrap();
+        assert!(dae.contains("<init_from>tex/Foo.PNG</init_from>"), "{}", dae);
+    }
+
+    #[test]
+    fn tex_dir_uri_is_relative_to_out_dir_and_encoded() {
+        let dir = TempDir::new("tex_dir_relative");
+        fs::create_dir_all(dir.0.join("my tex")).unwrap();
+        fs::create_dir_all(dir.0.join("out")).unwrap();
+        fs::write(dir.0.join("my tex").join("foo.tga"), b"").unwrap();
+        let options = Nxf2ColladaOptions {
+            tex_dir: Some(dir.0.join("my tex").to_string_lossy().into_owned()),
+            out_dir: Some(dir.0.join("out").to_string_lossy().into_owned()),
+            strict: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(quad_with_texture("foo"), options).unwrap();
+        assert!(dae.contains("<init_from>../my%20tex/foo.tga</init_from>"), "{}", dae);
+    }
+
+    #[test]
+    fn tex_dir_missing_texture_is_unhandled() {
+        let dir = TempDir::new("tex_dir_missing");
+        let options = Nxf2ColladaOptions {
+            tex_dir: Some(dir.0.to_string_lossy().into_owned()),
+            out_dir: Some(dir.0.to_string_lossy().into_owned()),
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(quad_with_texture("foo"), options.clone()).unwrap();
+        assert!(dae.contains("<init_from>foo.png</init_from>"), "{}", dae);
+        let strict = Nxf2ColladaOptions {
+            strict: true,
+            ..options
+        };
+        assert!(convert(quad_with_texture("foo"), strict).is_err());
+    }
+
+    #[test]
+    fn relative_path_walks_up_to_common_dir() {
+        assert_eq!(relative_path(Path::new("/a/b/c"), Path::new("/a/d/e.png")), Some(PathBuf::from("../../d/e.png")));
+        assert_eq!(relative_path(Path::new("/a"), Path::new("/a/e.png")), Some(PathBuf::from("e.png")));
+    }
+
+    #[test]
+    fn no_vertex_colors_drops_color_input_and_indices() {
+        let options = Nxf2ColladaOptions {
+            vertex_colors: false,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        assert!(!dae.contains("color_source"), "{}", dae);
+        assert!(!dae.contains("semantic=\"COLOR\""), "{}", dae);
+        // Vertex and uv index per corner, with the uvs at offset 1.
+        assert!(dae.contains("offset=\"1\" semantic=\"TEXCOORD\""), "{}", dae);
+        assert!(dae.contains("<p>0 0 1 1 2 2 0 0 2 2 3 3 </p>"), "{}", dae);
+    }
+
+    #[test]
+    fn flip_axis_none_keeps_positions() {
+        let verts = vec![
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(-4.0, 5.0, 6.0),
+            Vec3::new(7.0, -8.0, 9.0),
+            Vec3::new(10.0, 11.0, -12.0),
+        ];
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.arrays.verts = verts.clone();
+        let options = Nxf2ColladaOptions {
+            flip_axes: FlipAxes::parse("none").unwrap(),
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf.clone(), options).unwrap();
+        assert_eq!(float_array_vec3(&dae, "vertex_array"), verts);
+
+        let dae = convert(nxf, Nxf2ColladaOptions::default()).unwrap();
+        let flipped: Vec<Vec3> = verts.iter().map(|v| Vec3::new(v.x, -v.y, -v.z)).collect();
+        assert_eq!(float_array_vec3(&dae, "vertex_array"), flipped);
+    }
+
+    #[test]
+    fn empty_uv_array_gets_zero_filled_source() {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.arrays.uvs.clear();
+        let dae = convert(nxf, Nxf2ColladaOptions::default()).unwrap();
+        assert!(dae.contains("source=\"#uv_source\""), "{}", dae);
+        assert!(dae.contains("<source id=\"uv_source\">"), "{}", dae);
+        // The faces index uvs 0 through 3.
+        assert!(dae.contains("<float_array id=\"uv_array\" count=\"8\">0 1 0 1 0 1 0 1 </float_array>"), "{}", dae);
+    }
+
+    #[test]
+    fn geometry_extra_has_object_flags() {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.flags = 0x1234;
+        nxf.alpha_mode = 2;
+        let dae = convert(nxf, Nxf2ColladaOptions::default()).unwrap();
+        let geometry = dae.find("<geometry ").unwrap();
+        let geometry = &dae[geometry..geometry + dae[geometry..].find("</geometry>").unwrap()];
+        let extra = &geometry[geometry.find("<technique profile=\"PMW2\">").expect(geometry)..];
+        assert!(extra.contains("<flags>4660</flags>"), "{}", extra);
+        assert!(extra.contains("<alpha_mode>2</alpha_mode>"), "{}", extra);
+    }
+
+    #[test]
+    fn double_sided_marks_every_effect() {
+        let mut nxf = NxfFixture::quad().expected();
+        let mut second = nxf.materials[0].clone();
+        second.tex_name = "bar".to_string();
+        nxf.materials.push(second.clone());
+        let mut facelist = nxf.facelist_sets[0].facelists[0].clone();
+        facelist.material = Some(second);
+        nxf.facelist_sets[0].facelists.push(facelist);
+
+        let options = Nxf2ColladaOptions {
+            double_sided: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf.clone(), options).unwrap();
+        let effects: Vec<&str> = dae.split("<effect ").skip(1)
+            .map(|effect| &effect[..effect.find("</effect>").unwrap()])
+            .collect();
+        assert_eq!(effects.len(), 2, "{}", dae);
+        for effect in effects {
+            for profile in ["MAYA", "GOOGLEEARTH"].iter() {
+                let technique = format!("<technique profile=\"{}\">", profile);
+                let technique = &effect[effect.find(&technique).expect(effect)..];
+                assert!(technique.contains("<double_sided>1</double_sided>"), "{}", effect);
+            }
+        }
+
+        let dae = convert(nxf, Nxf2ColladaOptions::default()).unwrap();
+        assert!(!dae.contains("double_sided"), "{}", dae);
+    }
+
+    #[test]
+    fn progress_fires_once_per_facelist() {
+        let mut nxf = NxfFixture::quad().expected();
+        let facelist = nxf.facelist_sets[0].facelists[0].clone();
+        nxf.facelist_sets[0].facelists.push(facelist);
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut converter = Nxf2Collada::new("quad".to_string(), nxf, Vec::new(), Nxf2ColladaOptions::default());
+        let seen = calls.clone();
+        converter.set_progress(Box::new(move |done, total, _| seen.borrow_mut().push((done, total))));
+        converter.write_collada().unwrap();
+        assert_eq!(*calls.borrow(), [(1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn scale_multiplies_positions_but_not_normals() {
+        let nxf = NxfFixture::quad().expected();
+        let options = Nxf2ColladaOptions {
+            scale: 100.0,
+            recompute_normals: true,
+            flip_axes: FlipAxes::parse("none").unwrap(),
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf.clone(), options).unwrap();
+        let scaled: Vec<Vec3> = nxf.arrays.verts.iter().map(|v| Vec3::new(v.x * 100.0, v.y * 100.0, v.z * 100.0)).collect();
+        assert_eq!(float_array_vec3(&dae, "vertex_array"), scaled);
+        for normal in float_array_vec3(&dae, "normal_array") {
+            assert_eq!(normal, Vec3::new(0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn cutout_material_gets_mask_transparency() {
+        let dae = convert(quad_with_alpha_mode(nxf::ALPHA_MODE_CUTOUT), Nxf2ColladaOptions::default()).unwrap();
+        let effect = first_effect(&dae);
+        assert!(effect.contains("<transparent opaque=\"A_ONE\">"), "{}", effect);
+        assert!(effect.contains("<texture texture=\"foo.tex_sampler\" texcoord=\"nxf_uvs\""), "{}", effect);
+        assert!(effect.contains("<alpha_test>1</alpha_test>"), "{}", effect);
+
+        let dae = convert(quad_with_alpha_mode(0), Nxf2ColladaOptions::default()).unwrap();
+        let effect = first_effect(&dae);
+        assert!(!effect.contains("<transparent"), "{}", effect);
+        assert!(!effect.contains("alpha_test"), "{}", effect);
+    }
+
+    #[test]
+    fn merge_by_material_collapses_facelists() {
+        let mut nxf = NxfFixture::quad().expected();
+        let mut bar = nxf.materials[0].clone();
+        bar.tex_name = "bar".to_string();
+        nxf.materials.push(bar.clone());
+        let mut facelist = nxf.facelist_sets[0].facelists[0].clone();
+        let foo = facelist.clone();
+        facelist.material = Some(bar);
+        nxf.facelist_sets[0].facelists.push(facelist);
+        nxf.facelist_sets[0].facelists.push(foo);
+
+        let dae = convert(nxf.clone(), Nxf2ColladaOptions::default()).unwrap();
+        assert_eq!(dae.matches("<triangles ").count(), 3, "{}", dae);
+
+        let options = Nxf2ColladaOptions {
+            merge_by_material: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf, options).unwrap();
+        assert_eq!(dae.matches("<triangles ").count(), 2, "{}", dae);
+        assert!(dae.contains("<triangles count=\"4\" material=\"foo.tex_symbol\">"), "{}", dae);
+        assert!(dae.contains("<triangles count=\"2\" material=\"bar_symbol\">"), "{}", dae);
+    }
+
+    #[test]
+    fn asset_names_authoring_tool_version() {
+        let dae = convert(NxfFixture::quad().expected(), Nxf2ColladaOptions::default()).unwrap();
+        let tool = format!("<authoring_tool>pmw2_collada {}</authoring_tool>", env!("CARGO_PKG_VERSION"));
+        let asset = &dae[dae.find("<asset>").unwrap()..dae.find("</asset>").unwrap()];
+        assert!(asset.contains(&tool), "{}", asset);
+    }
+
+    #[test]
+    fn material_less_model_leaves_out_material_libraries() {
+        let mut nxf = NxfFixture::quad().expected();
+        nxf.materials.clear();
+        nxf.facelist_sets[0].facelists[0].material = None;
+
+        let options = Nxf2ColladaOptions {
+            no_default_material: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf.clone(), options).unwrap();
+        for absent in ["<library_effects", "<library_images", "<library_materials", "<bind_material"].iter() {
+            assert!(!dae.contains(absent), "{} in {}", absent, dae);
+        }
+        assert!(dae.contains("<library_geometries>"), "{}", dae);
+        for event in xml::reader::EventReader::new(dae.as_bytes()) {
+            event.unwrap();
+        }
+
+        // The default material still needs its effect, but no image.
+        let dae = convert(nxf, Nxf2ColladaOptions::default()).unwrap();
+        assert!(dae.contains("<library_effects>"), "{}", dae);
+        assert!(dae.contains("<library_materials>"), "{}", dae);
+        assert!(!dae.contains("<library_images"), "{}", dae);
+    }
+
+    /// Every `id` attribute in a document.
+    fn ids(dae: &str) -> Vec<String> {
+        dae.split(" id=\"").skip(1)
+            .map(|rest| rest[..rest.find('"').unwrap()].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn prefix_ids_keep_models_apart() {
+        let convert_named = |name: &str| {
+            let options = Nxf2ColladaOptions {
+                prefix_ids: true,
+                ..Nxf2ColladaOptions::default()
+            };
+            let mut converter = Nxf2Collada::new(name.to_string(), NxfFixture::quad().expected(), Vec::new(), options);
+            converter.write_collada().unwrap();
+            String::from_utf8(converter.into_inner()).unwrap()
+        };
+        let house = ids(&convert_named("house"));
+        let tree = ids(&convert_named("tree"));
+        assert!(house.iter().any(|id| id == "house_main_node"), "{:?}", house);
+        for id in house.iter() {
+            assert!(!tree.contains(id), "{} in both", id);
+        }
+    }
+
+    #[test]
+    fn material_extra_has_pmi_indices() {
+        let dae = convert(NxfFixture::quad().expected(), Nxf2ColladaOptions::default()).unwrap();
+        let material = &dae[dae.find("<material id=\"foo.tex_material\"").expect(&dae)..];
+        let material = &material[..material.find("</material>").unwrap()];
+        assert!(material.contains("<tex_pmi>1</tex_pmi>"), "{}", material);
+        assert!(material.contains("<ref_pmi>2</ref_pmi>"), "{}", material);
+    }
+
+    #[test]
+    fn tab_indent_string_indents_output() {
+        let options = Nxf2ColladaOptions {
+            indent_string: "\t".to_string(),
+            line_separator: "\r\n".to_string(),
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        assert!(dae.contains("\r\n\t<asset>\r\n\t\t<contributor>"), "{:?}", dae);
+        assert!(!dae.contains("\n "), "{:?}", dae);
+    }
+
+    #[test]
+    fn split_lods_names_a_node_per_set() {
+        let mut nxf = NxfFixture::quad().expected();
+        let set = nxf.facelist_sets[0].clone();
+        nxf.facelist_sets.push(set);
+        let options = Nxf2ColladaOptions {
+            split_lods: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf, options).unwrap();
+        assert!(dae.contains("<geometry id=\"quad_lod0_geometry\""), "{}", dae);
+        assert!(dae.contains("<geometry id=\"quad_lod1_geometry\""), "{}", dae);
+        let node = |id: &str| {
+            let start = dae.find(&format!("<node id=\"{}\"", id)).unwrap_or_else(|| panic!("no {} in {}", id, dae));
+            &dae[start..start + dae[start..].find("</node>").unwrap()]
+        };
+        assert!(node("main_node").contains("url=\"#quad_lod0_geometry\""), "{}", dae);
+        assert!(node("lod1").contains("url=\"#quad_lod1_geometry\""), "{}", dae);
+        // Only the first level is shown.
+        let scene = &dae[dae.find("<library_visual_scenes>").unwrap()..];
+        assert!(scene.contains("url=\"#main_node\""), "{}", scene);
+        assert!(!scene.contains("lod1"), "{}", scene);
+    }
+
+    #[test]
+    fn split_color_alpha_writes_separate_alpha_source() {
+        let options = Nxf2ColladaOptions {
+            split_color_alpha: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        let source = |id: &str| {
+            let start = dae.find(&format!("<source id=\"{}\">", id)).unwrap_or_else(|| panic!("no {} in {}", id, dae));
+            &dae[start..start + dae[start..].find("</source>").unwrap()]
+        };
+        let color = source("color_source");
+        assert!(color.contains("stride=\"3\""), "{}", color);
+        assert!(!color.contains("name=\"A\""), "{}", color);
+        let alpha = source("alpha_source");
+        assert!(alpha.contains("stride=\"1\""), "{}", alpha);
+        assert!(alpha.contains(&format!(">1 {} </float_array>", 128.0f32 / 255.0)), "{}", alpha);
+        assert!(dae.contains("source=\"#alpha_source\""), "{}", dae);
+    }
+
+    #[test]
+    fn tristrips_join_continuing_triangles() {
+        let face = |v0: u16, v1: u16, v2: u16| NxfTexUnlitTri {
+            v0: v0, c0: 0, uv0: v0,
+            v1: v1, c1: 0, uv1: v1,
+            v2: v2, c2: 0, uv2: v2,
+        };
+        let mut nxf = NxfFixture::quad().expected();
+        // The second triangle continues the first; the third doesn't.
+        nxf.facelist_sets[0].facelists[0].faces = NxfFaces::TexUnlitTri(vec![
+            face(0, 1, 2),
+            face(2, 1, 3),
+            face(0, 2, 1),
+        ]);
+        let options = Nxf2ColladaOptions {
+            tristrips: true,
+            vertex_colors: false,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf, options).unwrap();
+        assert!(!dae.contains("<triangles"), "{}", dae);
+        assert!(dae.contains("<tristrips count=\"2\""), "{}", dae);
+        assert!(dae.contains("<p>0 0 1 1 2 2 3 3</p>"), "{}", dae);
+        assert!(dae.contains("<p>0 0 2 2 1 1</p>"), "{}", dae);
+    }
+
+    #[test]
+    fn tex_ext_sets_image_extension() {
+        let dae = convert(NxfFixture::quad().expected(), Nxf2ColladaOptions::default()).unwrap();
+        assert!(dae.contains("<init_from>foo.tex.png</init_from>"), "{}", dae);
+
+        let options = Nxf2ColladaOptions {
+            tex_ext: "dds".to_string(),
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        assert!(dae.contains("<init_from>foo.tex.dds</init_from>"), "{}", dae);
+        assert!(!dae.contains(".png"), "{}", dae);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn blender_names_replace_dots_and_keep_original() {
+        let options = Nxf2ColladaOptions {
+            blender_names: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        let start = dae.find("<material id=\"foo_tex_material\"").expect(&dae);
+        let material = &dae[start..start + dae[start..].find("</material>").unwrap()];
+        assert!(material.contains("name=\"foo_tex\""), "{}", material);
+        assert!(material.contains("<tex_name>foo.tex</tex_name>"), "{}", material);
+        assert!(dae.contains("material=\"foo_tex_symbol\""), "{}", dae);
+        assert!(!dae.contains("foo.tex_"), "{}", dae);
+    }
+
+    #[test]
+    fn xml_declaration_uses_given_encoding() {
+        let dae = convert(NxfFixture::quad().expected(), Nxf2ColladaOptions::default()).unwrap();
+        assert!(dae.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>"), "{}", dae);
+
+        let options = Nxf2ColladaOptions {
+            xml_encoding: "UTF-8".to_string(),
+            xml_standalone: Some(true),
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        assert!(dae.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"), "{}", dae);
+    }
+
+    #[test]
+    fn polylist_writes_a_vcount_of_threes() {
+        let options = Nxf2ColladaOptions {
+            polylist: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(NxfFixture::quad().expected(), options).unwrap();
+        assert!(!dae.contains("<triangles"), "{}", dae);
+        assert!(dae.contains("<polylist count=\"2\""), "{}", dae);
+        assert!(dae.contains("<vcount>3 3</vcount>"), "{}", dae);
+    }
+
+    #[test]
+    fn force_alpha_blend_blends_every_effect() {
+        let mut nxf = quad_with_alpha_mode(nxf::ALPHA_MODE_CUTOUT);
+        let mut bar = nxf.materials[0].clone();
+        bar.tex_name = "bar".to_string();
+        bar.alpha_mode = 0;
+        nxf.materials.push(bar.clone());
+        let mut facelist = nxf.facelist_sets[0].facelists[0].clone();
+        facelist.material = Some(bar);
+        nxf.facelist_sets[0].facelists.push(facelist);
+
+        let options = Nxf2ColladaOptions {
+            force_alpha: Some(AlphaTreatment::Blend),
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf, options).unwrap();
+        let effects: Vec<&str> = dae.split("<effect ").skip(1).collect();
+        assert_eq!(effects.len(), 2, "{}", dae);
+        for effect in effects.iter() {
+            assert!(effect.contains("<transparent opaque=\"A_ONE\">"), "{}", effect);
+            assert!(!effect.contains("alpha_test"), "{}", effect);
+        }
+    }
+
+    #[test]
+    fn used_materials_only_drops_orphans() {
+        let mut nxf = NxfFixture::quad().expected();
+        let mut orphan = nxf.materials[0].clone();
+        orphan.tex_name = "orphan.tex".to_string();
+        nxf.materials.push(orphan);
+
+        let dae = convert(nxf.clone(), Nxf2ColladaOptions::default()).unwrap();
+        assert!(dae.contains("orphan.tex"), "{}", dae);
+
+        let options = Nxf2ColladaOptions {
+            used_materials_only: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf, options).unwrap();
+        assert!(!dae.contains("orphan.tex"), "{}", dae);
+        assert_eq!(dae.matches("<material ").count(), 1, "{}", dae);
+        assert_eq!(dae.matches("<effect ").count(), 1, "{}", dae);
+    }
+
+    #[test]
+    fn null_material_triangles_have_no_material_symbol() {
+        let mut nxf = NxfFixture::quad().expected();
+        let mut bare = nxf.facelist_sets[0].facelists[0].clone();
+        bare.material = None;
+        nxf.facelist_sets[0].facelists.push(bare);
+
+        let options = Nxf2ColladaOptions {
+            no_default_material: true,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf.clone(), options).unwrap();
+        assert!(dae.contains("<triangles count=\"2\" material=\"foo.tex_symbol\">"), "{}", dae);
+        assert!(dae.contains("<triangles count=\"2\">"), "{}", dae);
+        assert!(!dae.contains(DEFAULT_MATERIAL), "{}", dae);
+
+        let dae = convert(nxf, Nxf2ColladaOptions::default()).unwrap();
+        assert!(!dae.contains("<triangles count=\"2\">"), "{}", dae);
+        assert!(dae.contains(&format!("material=\"{}_symbol\"", DEFAULT_MATERIAL)), "{}", dae);
+    }
+
+    #[test]
+    fn blended_triangles_carry_alpha_blend_hint() {
+        let dae = convert(quad_with_alpha_mode(nxf::ALPHA_MODE_BLEND), Nxf2ColladaOptions::default()).unwrap();
+        let start = dae.find("<triangles ").unwrap();
+        let triangles = &dae[start..start + dae[start..].find("</triangles>").unwrap()];
+        assert!(triangles.contains("<alpha_blend>1</alpha_blend>"), "{}", triangles);
+
+        for &alpha_mode in [0, nxf::ALPHA_MODE_CUTOUT].iter() {
+            let dae = convert(quad_with_alpha_mode(alpha_mode), Nxf2ColladaOptions::default()).unwrap();
+            assert!(!dae.contains("alpha_blend"), "{}", dae);
+        }
+    }
+
+    #[test]
+    fn center_makes_bounds_symmetric_about_origin() {
+        let mut nxf = NxfFixture::quad().expected();
+        for vert in nxf.arrays.verts.iter_mut() {
+            *vert = Vec3::new(vert.x * 4.0 + 100.0, vert.y * 2.0 - 50.0, vert.z + 7.0);
+        }
+        let options = Nxf2ColladaOptions {
+            center: true,
+            flip_axes: FlipAxes::parse("none").unwrap(),
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf, options).unwrap();
+        let verts = float_array_vec3(&dae, "vertex_array");
+        for axis in 0..3 {
+            let values: Vec<f32> = verts.iter().map(|v| [v.x, v.y, v.z][axis]).collect();
+            let min = values.iter().cloned().fold(f32::INFINITY, f32::min);
+            let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+            assert!((min + max).abs() < 1e-4, "axis {}: {} .. {}", axis, min, max);
+        }
+        assert!(dae.contains("<center_offset>102 -49 7</center_offset>"), "{}", dae);
+    }
+
+    #[test]
+    fn degenerate_triangles_are_dropped_unless_kept() {
+        let face = |v0: u16, v1: u16, v2: u16| NxfTexUnlitTri {
+            v0: v0, c0: 0, uv0: v0,
+            v1: v1, c1: 0, uv1: v1,
+            v2: v2, c2: 0, uv2: v2,
+        };
+        let mut nxf = NxfFixture::quad().expected();
+        // A strip restart's repeated vertex between the quad's triangles.
+        nxf.facelist_sets[0].facelists[0].faces = NxfFaces::TexUnlitTri(vec![
+            face(0, 1, 2),
+            face(2, 2, 0),
+            face(0, 2, 3),
+        ]);
+        let options = Nxf2ColladaOptions {
+            vertex_colors: false,
+            ..Nxf2ColladaOptions::default()
+        };
+        let dae = convert(nxf.clone(), options.clone()).unwrap();
+        assert!(dae.contains("<triangles count=\"2\""), "{}", dae);
+        assert!(dae.contains("<p>0 0 1 1 2 2 0 0 2 2 3 3 </p>"), "{}", dae);
+
+        let options = Nxf2ColladaOptions {
+            drop_degenerate: false,
+            ..options
+        };
+        let dae = convert(nxf, options).unwrap();
+        assert!(dae.contains("<triangles count=\"3\""), "{}", dae);
+        assert!(dae.contains("<p>0 0 1 1 2 2 2 2 2 2 0 0 0 0 2 2 3 3 </p>"), "{}", dae);
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+/// The converted document's destination: the output file as is, or
+/// gzip-compressed for `--gzip`.
+pub enum Output {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+}
+
+impl Output {
+    pub fn create<P>(path: P, gzip: bool) -> io::Result<Output>
+        where P: AsRef<Path>
+    {
+        let file = File::create(path)?;
+        if gzip {
+            Ok(Output::Gzip(GzEncoder::new(file, Compression::default())))
+        } else {
+            Ok(Output::Plain(file))
+        }
+    }
+
+    /// Flushes the output, writing the gzip trailer if compressing. Dropping
+    /// an `Output` without this would lose any error doing so.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut file) => file.flush(),
+            Output::Gzip(encoder) => encoder.finish().map(|_| ()),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(file) => file.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,11 @@
+/// Called with `(done, total, description)` as a converter works through its
+/// input, so long conversions can report that they're still alive.
+pub type ProgressFn = Box<dyn FnMut(usize, usize, &str)>;
+
+/// A progress callback that prints to stderr, keeping stdout clean for
+/// scripted use.
+pub fn stderr_progress() -> ProgressFn {
+    Box::new(|done, total, what| {
+        eprintln!("converting {}/{}: {}", done, total, what);
+    })
+}
@@ -1,103 +1,357 @@
+use std::collections::HashMap;
 use std::io::Write;
 
-use sf::{SceneTemplate, ScenePlacementData, SceneGeomFormat};
-use xml::EmitterConfig;
-use xml::writer::{EventWriter, Error as EmitterError};
+use sf::{SceneTemplate, ScenePlacement, ScenePlacementData, SceneGeomFormat};
+use sf::matrix::Matrix;
 use xml::writer::events::XmlEvent;
 
-use crate::matrix::Matrix;
+use crate::axes::FlipAxes;
+use crate::document::{ColladaDocument, DocumentOptions, Unit};
+use crate::error::ConvertError;
+use crate::extra::write_pmw2_extra;
+use crate::names::blender_name;
+use crate::progress::ProgressFn;
+use crate::strict::unhandled;
+
+fn is_light(data: &ScenePlacementData) -> bool {
+    matches!(data, ScenePlacementData::DirLight { .. } | ScenePlacementData::AmbientLight { .. })
+}
+
+fn is_camera(data: &ScenePlacementData) -> bool {
+    matches!(data, ScenePlacementData::Camera { .. })
+}
+
+fn matrix_string(mat: &Matrix) -> String {
+    format!("{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
+        mat.0[0x0], mat.0[0x1], mat.0[0x2], mat.0[0x3],
+        mat.0[0x4], mat.0[0x5], mat.0[0x6], mat.0[0x7],
+        mat.0[0x8], mat.0[0x9], mat.0[0xa], mat.0[0xb],
+        mat.0[0xc], mat.0[0xd], mat.0[0xe], mat.0[0xf],
+    )
+}
+
+/// Half height of the boxes drawn for scene and clump bounds, which only
+/// have X and Z extents.
+const BOUNDS_HALF_HEIGHT: f32 = 1.0;
+
+/// Clipping planes given to exported cameras, in game units. The SF only
+/// stores the field of view.
+const CAMERA_ZNEAR: f32 = 1.0;
+const CAMERA_ZFAR: f32 = 10000.0;
+
+/// Which of a placement's two names to use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlacementName {
+    Geom,
+    Model,
+}
+
+impl PlacementName {
+    /// Parses `"geom"` or `"model"`.
+    pub fn parse(s: &str) -> Option<PlacementName> {
+        match s {
+            "geom" => Some(PlacementName::Geom),
+            "model" => Some(PlacementName::Model),
+            _ => None,
+        }
+    }
+
+    /// Name of the placement field this reads.
+    fn field(&self) -> &'static str {
+        match self {
+            PlacementName::Geom => "geom_name",
+            PlacementName::Model => "model_name",
+        }
+    }
+
+    fn get<'a>(&self, placement: &'a ScenePlacement) -> &'a str {
+        match self {
+            PlacementName::Geom => &placement.geom_name,
+            PlacementName::Model => &placement.model_name,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sf2ColladaOptions {
+    /// Emit the `library_nodes` section with points and bounding boxes.
+    pub include_placements: bool,
+    /// Wrap each clump's placements in a `clump_N` parent node.
+    pub group_by_clump: bool,
+    /// Axes negated when converting placements into COLLADA space.
+    pub flip_axes: FlipAxes,
+    /// Error out on placements the converter can't handle instead of
+    /// warning and skipping them.
+    pub strict: bool,
+    /// Factor applied to placement translations, matching geometry
+    /// converted with the same scale.
+    pub scale: f32,
+    /// Length of a game unit declared in the output, leaving the vertex
+    /// data as is. `None` leaves it undeclared.
+    pub unit: Option<Unit>,
+    /// File referenced by each geometry instance, with `{name}` replaced
+    /// by the placement's geometry name.
+    pub geom_file_template: String,
+    /// Node id instanced from each referenced geometry file, with `{name}`
+    /// replaced as in `geom_file_template`. Use `"{name}_main_node"` for
+    /// geometry converted with prefixed ids.
+    pub geom_node_id: String,
+    /// Emit directional and ambient lights and cameras, each instanced in
+    /// the visual scene.
+    pub include_lights: bool,
+    /// Emit flat boxes outlining the scene's bounds and each clump's bounds.
+    pub show_bounds: bool,
+    /// Name that fills `{name}` in the referenced geometry file and node id.
+    pub file_name: PlacementName,
+    /// Name given to each placement's node.
+    pub node_name: PlacementName,
+    /// Placement types, by `ScenePlacementData::type_name`, to leave out.
+    pub exclude_types: Vec<String>,
+    /// Make node names safe from Blender's renaming: no dots and at most 63
+    /// bytes. The original name is kept in each node's extra data.
+    pub blender_names: bool,
+    /// Encoding named in the XML declaration, e.g. "utf-8" or "UTF-8". Only
+    /// the label changes; the output is always UTF-8.
+    pub xml_encoding: String,
+    /// `standalone` value of the XML declaration, left out when `None`.
+    pub xml_standalone: Option<bool>,
+    /// String used for one level of indentation in the output.
+    pub indent_string: String,
+    /// String written between lines of output.
+    pub line_separator: String,
+}
+
+impl Default for Sf2ColladaOptions {
+    fn default() -> Sf2ColladaOptions {
+        Sf2ColladaOptions {
+            include_placements: false,
+            group_by_clump: false,
+            flip_axes: FlipAxes::default(),
+            strict: false,
+            scale: 1.0,
+            unit: None,
+            geom_file_template: "{name}.dae".to_string(),
+            geom_node_id: "main_node".to_string(),
+            include_lights: false,
+            show_bounds: false,
+            file_name: PlacementName::Geom,
+            node_name: PlacementName::Geom,
+            exclude_types: Vec::new(),
+            blender_names: false,
+            xml_encoding: "utf-8".to_string(),
+            xml_standalone: None,
+            indent_string: "  ".to_string(),
+            line_separator: "\n".to_string(),
+        }
+    }
+}
 
 pub struct Sf2Collada<W> {
-    writer: EventWriter<W>,
+    writer: ColladaDocument<W>,
     sf: SceneTemplate,
-    include_placements: bool,
+    options: Sf2ColladaOptions,
+    progress: Option<ProgressFn>,
 }
 
 impl<W> Sf2Collada<W>
     where W: Write,
 {
-    pub fn new(sf: SceneTemplate, write: W, include_placements: bool) -> Sf2Collada<W> {
+    pub fn new(sf: SceneTemplate, write: W, options: Sf2ColladaOptions) -> Sf2Collada<W> {
         Sf2Collada {
-            writer: EventWriter::new_with_config(write, EmitterConfig::new().perform_indent(true)),
+            writer: ColladaDocument::new(write, DocumentOptions {
+                xml_encoding: &options.xml_encoding,
+                xml_standalone: options.xml_standalone,
+                scale: options.scale,
+                unit: options.unit,
+                indent_string: &options.indent_string,
+                line_separator: &options.line_separator,
+            }),
             sf: sf,
-            include_placements: include_placements,
+            options: options,
+            progress: None,
         }
     }
 
-    pub fn write_collada(&mut self) -> Result<(), EmitterError> {
-        self.write_start()?;
-        if self.include_placements {
+    /// Gives back the writer, e.g. to finish a compressed stream once the
+    /// document is written.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    /// Reports progress once per clump while writing the visual scene.
+    pub fn set_progress(&mut self, progress: ProgressFn) {
+        self.progress = Some(progress);
+    }
+
+    pub fn write_collada(&mut self) -> Result<(), ConvertError> {
+        self.check_placements()?;
+        self.writer.write_start()?;
+        if self.options.include_placements || self.options.show_bounds {
             self.write_library_nodes()?;
         }
+        if self.options.include_lights {
+            if self.has_placement(is_light) {
+                self.write_library_lights()?;
+            }
+            if self.has_placement(is_camera) {
+                self.write_library_cameras()?;
+            }
+        }
         self.write_library_visual_scenes()?;
-        self.write_end()
+        self.writer.write_end()?;
+        Ok(())
     }
 
-    fn write_start(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(
-            XmlEvent::start_element("COLLADA")
-                .attr("xmlns", "http://www.collada.org/2005/11/COLLADASchema")
-                .attr("version", "1.4.1")
-        )?;
-        self.writer.write(XmlEvent::start_element("asset"))?;
-        self.writer.write(XmlEvent::start_element("created"))?;
-        self.writer.write("2020-04-18T17:41:28")?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::start_element("modified"))?;
-        self.writer.write("2020-04-18T17:41:28")?;
-        self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())
+    fn check_placements(&self) -> Result<(), ConvertError> {
+        for (clump_idx, clump) in self.sf.clumps.iter().enumerate() {
+            for placement in clump.placements.iter() {
+                if self.is_excluded(&placement.data) {
+                    continue;
+                }
+                match &placement.data {
+                    ScenePlacementData::Unknown(main_type, sub_type, _) => {
+                        let msg = format!("skipping placement \"{}\" in clump {} with unknown type {} (sub type {})",
+                            placement.geom_name, clump_idx, main_type, sub_type);
+                        unhandled(self.options.strict, &msg)?;
+                    }
+                    // Only NXF geometry has a parser so far.
+                    data => {
+                        match data.geom_format() {
+                            // An empty name would make a ".dae#main_node" reference to nothing.
+                            Some(SceneGeomFormat::Nxf) if self.options.file_name.get(placement).is_empty() => {
+                                let msg = format!("skipping placement in clump {} with an empty {}",
+                                    clump_idx, self.options.file_name.field());
+                                unhandled(self.options.strict, &msg)?;
+                            }
+                            Some(SceneGeomFormat::Nxf) | None => {}
+                            Some(format) => {
+                                let msg = format!("skipping placement \"{}\" in clump {} with unsupported geometry format {:?}",
+                                    placement.geom_name, clump_idx, format);
+                                unhandled(self.options.strict, &msg)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn write_library_nodes(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::start_element("library_nodes"))?;
+    /// Name of a placement's node, made Blender safe when `blender_names`
+    /// is set.
+    fn node_name(&self, placement: &ScenePlacement) -> String {
+        let name = self.options.node_name.get(placement);
+        if self.options.blender_names {
+            blender_name(name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Extra data entry keeping a placement's original node name, for nodes
+    /// whose name was made Blender safe.
+    fn name_extra(&self, placement: &ScenePlacement) -> Option<(&'static str, String)> {
+        if self.options.blender_names {
+            Some(("name", self.options.node_name.get(placement).to_string()))
+        } else {
+            None
+        }
+    }
+
+    /// Whether a placement gets a node instancing its geometry file in the
+    /// visual scene.
+    fn is_instanced(&self, placement: &ScenePlacement) -> bool {
+        if self.is_excluded(&placement.data) || self.options.file_name.get(placement).is_empty() {
+            return false;
+        }
+        matches!(placement.data.geom_format(), Some(SceneGeomFormat::Nxf))
+    }
+
+    /// Whether any placement that isn't excluded matches `f`.
+    fn has_placement(&self, f: fn(&ScenePlacementData) -> bool) -> bool {
+        self.sf.clumps.iter()
+            .flat_map(|clump| clump.placements.iter())
+            .any(|placement| !self.is_excluded(&placement.data) && f(&placement.data))
+    }
+
+    fn is_excluded(&self, data: &ScenePlacementData) -> bool {
+        self.options.exclude_types.iter().any(|name| name == data.type_name())
+    }
 
+    fn write_library_nodes(&mut self) -> Result<(), ConvertError> {
+        self.writer.start_library("library_nodes")?;
+        if self.options.include_placements {
+            self.write_points_node()?;
+        }
+        if self.options.show_bounds {
+            self.write_bounds_node()?;
+        }
+        self.writer.end_library()?;
+        Ok(())
+    }
+
+    fn write_points_node(&mut self) -> Result<(), ConvertError> {
         self.writer.write(
             XmlEvent::start_element("node")
                 .attr("id", "points")
         )?;
         for clump in self.sf.clumps.iter() {
             for placement in clump.placements.iter() {
+                if self.is_excluded(&placement.data) {
+                    continue;
+                }
                 match placement.data {
-                    ScenePlacementData::Point(_) => {
+                    ScenePlacementData::Point(sub_type) => {
                         self.writer.write(
                             XmlEvent::start_element("node")
-                                .attr("name", &placement.geom_name)
+                                .attr("name", self.node_name(placement).as_str())
                         )?;
                         self.writer.write(XmlEvent::start_element("translate"))?;
-                        self.writer.write(format!("{} {} {}",
-                            placement.x_pos,
-                            -placement.y_pos,
-                            -placement.z_pos,
-                        ).as_str())?;
+                        // Same transform as the other placements, applied to
+                        // the point's origin.
+                        let scale = self.options.scale;
+                        let mat = Matrix::new()
+                            .scale((scale, scale, scale))
+                            .mult(&placement.world_matrix());
+                        let mat = self.options.flip_axes.apply_matrix(mat);
+                        let (x, y, z) = mat.transform_point((0.0, 0.0, 0.0));
+                        self.writer.write(format!("{} {} {}", x, y, z).as_str())?;
                         self.writer.write(XmlEvent::end_element())?;
+                        // Points are markers, not lights; the sphere only
+                        // makes them visible in a viewer.
                         self.writer.write(
                             XmlEvent::start_element("instance_geometry")
                                 .attr("url", "sphere.dae#Sphere-mesh")
                         )?;
                         self.writer.write(XmlEvent::end_element())?;
+
+                        let mut extra = vec![
+                            ("sub_type", sub_type.to_string()),
+                            ("model_name", placement.model_name.clone()),
+                        ];
+                        extra.extend(self.name_extra(placement));
+                        write_pmw2_extra(&mut self.writer, &extra)?;
+
                         self.writer.write(XmlEvent::end_element())?;
                     }
                     ScenePlacementData::BoundingBox{ min: (minx, miny, minz, _minw), max: (maxx, maxy, maxz, _maxw), .. } => {
                         self.writer.write(
                             XmlEvent::start_element("node")
-                                .attr("name", &placement.geom_name)
+                                .attr("name", self.node_name(placement).as_str())
                         )?;
 
                         self.writer.write(XmlEvent::start_element("matrix"))?;
-                        let mut mat = Matrix::new();
-                        let c_x = ((minx + maxx) / 2.0) + placement.x_pos;
-                        let c_y = ((miny + maxy) / 2.0) + placement.y_pos;
-                        let c_z = ((minz + maxz) / 2.0) + placement.z_pos;
-                        mat = mat.translate((c_x, -c_y, -c_z, placement.w_pos));
-                        mat = mat.scale(((maxx - minx) / 2.0, (maxy - miny) / 2.0, (maxz - minz) / 2.0));
-                        mat = mat.rot_yxz((placement.x_rot, -placement.y_rot, -placement.z_rot));
-                        self.writer.write(format!("{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
-                            mat.0[0x0], mat.0[0x1], mat.0[0x2], mat.0[0x3],
-                            mat.0[0x4], mat.0[0x5], mat.0[0x6], mat.0[0x7],
-                            mat.0[0x8], mat.0[0x9], mat.0[0xa], mat.0[0xb],
-                            mat.0[0xc], mat.0[0xd], mat.0[0xe], mat.0[0xf],
-                        ).as_str())?;
+                        let box_mat = Matrix::new()
+                            .translate(((minx + maxx) / 2.0, (miny + maxy) / 2.0, (minz + maxz) / 2.0))
+                            .scale(((maxx - minx) / 2.0, (maxy - miny) / 2.0, (maxz - minz) / 2.0));
+                        // The cube is an external unit mesh, so its extents get scaled too.
+                        let scale = self.options.scale;
+                        let mat = Matrix::new()
+                            .scale((scale, scale, scale))
+                            .mult(&placement.world_matrix())
+                            .mult(&box_mat);
+                        let mat = self.options.flip_axes.apply_matrix(mat);
+                        self.writer.write(matrix_string(&mat).as_str())?;
                         self.writer.write(XmlEvent::end_element())?;
 
                         self.writer.write(
@@ -106,6 +360,10 @@ impl<W> Sf2Collada<W>
                         )?;
                         self.writer.write(XmlEvent::end_element())?;
 
+                        if let Some(name) = self.name_extra(placement) {
+                            write_pmw2_extra(&mut self.writer, &[name])?;
+                        }
+
                         self.writer.write(XmlEvent::end_element())?;
                     }
                     _ => {}
@@ -113,64 +371,575 @@ impl<W> Sf2Collada<W>
             }
         }
         self.writer.write(XmlEvent::end_element())?;
-
-        self.writer.write(XmlEvent::end_element())
+        Ok(())
     }
 
-    fn write_library_visual_scenes(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::start_element("library_visual_scenes"))?;
+    fn write_bounds_node(&mut self) -> Result<(), ConvertError> {
         self.writer.write(
-            XmlEvent::start_element("visual_scene")
-                .attr("id", "visual_scene")
+            XmlEvent::start_element("node")
+                .attr("id", "bounds")
         )?;
+        let (min_x, max_x, min_z, max_z) = (self.sf.min_x, self.sf.max_x, self.sf.min_z, self.sf.max_z);
+        self.write_bounds_box("scene_bounds", min_x, max_x, min_z, max_z)?;
+        for clump_idx in 0..self.sf.clumps.len() {
+            let clump = &self.sf.clumps[clump_idx];
+            let (min_x, max_x, min_z, max_z) = (clump.min_x, clump.max_x, clump.min_z, clump.max_z);
+            self.write_bounds_box(&format!("clump_{}_bounds", clump_idx), min_x, max_x, min_z, max_z)?;
+        }
+        self.writer.write(XmlEvent::end_element())?;
+        Ok(())
+    }
 
+    /// Writes a flat box around an XZ region, centered on Y = 0.
+    fn write_bounds_box(&mut self, name: &str, min_x: f32, max_x: f32, min_z: f32, max_z: f32) -> Result<(), ConvertError> {
         self.writer.write(
             XmlEvent::start_element("node")
-                .attr("name", "__points")
+                .attr("name", name)
         )?;
+
+        self.writer.write(XmlEvent::start_element("matrix"))?;
+        let scale = self.options.scale;
+        let mat = Matrix::new()
+            .scale((scale, scale, scale))
+            .translate(((min_x + max_x) / 2.0, 0.0, (min_z + max_z) / 2.0))
+            .scale(((max_x - min_x) / 2.0, BOUNDS_HALF_HEIGHT, (max_z - min_z) / 2.0));
+        let mat = self.options.flip_axes.apply_matrix(mat);
+        self.writer.write(matrix_string(&mat).as_str())?;
+        self.writer.write(XmlEvent::end_element())?;
+
         self.writer.write(
-            XmlEvent::start_element("instance_node")
-                .attr("url", "#points")
+            XmlEvent::start_element("instance_geometry")
+                .attr("url", "cube.dae#Cube-mesh")
         )?;
         self.writer.write(XmlEvent::end_element())?;
+
         self.writer.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    fn write_library_lights(&mut self) -> Result<(), ConvertError> {
+        self.writer.start_library("library_lights")?;
+        for (clump_idx, clump) in self.sf.clumps.iter().enumerate() {
+            for (placement_idx, placement) in clump.placements.iter().enumerate() {
+                if self.is_excluded(&placement.data) {
+                    continue;
+                }
+                let (kind, sub_type, r, g, b) = match placement.data {
+                    ScenePlacementData::DirLight { sub_type, r, g, b } => ("directional", sub_type, r, g, b),
+                    ScenePlacementData::AmbientLight { sub_type, r, g, b } => ("ambient", sub_type, r, g, b),
+                    _ => continue,
+                };
+                self.writer.write(
+                    XmlEvent::start_element("light")
+                        .attr("id", format!("light_{}_{}", clump_idx, placement_idx).as_str())
+                        .attr("name", self.node_name(placement).as_str())
+                )?;
+                self.writer.write(XmlEvent::start_element("technique_common"))?;
+                self.writer.write(XmlEvent::start_element(kind))?;
+                self.writer.write(XmlEvent::start_element("color"))?;
+                self.writer.write(format!("{} {} {}", r, g, b).as_str())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::end_element())?;
+                write_pmw2_extra(&mut self.writer, &[("sub_type", sub_type.to_string())])?;
+                self.writer.write(XmlEvent::end_element())?;
+            }
+        }
+        self.writer.end_library()?;
+        Ok(())
+    }
+
+    fn write_library_cameras(&mut self) -> Result<(), ConvertError> {
+        self.writer.start_library("library_cameras")?;
+        for (clump_idx, clump) in self.sf.clumps.iter().enumerate() {
+            for (placement_idx, placement) in clump.placements.iter().enumerate() {
+                if self.is_excluded(&placement.data) {
+                    continue;
+                }
+                let (sub_type, field_of_view) = match placement.data {
+                    ScenePlacementData::Camera { sub_type, field_of_view, .. } => (sub_type, field_of_view),
+                    _ => continue,
+                };
+                self.writer.write(
+                    XmlEvent::start_element("camera")
+                        .attr("id", format!("camera_{}_{}", clump_idx, placement_idx).as_str())
+                        .attr("name", self.node_name(placement).as_str())
+                )?;
+                self.writer.write(XmlEvent::start_element("optics"))?;
+                self.writer.write(XmlEvent::start_element("technique_common"))?;
+                self.writer.write(XmlEvent::start_element("perspective"))?;
+                // The field of view's unit hasn't been confirmed; it's
+                // written as degrees, and kept as is in the extra data.
+                self.writer.write(XmlEvent::start_element("yfov"))?;
+                self.writer.write(field_of_view.to_string().as_str())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::start_element("znear"))?;
+                self.writer.write((CAMERA_ZNEAR * self.options.scale).to_string().as_str())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::start_element("zfar"))?;
+                self.writer.write((CAMERA_ZFAR * self.options.scale).to_string().as_str())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::end_element())?;
+                self.writer.write(XmlEvent::end_element())?;
+                write_pmw2_extra(&mut self.writer, &[
+                    ("sub_type", sub_type.to_string()),
+                    ("field_of_view", field_of_view.to_string()),
+                ])?;
+                self.writer.write(XmlEvent::end_element())?;
+            }
+        }
+        self.writer.end_library()?;
+        Ok(())
+    }
+
+    /// Writes a visual scene node instancing each exported light and
+    /// camera. Directional lights shine down their node's -Z, so they take
+    /// the placement's rotation; cameras are aimed at their interest point
+    /// with a `<lookat>`.
+    fn write_light_nodes(&mut self) -> Result<(), ConvertError> {
+        let scale = self.options.scale;
+        for (clump_idx, clump) in self.sf.clumps.iter().enumerate() {
+            for (placement_idx, placement) in clump.placements.iter().enumerate() {
+                if self.is_excluded(&placement.data) {
+                    continue;
+                }
+                if !is_light(&placement.data) && !is_camera(&placement.data) {
+                    continue;
+                }
+                self.writer.write(
+                    XmlEvent::start_element("node")
+                        .attr("name", self.node_name(placement).as_str())
+                )?;
+                let mat = Matrix::new()
+                    .scale((scale, scale, scale))
+                    .mult(&placement.world_matrix())
+                    .scale((1.0 / scale, 1.0 / scale, 1.0 / scale));
+                let mat = self.options.flip_axes.apply_matrix(mat);
+                if let ScenePlacementData::Camera { interest_x, interest_y, interest_z, .. } = placement.data {
+                    let (x, y, z) = mat.transform_point((0.0, 0.0, 0.0));
+                    let (ix, iy, iz) = self.options.flip_axes
+                        .apply((interest_x * scale, interest_y * scale, interest_z * scale));
+                    let (ux, uy, uz) = self.options.flip_axes.apply((0.0, 1.0, 0.0));
+                    self.writer.write(XmlEvent::start_element("lookat"))?;
+                    self.writer.write(format!("{} {} {} {} {} {} {} {} {}", x, y, z, ix, iy, iz, ux, uy, uz).as_str())?;
+                    self.writer.write(XmlEvent::end_element())?;
+                    self.writer.write(
+                        XmlEvent::start_element("instance_camera")
+                            .attr("url", format!("#camera_{}_{}", clump_idx, placement_idx).as_str())
+                    )?;
+                } else {
+                    self.writer.write(XmlEvent::start_element("matrix"))?;
+                    self.writer.write(matrix_string(&mat).as_str())?;
+                    self.writer.write(XmlEvent::end_element())?;
+                    self.writer.write(
+                        XmlEvent::start_element("instance_light")
+                            .attr("url", format!("#light_{}_{}", clump_idx, placement_idx).as_str())
+                    )?;
+                }
+                self.writer.write(XmlEvent::end_element())?;
+                if let Some(name) = self.name_extra(placement) {
+                    write_pmw2_extra(&mut self.writer, &[name])?;
+                }
+                self.writer.write(XmlEvent::end_element())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_library_visual_scenes(&mut self) -> Result<(), ConvertError> {
+        self.writer.start_library("library_visual_scenes")?;
+        self.writer.write(
+            XmlEvent::start_element("visual_scene")
+                .attr("id", "visual_scene")
+        )?;
+
+        if self.options.include_placements {
+            self.writer.write(
+                XmlEvent::start_element("node")
+                    .attr("name", "__points")
+            )?;
+            self.writer.write(
+                XmlEvent::start_element("instance_node")
+                    .attr("url", "#points")
+            )?;
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+        }
 
+        if self.options.show_bounds {
+            self.writer.write(
+                XmlEvent::start_element("node")
+                    .attr("name", "__bounds")
+            )?;
+            self.writer.write(
+                XmlEvent::start_element("instance_node")
+                    .attr("url", "#bounds")
+            )?;
+            self.writer.write(XmlEvent::end_element())?;
+            self.writer.write(XmlEvent::end_element())?;
+        }
+
+        let mut name_counts: HashMap<&str, usize> = HashMap::new();
         for clump in self.sf.clumps.iter() {
             for placement in clump.placements.iter() {
-                match placement.data {
-                    ScenePlacementData::Static(SceneGeomFormat::Nxf) |
-                    ScenePlacementData::StaticInst(SceneGeomFormat::Nxf) |
-                    ScenePlacementData::Ground(SceneGeomFormat::Nxf) |
-                    ScenePlacementData::GroundVU1(SceneGeomFormat::Nxf) |
-                    ScenePlacementData::Sky(SceneGeomFormat::Nxf) => {
-                        self.writer.write(
-                            XmlEvent::start_element("node")
-                                .attr("name", &placement.geom_name)
-                        )?;
-                        self.writer.write(XmlEvent::start_element("translate"))?;
-                        self.writer.write(format!("{} {} {}",
-                            placement.x_pos,
-                            -placement.y_pos,
-                            -placement.z_pos,
-                        ).as_str())?;
-                        self.writer.write(XmlEvent::end_element())?;
-                        self.writer.write(
-                            XmlEvent::start_element("instance_node")
-                                .attr("url", (placement.geom_name.clone() + ".dae#main_node").as_str())
-                        )?;
-                        self.writer.write(XmlEvent::end_element())?;
-                        self.writer.write(XmlEvent::end_element())?;
-                    }
-                    _ => {}
+                if self.is_instanced(placement) {
+                    *name_counts.entry(self.options.node_name.get(placement)).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut name_instances: HashMap<&str, usize> = HashMap::new();
+
+        let total_clumps = self.sf.clumps.len();
+        for (clump_idx, clump) in self.sf.clumps.iter().enumerate() {
+            if let Some(progress) = self.progress.as_mut() {
+                progress(clump_idx + 1, total_clumps, &format!("clump_{}", clump_idx));
+            }
+
+            if self.options.group_by_clump {
+                self.writer.write(
+                    XmlEvent::start_element("node")
+                        .attr("name", format!("clump_{}", clump_idx).as_str())
+                )?;
+            }
+
+            for placement in clump.placements.iter() {
+                if !self.is_instanced(placement) {
+                    continue;
+                }
+                let mut name = self.node_name(placement);
+                // Repeated names get a per-instance suffix, e.g. crate_01.
+                if name_counts[self.options.node_name.get(placement)] > 1 {
+                    let instance = name_instances.entry(self.options.node_name.get(placement)).or_insert(0);
+                    *instance += 1;
+                    name = format!("{}_{:02}", name, instance);
+                }
+                self.writer.write(
+                    XmlEvent::start_element("node")
+                        .attr("name", name.as_str())
+                )?;
+                self.writer.write(XmlEvent::start_element("matrix"))?;
+                // The instanced geometry is already scaled, so only the
+                // translation changes (S * M * S^-1).
+                let scale = self.options.scale;
+                let mat = Matrix::new()
+                    .scale((scale, scale, scale))
+                    .mult(&placement.world_matrix())
+                    .scale((1.0 / scale, 1.0 / scale, 1.0 / scale));
+                let mat = self.options.flip_axes.apply_matrix(mat);
+                self.writer.write(matrix_string(&mat).as_str())?;
+                self.writer.write(XmlEvent::end_element())?;
+                let url = format!("{}#{}",
+                    self.options.geom_file_template.replace("{name}", self.options.file_name.get(placement)),
+                    self.options.geom_node_id.replace("{name}", self.options.file_name.get(placement)));
+                self.writer.write(
+                    XmlEvent::start_element("instance_node")
+                        .attr("url", url.as_str())
+                )?;
+                self.writer.write(XmlEvent::end_element())?;
+                if let Some(name) = self.name_extra(placement) {
+                    write_pmw2_extra(&mut self.writer, &[name])?;
                 }
+                self.writer.write(XmlEvent::end_element())?;
+            }
+
+            if self.options.group_by_clump {
+                self.writer.write(XmlEvent::end_element())?;
             }
         }
 
+        if self.options.include_lights {
+            self.write_light_nodes()?;
+        }
+
         self.writer.write(XmlEvent::end_element())?;
-        self.writer.write(XmlEvent::end_element())
+        self.writer.end_library()?;
+        Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use nxf::capture_log::capture_warnings;
+    use sf::fixture::{self, ClumpFixture, PlacementFixture};
+
+    use super::*;
 
-    fn write_end(&mut self) -> Result<(), EmitterError> {
-        self.writer.write(XmlEvent::end_element())
+    fn clump(placements: Vec<PlacementFixture>) -> ClumpFixture {
+        ClumpFixture {
+            bounds: (0.0, 10.0, 0.0, 10.0),
+            placements: placements,
+        }
+    }
+
+    fn convert(clumps: &[ClumpFixture], options: Sf2ColladaOptions) -> Result<String, ConvertError> {
+        let sf = SceneTemplate::from_read(Cursor::new(fixture::build("town", clumps))).unwrap();
+        let mut converter = Sf2Collada::new(sf, Vec::new(), options);
+        converter.write_collada()?;
+        Ok(String::from_utf8(converter.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn group_by_clump_nests_placements() {
+        let options = Sf2ColladaOptions {
+            group_by_clump: true,
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&[
+            clump(vec![PlacementFixture::new(0, 9, "house")]),
+            clump(vec![PlacementFixture::new(0, 9, "tree")]),
+        ], options).unwrap();
+        let clump_0 = dae.find("<node name=\"clump_0\">").unwrap();
+        let clump_1 = dae.find("<node name=\"clump_1\">").unwrap();
+        assert!(clump_0 < clump_1);
+        assert!(dae[clump_0..clump_1].contains("<node name=\"house\">"), "{}", dae);
+        assert!(!dae[clump_0..clump_1].contains("<node name=\"tree\">"), "{}", dae);
+        assert!(dae[clump_1..].contains("<node name=\"tree\">"), "{}", dae);
+    }
+
+    #[test]
+    fn point_carries_sub_type() {
+        let options = Sf2ColladaOptions {
+            include_placements: true,
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&[
+            clump(vec![PlacementFixture::new(6, 3, "spawn")]),
+        ], options).unwrap();
+        let node = dae.find("<node name=\"spawn\">").unwrap();
+        let node = &dae[node..];
+        assert!(node.contains("<technique profile=\"PMW2\">"), "{}", dae);
+        assert!(node.contains("<sub_type>3</sub_type>"), "{}", dae);
+        assert!(node.contains("<model_name>spawn</model_name>"), "{}", dae);
+    }
+
+    #[test]
+    fn unknown_placement_errors_when_strict_and_warns_otherwise() {
+        let mut odd = PlacementFixture::new(99, 7, "odd");
+        odd.data = vec![1, 2, 3, 4];
+        let clumps = [clump(vec![odd, PlacementFixture::new(0, 9, "house")])];
+
+        let options = Sf2ColladaOptions {
+            strict: true,
+            ..Sf2ColladaOptions::default()
+        };
+        match convert(&clumps, options) {
+            Err(ConvertError::Strict(msg)) => assert!(msg.contains("\"odd\"") && msg.contains("unknown type 99"), "{}", msg),
+            other => panic!("expected a strict mode error, got {:?}", other.map(|_| ())),
+        }
+
+        let (dae, warnings) = capture_warnings(|| convert(&clumps, Sf2ColladaOptions::default()));
+        let dae = dae.unwrap();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].contains("unknown type 99"), "{:?}", warnings);
+        assert!(!dae.contains("<node name=\"odd\">"), "{}", dae);
+        assert!(dae.contains("<node name=\"house\">"), "{}", dae);
+    }
+
+    #[test]
+    fn progress_fires_once_per_clump() {
+        let clumps = [
+            clump(vec![PlacementFixture::new(0, 9, "house")]),
+            clump(Vec::new()),
+            clump(vec![PlacementFixture::new(0, 9, "tree")]),
+        ];
+        let sf = SceneTemplate::from_read(Cursor::new(fixture::build("town", &clumps))).unwrap();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut converter = Sf2Collada::new(sf, Vec::new(), Sf2ColladaOptions::default());
+        let seen = calls.clone();
+        converter.set_progress(Box::new(move |done, total, what| {
+            seen.borrow_mut().push((done, total, what.to_string()));
+        }));
+        converter.write_collada().unwrap();
+        assert_eq!(*calls.borrow(), [
+            (1, 3, "clump_0".to_string()),
+            (2, 3, "clump_1".to_string()),
+            (3, 3, "clump_2".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn geom_file_template_fills_instance_urls() {
+        let clumps = [clump(vec![PlacementFixture::new(0, 9, "house")])];
+        let dae = convert(&clumps, Sf2ColladaOptions::default()).unwrap();
+        assert!(dae.contains("<instance_node url=\"house.dae#main_node\" />"), "{}", dae);
+
+        let options = Sf2ColladaOptions {
+            geom_file_template: "models/{name}.DAE".to_string(),
+            geom_node_id: "{name}_main_node".to_string(),
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&clumps, options).unwrap();
+        assert!(dae.contains("<instance_node url=\"models/house.DAE#house_main_node\" />"), "{}", dae);
+    }
+
+    #[test]
+    fn asset_names_authoring_tool_version() {
+        let dae = convert(&[clump(Vec::new())], Sf2ColladaOptions::default()).unwrap();
+        let tool = format!("<authoring_tool>pmw2_collada {}</authoring_tool>", env!("CARGO_PKG_VERSION"));
+        let asset = &dae[dae.find("<asset>").unwrap()..dae.find("</asset>").unwrap()];
+        assert!(asset.contains(&tool), "{}", asset);
+    }
+
+    #[test]
+    fn show_bounds_writes_scene_and_clump_boxes() {
+        let clumps = [clump(Vec::new()), clump(Vec::new()), clump(Vec::new())];
+        let dae = convert(&clumps, Sf2ColladaOptions::default()).unwrap();
+        assert!(!dae.contains("cube.dae#Cube-mesh"), "{}", dae);
+
+        let options = Sf2ColladaOptions {
+            show_bounds: true,
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&clumps, options).unwrap();
+        assert_eq!(dae.matches("url=\"cube.dae#Cube-mesh\"").count(), 1 + 3, "{}", dae);
+        assert!(dae.contains("<node name=\"scene_bounds\">"), "{}", dae);
+        assert!(dae.contains("<node name=\"clump_2_bounds\">"), "{}", dae);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn exclude_type_point_drops_point_nodes() {
+        let clumps = [clump(vec![
+            PlacementFixture::new(6, 3, "spawn"),
+            PlacementFixture::new(0, 9, "house"),
+            PlacementFixture::new(6, 0, "waypoint"),
+        ])];
+        let options = Sf2ColladaOptions {
+            include_placements: true,
+            exclude_types: vec!["Point".to_string()],
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&clumps, options).unwrap();
+        assert!(!dae.contains("spawn"), "{}", dae);
+        assert!(!dae.contains("waypoint"), "{}", dae);
+        assert!(!dae.contains("sphere.dae"), "{}", dae);
+        assert!(dae.contains("<node name=\"house\">"), "{}", dae);
+    }
+
+    #[test]
+    fn file_name_field_drives_instance_url() {
+        let mut house = PlacementFixture::new(0, 9, "house");
+        house.model_name = "house_model";
+        let clumps = [clump(vec![house])];
+
+        let dae = convert(&clumps, Sf2ColladaOptions::default()).unwrap();
+        assert!(dae.contains("<node name=\"house\">"), "{}", dae);
+        assert!(dae.contains("url=\"house.dae#main_node\""), "{}", dae);
+
+        let options = Sf2ColladaOptions {
+            file_name: PlacementName::Model,
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&clumps, options).unwrap();
+        assert!(dae.contains("<node name=\"house\">"), "{}", dae);
+        assert!(dae.contains("url=\"house_model.dae#main_node\""), "{}", dae);
+    }
+
+    #[test]
+    fn empty_geom_name_is_skipped() {
+        let clumps = [clump(vec![PlacementFixture::new(0, 9, ""), PlacementFixture::new(0, 9, "house")])];
+        let (dae, warnings) = capture_warnings(|| convert(&clumps, Sf2ColladaOptions::default()));
+        let dae = dae.unwrap();
+        assert!(!dae.contains("\".dae#"), "{}", dae);
+        assert!(dae.contains("url=\"house.dae#main_node\""), "{}", dae);
+        assert_eq!(warnings, ["skipping placement in clump 0 with an empty geom_name"]);
+
+        let options = Sf2ColladaOptions {
+            strict: true,
+            ..Sf2ColladaOptions::default()
+        };
+        assert!(convert(&clumps, options).is_err());
+    }
+
+    #[test]
+    fn point_translation_follows_placement_matrix() {
+        let mut spawn = PlacementFixture::new(6, 3, "spawn");
+        spawn.pos = (1.0, 2.0, 3.0);
+        spawn.rot = (0.0, 1.0, 0.0);
+        spawn.scale = (2.0, 2.0, 2.0);
+        let options = Sf2ColladaOptions {
+            include_placements: true,
+            scale: 10.0,
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&[clump(vec![spawn])], options).unwrap();
+        let start = dae.find("<translate>").unwrap() + "<translate>".len();
+        let translate: Vec<f32> = dae[start..start + dae[start..].find('<').unwrap()]
+            .split_whitespace()
+            .map(|v| v.parse().unwrap())
+            .collect();
+        // Rotation and scale leave the origin alone; the default flip
+        // negates y and z.
+        assert_eq!(translate, [10.0, -20.0, -30.0]);
+    }
+
+    #[test]
+    fn repeated_names_get_instance_suffixes() {
+        let clumps = [clump(vec![
+            PlacementFixture::new(0, 9, "crate"),
+            PlacementFixture::new(0, 9, "crate"),
+            PlacementFixture::new(0, 9, "house"),
+            PlacementFixture::new(0, 9, "crate"),
+        ])];
+        let dae = convert(&clumps, Sf2ColladaOptions::default()).unwrap();
+        for name in ["crate_01", "crate_02", "crate_03"].iter() {
+            assert!(dae.contains(&format!("<node name=\"{}\">", name)), "{}", dae);
+        }
+        assert!(!dae.contains("<node name=\"crate\">"), "{}", dae);
+        assert!(dae.contains("<node name=\"house\">"), "{}", dae);
+        assert_eq!(dae.matches("url=\"crate.dae#main_node\"").count(), 3, "{}", dae);
+    }
+
+    #[test]
+    fn points_stay_markers_and_lights_keep_their_kind() {
+        let rgb: Vec<u8> = [0.25f32, 0.5, 1.0].iter().flat_map(|v| v.to_be_bytes()).collect();
+        let mut sun = PlacementFixture::new(7, 0, "sun");
+        sun.data = rgb.clone();
+        let mut sky = PlacementFixture::new(8, 0, "sky");
+        sky.data = rgb;
+        let mut placements = vec![sun, sky];
+        for &(sub_type, name) in [(0, "spot_0"), (1, "spot_1"), (2, "spot_2"), (3, "spot_3")].iter() {
+            placements.push(PlacementFixture::new(6, sub_type, name));
+        }
+        let options = Sf2ColladaOptions {
+            include_placements: true,
+            include_lights: true,
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&[clump(placements)], options).unwrap();
+
+        assert_eq!(dae.matches("<light ").count(), 2, "{}", dae);
+        assert!(dae.contains("<directional>"), "{}", dae);
+        assert!(dae.contains("<ambient>"), "{}", dae);
+        assert!(!dae.contains("<point>"), "{}", dae);
+        assert_eq!(dae.matches("url=\"sphere.dae#Sphere-mesh\"").count(), 4, "{}", dae);
+    }
+
+    #[test]
+    fn one_camera_and_one_light_are_instanced_once() {
+        let floats = |values: &[f32]| -> Vec<u8> { values.iter().flat_map(|v| v.to_be_bytes()).collect() };
+        let mut camera = PlacementFixture::new(9, 0, "camera");
+        camera.data = floats(&[4.0, 5.0, 6.0, 45.0]);
+        let mut sun = PlacementFixture::new(7, 0, "sun");
+        sun.data = floats(&[1.0, 1.0, 1.0]);
+        let clumps = [clump(vec![camera, sun, PlacementFixture::new(0, 9, "house")])];
+        let options = Sf2ColladaOptions {
+            include_lights: true,
+            ..Sf2ColladaOptions::default()
+        };
+        let dae = convert(&clumps, options).unwrap();
+
+        assert!(dae.contains("<camera id=\"camera_0_0\""), "{}", dae);
+        assert!(dae.contains("<light id=\"light_0_1\""), "{}", dae);
+        let scene = &dae[dae.find("<library_visual_scenes>").unwrap()..];
+        assert_eq!(scene.matches("<instance_camera ").count(), 1, "{}", dae);
+        assert_eq!(scene.matches("<instance_light ").count(), 1, "{}", dae);
+        assert!(scene.contains("<instance_camera url=\"#camera_0_0\""), "{}", dae);
+        assert!(scene.contains("<instance_light url=\"#light_0_1\""), "{}", dae);
+        assert!(scene.contains("<lookat>"), "{}", dae);
+    }
+}
@@ -0,0 +1,36 @@
+use log::warn;
+
+use crate::error::ConvertError;
+
+/// Reports a construct the converter can't handle. In strict mode this is a
+/// hard error, otherwise it is logged as a warning and the caller skips it.
+pub fn unhandled(strict: bool, msg: &str) -> Result<(), ConvertError> {
+    if strict {
+        Err(ConvertError::Strict(msg.to_string()))
+    } else {
+        warn!("{}", msg);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nxf::capture_log::capture_warnings;
+
+    use super::*;
+
+    #[test]
+    fn unhandled_warns_unless_strict() {
+        let msg = "placement \"odd\" has unknown type 99";
+        let (result, warnings) = capture_warnings(|| unhandled(false, msg));
+        assert!(result.is_ok());
+        assert_eq!(warnings, [msg]);
+
+        let (result, warnings) = capture_warnings(|| unhandled(true, msg));
+        match result {
+            Err(ConvertError::Strict(err)) => assert_eq!(err, msg),
+            other => panic!("expected a strict mode error, got {:?}", other),
+        }
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+}
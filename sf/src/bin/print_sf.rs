@@ -1,11 +1,290 @@
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::env;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::num::ParseIntError;
+use std::path::Path;
+use std::process;
 
-use sf::SceneTemplate;
+use nxf::{NxfObjGeom, OffsetReader};
+use sf::{SceneGeomFormat, SceneTemplate};
+
+const USAGE: &str = "usage: print_sf [--csv | --summary | --unknowns | --find=NAME] [--offset=N] FILE
+       print_sf --extract=CONTAINER --index=INDEX [--out-dir=DIR] [--offset=N] FILE
+numbers are decimal or 0x prefixed hex";
+
+/// Prints `error` and the usage, then exits.
+fn usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+/// Parses a decimal or `0x` prefixed hex number.
+fn parse_num(s: &str) -> Result<u64, ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn write_csv<W>(sf: &SceneTemplate, mut out: W) -> io::Result<()>
+    where W: Write
+{
+    writeln!(out, "clump,type,main_type,sub_type,model_name,geom_name,x_pos,y_pos,z_pos,x_rot,y_rot,z_rot,w_rot,x_scale,y_scale,z_scale")?;
+    for (clump_idx, clump) in sf.clumps.iter().enumerate() {
+        for placement in clump.placements.iter() {
+            writeln!(out, "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                clump_idx,
+                placement.data.type_name(),
+                placement.main_type,
+                placement.sub_type,
+                csv_field(&placement.model_name),
+                csv_field(&placement.geom_name),
+                placement.x_pos,
+                placement.y_pos,
+                placement.z_pos,
+                placement.x_rot,
+                placement.y_rot,
+                placement.z_rot,
+                placement.w_rot,
+                placement.x_scale,
+                placement.y_scale,
+                placement.z_scale,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn print_summary(sf: &SceneTemplate) {
+    let stats = sf.stats();
+    println!("clumps: {}", stats.clumps);
+    println!("placements: {}", stats.placements);
+    for (type_name, count) in stats.placements_by_type.iter() {
+        println!("  {}: {}", type_name, count);
+    }
+    println!("unknown placements: {}", stats.unknown_placements);
+}
+
+fn print_unknowns(sf: &SceneTemplate) {
+    println!("main_type,sub_type,count");
+    for (main_type, sub_type, count) in sf.unknown_types() {
+        println!("{},{},{}", main_type, sub_type, count);
+    }
+}
+
+/// Reads an `--index` file: one `geom_name offset` pair per line, giving
+/// where each NXF starts in the `--extract` container. The SF only names its
+/// geometry, so the offsets have to come from the container's own table.
+fn read_index(path: &str) -> Result<HashMap<String, u64>, String> {
+    let mut index = HashMap::new();
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    for (line_idx, line) in text.lines().enumerate() {
+        let mut parts = line.split_whitespace();
+        if let (Some(name), Some(offset)) = (parts.next(), parts.next()) {
+            let offset = parse_num(offset)
+                .map_err(|err| format!("{}:{}: bad offset {:?}: {}", path, line_idx + 1, offset, err))?;
+            index.insert(name.to_owned(), offset);
+        }
+    }
+    Ok(index)
+}
+
+/// Makes a geometry name safe to use as a file name, the way the converter
+/// does: anything but ASCII letters, digits, `-` and `_` becomes an
+/// underscore, so a name with `/` or `..` can't write outside `--out-dir`.
+/// Names that come out the same get `_2`, `_3`, ... appended.
+fn file_name(name: &str, used: &mut HashSet<String>) -> String {
+    let base: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let base = if base.is_empty() { "unnamed".to_owned() } else { base };
+    let mut file_name = base.clone();
+    let mut n = 2;
+    while !used.insert(file_name.clone()) {
+        file_name = format!("{}_{}", base, n);
+        n += 1;
+    }
+    file_name
+}
+
+/// Writes each NXF the scene places to `<out_dir>/<geom_name>.nxf`, sliced
+/// out of the container at the offset the index gives for it. The NXF's
+/// length is how far parsing it reads. `geom_name` is made file name safe
+/// first, as in `file_name`.
+fn extract<R>(sf: &SceneTemplate, mut container: R, index: &HashMap<String, u64>, out_dir: &Path)
+    where R: Read + Seek
+{
+    let mut done = HashSet::new();
+    let mut used = HashSet::new();
+    for clump in sf.clumps.iter() {
+        for placement in clump.placements.iter() {
+            match placement.data.geom_format() {
+                Some(SceneGeomFormat::Nxf) => {}
+                _ => continue,
+            }
+            let name = &placement.geom_name;
+            if !done.insert(name.clone()) {
+                continue;
+            }
+            let offset = match index.get(name) {
+                Some(offset) => *offset,
+                None => {
+                    eprintln!("{}: not in index", name);
+                    continue;
+                }
+            };
+            container.seek(SeekFrom::Start(offset)).unwrap();
+            let len = match NxfObjGeom::from_read_with_len(OffsetReader::new(&mut container, offset)) {
+                Ok((_, len)) => len,
+                Err(err) => {
+                    eprintln!("{}: could not parse NXF at {:#x}: {}", name, offset, err);
+                    continue;
+                }
+            };
+            let mut bytes = vec![0; len as usize];
+            container.seek(SeekFrom::Start(offset)).unwrap();
+            container.read_exact(&mut bytes).unwrap();
+            let out = out_dir.join(format!("{}.nxf", file_name(name, &mut used)));
+            fs::write(&out, &bytes).unwrap();
+            println!("{}: {:#x} bytes at {:#x} -> {}", name, len, offset, out.display());
+        }
+    }
+}
 
 fn main() {
-    let filename = env::args().skip(1).next().unwrap();
+    let mut csv = false;
+    let mut summary = false;
+    let mut unknowns = false;
+    let mut find = None;
+    let mut offset = 0;
+    let mut extract_from = None;
+    let mut index = None;
+    let mut out_dir = ".".to_owned();
+    let mut filename = None;
+    for arg in env::args().skip(1) {
+        if arg == "--csv" {
+            csv = true;
+        } else if arg == "--summary" {
+            summary = true;
+        } else if arg == "--unknowns" {
+            unknowns = true;
+        } else if let Some(value) = arg.strip_prefix("--offset=") {
+            offset = parse_num(value)
+                .unwrap_or_else(|err| usage(&format!("{}: {}", arg, err)));
+        } else if let Some(value) = arg.strip_prefix("--extract=") {
+            extract_from = Some(value.to_owned());
+        } else if let Some(value) = arg.strip_prefix("--index=") {
+            index = Some(value.to_owned());
+        } else if let Some(value) = arg.strip_prefix("--out-dir=") {
+            out_dir = value.to_owned();
+        } else if let Some(value) = arg.strip_prefix("--find=") {
+            find = Some(value.to_owned());
+        } else {
+            filename = Some(arg);
+        }
+    }
+
+    let filename = filename.unwrap_or_else(|| usage("no file given"));
     let f = File::open(filename).unwrap();
-    let sf = SceneTemplate::from_read(f).unwrap();
-    println!("{:#?}", sf);
-}
\ No newline at end of file
+    let sf = SceneTemplate::from_read_at(f, offset).unwrap();
+    if let Some(container) = extract_from {
+        let index = index.unwrap_or_else(|| usage("--extract needs an --index"));
+        let index = read_index(&index).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        extract(&sf, File::open(container).unwrap(), &index, Path::new(&out_dir));
+    } else if csv {
+        write_csv(&sf, io::stdout().lock()).unwrap();
+    } else if summary {
+        print_summary(&sf);
+    } else if unknowns {
+        print_unknowns(&sf);
+    } else if let Some(name) = find {
+        println!("{:#?}", sf.find_by_name(&name));
+    } else {
+        println!("{:#?}", sf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use nxf::fixture::NxfFixture;
+    use sf::fixture::{self, ClumpFixture, PlacementFixture};
+
+    use super::*;
+
+    #[test]
+    fn extract_slices_embedded_nxf() {
+        let nxf = NxfFixture::quad();
+        // The quad's furthest read is its string, at 0x500 and 8 bytes long.
+        let nxf_len = 0x508;
+        let offset = 0x40;
+        let mut container = vec![0xff; offset];
+        container.extend_from_slice(&nxf.data[..nxf_len]);
+        container.extend_from_slice(&[0xff; 0x40]);
+
+        let names = ["../up", "a/b", "a_b"];
+        let sf = fixture::build("town", &[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: names.iter().map(|&name| PlacementFixture::new(0, 9, name)).collect(),
+        }]);
+        let sf = SceneTemplate::from_read(Cursor::new(&sf)).unwrap();
+        let index = names.iter().map(|&name| (name.to_owned(), offset as u64)).collect();
+
+        let out_dir = env::temp_dir().join(format!("print_sf_extract_{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+        extract(&sf, Cursor::new(&container), &index, &out_dir);
+        let mut written = fs::read_dir(&out_dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        written.sort();
+        let contents = written.iter()
+            .map(|name| fs::read(out_dir.join(name)).unwrap())
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&out_dir).unwrap();
+
+        assert_eq!(written, ["___up.nxf", "a_b.nxf", "a_b_2.nxf"]);
+        for bytes in contents {
+            assert_eq!(&bytes[..], &nxf.data[..nxf_len]);
+        }
+    }
+
+    #[test]
+    fn csv_has_a_row_per_placement() {
+        let mut unknown = PlacementFixture::new(99, 7, "odd,name");
+        unknown.data = vec![1, 2, 3, 4];
+        let sf = fixture::build("town", &[
+            ClumpFixture {
+                bounds: (0.0, 1.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(0, 9, "house"), unknown],
+            },
+            ClumpFixture {
+                bounds: (1.0, 2.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(6, 3, "spawn")],
+            },
+        ]);
+        let sf = SceneTemplate::from_read(Cursor::new(&sf)).unwrap();
+
+        let mut csv = Vec::new();
+        write_csv(&sf, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 1 + 3, "{}", csv);
+        assert!(rows[0].starts_with("clump,type,"));
+        assert!(rows[2].starts_with("0,Unknown,99,7,\"odd,name\","), "{}", rows[2]);
+        assert!(rows[3].starts_with("1,Point,6,3,spawn,"), "{}", rows[3]);
+    }
+}
@@ -0,0 +1,172 @@
+//! Builds SF files in memory for tests. Clumps are laid out one after
+//! another following the header and its clump offsets.
+
+/// A placement to write. `new` gives it no rotation, unit scale and no
+/// type specific data; set the fields to change that.
+pub struct PlacementFixture {
+    pub main_type: u32,
+    pub sub_type: u32,
+    pub model_name: &'static str,
+    pub geom_name: &'static str,
+    pub pos: (f32, f32, f32),
+    pub rot: (f32, f32, f32),
+    pub scale: (f32, f32, f32),
+    pub data: Vec<u8>,
+}
+
+impl PlacementFixture {
+    pub fn new(main_type: u32, sub_type: u32, name: &'static str) -> PlacementFixture {
+        PlacementFixture {
+            main_type: main_type,
+            sub_type: sub_type,
+            model_name: name,
+            geom_name: name,
+            pos: (0.0, 0.0, 0.0),
+            rot: (0.0, 0.0, 0.0),
+            scale: (1.0, 1.0, 1.0),
+            data: Vec::new(),
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        put_u32(out, self.main_type);
+        put_u32(out, self.sub_type);
+        put_name(out, self.model_name);
+        put_name(out, self.geom_name);
+        for &(x, y, z, w) in [
+            (self.pos.0, self.pos.1, self.pos.2, 1.0),
+            (self.rot.0, self.rot.1, self.rot.2, 0.0),
+            (self.scale.0, self.scale.1, self.scale.2, 1.0),
+        ].iter() {
+            for &value in [x, y, z, w].iter() {
+                put_f32(out, value);
+            }
+        }
+        put_u32(out, self.data.len() as u32);
+        out.extend_from_slice(&self.data);
+    }
+}
+
+/// A clump to write, with its `(min_x, max_x, min_z, max_z)` bounds.
+pub struct ClumpFixture {
+    pub bounds: (f32, f32, f32, f32),
+    pub placements: Vec<PlacementFixture>,
+}
+
+/// Size of the template header, up to its clump offsets.
+pub const HEADER_SIZE: usize = 0x48;
+
+/// Builds an SF template named `name` holding `clumps`. The template's
+/// bounds cover every clump's.
+pub fn build(name: &str, clumps: &[ClumpFixture]) -> Vec<u8> {
+    let mut bodies = Vec::new();
+    for clump in clumps {
+        let mut body = Vec::new();
+        put_u16(&mut body, clump.placements.len() as u16);
+        put_u16(&mut body, 0);
+        let (min_x, max_x, min_z, max_z) = clump.bounds;
+        for &value in [min_x, max_x, min_z, max_z].iter() {
+            put_f32(&mut body, value);
+        }
+        for placement in clump.placements.iter() {
+            placement.write(&mut body);
+        }
+        bodies.push(body);
+    }
+
+    let bounds = clumps.iter().fold(None, |bounds: Option<(f32, f32, f32, f32)>, clump| {
+        let (min_x, max_x, min_z, max_z) = clump.bounds;
+        Some(match bounds {
+            Some((a, b, c, d)) => (a.min(min_x), b.max(max_x), c.min(min_z), d.max(max_z)),
+            None => clump.bounds,
+        })
+    }).unwrap_or((0.0, 0.0, 0.0, 0.0));
+
+    let mut out = Vec::new();
+    put_u32(&mut out, 0x5346_0000);
+    put_u32(&mut out, 1);
+    put_f32(&mut out, 1.0);
+    put_name(&mut out, name);
+    put_f32(&mut out, 100.0);
+    put_f32(&mut out, 100.0);
+    for &value in [bounds.0, bounds.1, bounds.2, bounds.3].iter() {
+        put_f32(&mut out, value);
+    }
+    put_u16(&mut out, clumps.len() as u16);
+    put_u16(&mut out, 0);
+
+    let mut offset = HEADER_SIZE + 4 * clumps.len();
+    for body in bodies.iter() {
+        put_u32(&mut out, offset as u32);
+        offset += body.len();
+    }
+    for body in bodies.iter() {
+        out.extend_from_slice(body);
+    }
+    out
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    let mut field = [0; 0x20];
+    field[..name.len()].copy_from_slice(name.as_bytes());
+    out.extend_from_slice(&field);
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_f32(out: &mut Vec<u8>, value: f32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{SceneGeomFormat, ScenePlacementData, SceneTemplate};
+
+    #[test]
+    fn fixture_parses_back() {
+        let mut light = PlacementFixture::new(8, 0, "amb");
+        light.data = [0.25f32, 0.5, 1.0].iter().flat_map(|v| v.to_be_bytes()).collect();
+        let mut house = PlacementFixture::new(0, 9, "house");
+        house.pos = (1.0, 2.0, 3.0);
+        house.scale = (2.0, 2.0, 2.0);
+        let data = build("town", &[
+            ClumpFixture {
+                bounds: (0.0, 10.0, 0.0, 10.0),
+                placements: vec![house],
+            },
+            ClumpFixture {
+                bounds: (10.0, 20.0, -5.0, 0.0),
+                placements: vec![light],
+            },
+        ]);
+
+        let sf = SceneTemplate::from_read(Cursor::new(&data)).unwrap();
+        assert_eq!(sf.name, "town");
+        assert_eq!((sf.min_x, sf.max_x, sf.min_z, sf.max_z), (0.0, 20.0, -5.0, 10.0));
+        assert_eq!(sf.clumps.len(), 2);
+        assert_eq!((sf.clumps[1].min_x, sf.clumps[1].max_z), (10.0, 0.0));
+
+        let house = &sf.clumps[0].placements[0];
+        assert_eq!(house.model_name, "house");
+        assert_eq!((house.x_pos, house.y_pos, house.z_pos, house.w_pos), (1.0, 2.0, 3.0, 1.0));
+        assert_eq!(house.x_scale, 2.0);
+        match house.data {
+            ScenePlacementData::Static(SceneGeomFormat::Nxf) => {}
+            ref data => panic!("expected a static NXF placement, got {:?}", data),
+        }
+
+        match sf.clumps[1].placements[0].data {
+            ScenePlacementData::AmbientLight { r, g, b, .. } => assert_eq!((r, g, b), (0.25, 0.5, 1.0)),
+            ref data => panic!("expected an ambient light, got {:?}", data),
+        }
+    }
+}
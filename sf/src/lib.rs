@@ -1,7 +1,32 @@
-use std::io::{Read, Seek, SeekFrom, Error as IOError};
+#[cfg(any(test, feature = "fixture"))]
+pub mod fixture;
+pub mod matrix;
+
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom, Error as IOError, ErrorKind};
 use std::iter;
 
 use byteorder::{ReadBytesExt, BE};
+use log::debug;
+use nxf::ExtentReader;
+
+use crate::matrix::Matrix;
+
+/// Reads a fixed size name field. The name ends at the first NUL, or fills
+/// the field if there is none. An all-NUL field is the empty name, which is
+/// kept as `""`; names that aren't valid UTF-8 are an `InvalidData` error.
+fn read_name<R>(mut read: R, field: &str) -> Result<String, IOError>
+    where R: Read
+{
+    let mut bytes = [0; 0x20];
+    read.read_exact(&mut bytes)?;
+    let len = bytes
+        .iter()
+        .position(|x| *x == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8(bytes[0..len].to_owned())
+        .map_err(|err| IOError::new(ErrorKind::InvalidData, format!("{} is not valid UTF-8: {}", field, err)))
+}
 
 #[derive(Clone, Debug)]
 pub enum SceneGeomFormat {
@@ -40,6 +65,9 @@ pub enum ScenePlacementData {
     AnimatedInst,
     Ground(SceneGeomFormat),
     GroundVU1(SceneGeomFormat),
+    /// A bare position marker. Only the sub type is stored; there's no
+    /// color, range or falloff, so these aren't lights (lights use the
+    /// `DirLight`/`AmbientLight` types), just spots referenced by name.
     Point(u32),
     DirLight {
         sub_type: u32,
@@ -96,6 +124,46 @@ pub enum ScenePlacementData {
 }
 
 impl ScenePlacementData {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ScenePlacementData::Static(_) => "Static",
+            ScenePlacementData::StaticInst(_) => "StaticInst",
+            ScenePlacementData::Animated => "Animated",
+            ScenePlacementData::AnimatedInst => "AnimatedInst",
+            ScenePlacementData::Ground(_) => "Ground",
+            ScenePlacementData::GroundVU1(_) => "GroundVU1",
+            ScenePlacementData::Point(_) => "Point",
+            ScenePlacementData::DirLight { .. } => "DirLight",
+            ScenePlacementData::AmbientLight { .. } => "AmbientLight",
+            ScenePlacementData::Camera { .. } => "Camera",
+            ScenePlacementData::Path_ => "Path",
+            ScenePlacementData::AnimWithPath => "AnimWithPath",
+            ScenePlacementData::AnimWithoutPath => "AnimWithoutPath",
+            ScenePlacementData::BoundingBox { .. } => "BoundingBox",
+            ScenePlacementData::WorldSprite => "WorldSprite",
+            ScenePlacementData::PointList => "PointList",
+            ScenePlacementData::Sky(_) => "Sky",
+            ScenePlacementData::Bezier { .. } => "Bezier",
+            ScenePlacementData::ColCylinder { .. } => "ColCylinder",
+            ScenePlacementData::CoverList => "CoverList",
+            ScenePlacementData::CombatPath => "CombatPath",
+            ScenePlacementData::Unknown(..) => "Unknown",
+        }
+    }
+
+    /// Format of the geometry file the placement instances, for the
+    /// geometry-bearing types, or `None` for the rest.
+    pub fn geom_format(&self) -> Option<&SceneGeomFormat> {
+        match self {
+            ScenePlacementData::Static(format) |
+            ScenePlacementData::StaticInst(format) |
+            ScenePlacementData::Ground(format) |
+            ScenePlacementData::GroundVU1(format) |
+            ScenePlacementData::Sky(format) => Some(format),
+            _ => None,
+        }
+    }
+
     fn from_bytes(main_type: u32, sub_type: u32, data: Vec<u8>) -> Result<ScenePlacementData, IOError> {
         let mut read = &data[..];
         match main_type {
@@ -154,13 +222,18 @@ impl ScenePlacementData {
                 min: (read.read_f32::<BE>()?, read.read_f32::<BE>()?, read.read_f32::<BE>()?, read.read_f32::<BE>()?),
                 max: (read.read_f32::<BE>()?, read.read_f32::<BE>()?, read.read_f32::<BE>()?, read.read_f32::<BE>()?),
             }),
-            _ => Ok(ScenePlacementData::Unknown(main_type, sub_type, data)),
+            _ => {
+                debug!("unknown placement type {} (sub type {})", main_type, sub_type);
+                Ok(ScenePlacementData::Unknown(main_type, sub_type, data))
+            }
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct ScenePlacement {
+    pub main_type: u32,
+    pub sub_type: u32,
     pub model_name: String,
     pub geom_name: String,
     pub x_pos: f32,
@@ -185,21 +258,8 @@ impl ScenePlacement {
         let main_type = read.read_u32::<BE>()?;
         let sub_type = read.read_u32::<BE>()?;
 
-        let mut model_name_bytes = [0; 0x20];
-        read.read_exact(&mut model_name_bytes)?;
-        let model_name_len = model_name_bytes
-            .iter()
-            .position(|x| *x == 0)
-            .unwrap_or(0x20);
-        let model_name = String::from_utf8(model_name_bytes[0..model_name_len].to_owned()).unwrap();
-
-        let mut geom_name_bytes = [0; 0x20];
-        read.read_exact(&mut geom_name_bytes)?;
-        let geom_name_len = geom_name_bytes
-            .iter()
-            .position(|x| *x == 0)
-            .unwrap_or(0x20);
-        let geom_name = String::from_utf8(geom_name_bytes[0..geom_name_len].to_owned()).unwrap();
+        let model_name = read_name(&mut read, "model_name")?;
+        let geom_name = read_name(&mut read, "geom_name")?;
 
         let x_pos = read.read_f32::<BE>()?;
         let y_pos = read.read_f32::<BE>()?;
@@ -224,6 +284,8 @@ impl ScenePlacement {
 
         Ok(
             ScenePlacement {
+                main_type: main_type,
+                sub_type: sub_type,
                 model_name: model_name,
                 geom_name: geom_name,
                 x_pos: x_pos,
@@ -242,10 +304,25 @@ impl ScenePlacement {
             }
         )
     }
+
+    /// Builds the placement's transform (translate * rotate * scale) in the
+    /// scene's native coordinate space.
+    ///
+    /// `w_pos` is the position's homogeneous coordinate (1 in the files), so
+    /// it isn't used.
+    pub fn world_matrix(&self) -> Matrix {
+        Matrix::new()
+            .translate((self.x_pos, self.y_pos, self.z_pos))
+            .rot_yxz((self.x_rot, self.y_rot, self.z_rot))
+            .scale((self.x_scale, self.y_scale, self.z_scale))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct SceneClump {
+    /// The u16 after the placement count, zero in the known files. Kept
+    /// raw in case some format version puts flags or a count here.
+    pub pad: u16,
     pub min_x: f32,
     pub max_x: f32,
     pub min_z: f32,
@@ -254,34 +331,49 @@ pub struct SceneClump {
 }
 
 impl SceneClump {
-    fn from_read<R>(mut read: R) -> Result<SceneClump, IOError>
-        where R: Read + Seek
+    /// Reads a clump, handing each placement to `placement` instead of
+    /// storing it, so the returned clump has no placements of its own
+    /// unless `placement` adds them.
+    fn read<R, F>(mut read: R, placement: &mut F) -> Result<SceneClump, IOError>
+        where R: Read + Seek,
+              F: FnMut(&mut SceneClump, ScenePlacement),
     {
         let num_placements = read.read_u16::<BE>()?;
-        let _pad = read.read_u16::<BE>()?;
+        let pad = read.read_u16::<BE>()?;
+        if pad != 0 {
+            debug!("clump has nonzero pad {:#x}", pad);
+        }
         let min_x = read.read_f32::<BE>()?;
         let max_x = read.read_f32::<BE>()?;
         let min_z = read.read_f32::<BE>()?;
         let max_z = read.read_f32::<BE>()?;
 
-        let mut placements = Vec::new();
+        let mut clump = SceneClump {
+            pad: pad,
+            min_x: min_x,
+            max_x: max_x,
+            min_z: min_z,
+            max_z: max_z,
+            placements: Vec::new(),
+        };
         for _ in 0..num_placements {
-            let placement = ScenePlacement::from_read(&mut read)?;
-            placements.push(placement);
+            placement(&mut clump, ScenePlacement::from_read(&mut read)?);
         }
 
-        Ok (
-            SceneClump {
-                min_x: min_x,
-                max_x: max_x,
-                min_z: min_z,
-                max_z: max_z,
-                placements: placements,
-            }
-        )
+        Ok(clump)
     }
 }
 
+/// Counts describing a scene, from `SceneTemplate::stats`.
+#[derive(Clone, Debug, Default)]
+pub struct SceneStats {
+    pub clumps: usize,
+    pub placements: usize,
+    /// Placement count keyed by `ScenePlacementData::type_name`.
+    pub placements_by_type: BTreeMap<&'static str, usize>,
+    pub unknown_placements: usize,
+}
+
 #[derive(Clone, Debug)]
 pub struct SceneTemplate {
     pub header: u32,
@@ -294,24 +386,70 @@ pub struct SceneTemplate {
     pub max_x: f32,
     pub min_z: f32,
     pub max_z: f32,
+    /// The u16 after the clump count, kept raw like `SceneClump::pad`.
+    pub pad: u16,
     pub clumps: Vec<SceneClump>,
 }
 
 impl SceneTemplate {
-    pub fn from_read<R>(mut read: R) -> Result<SceneTemplate, IOError>
+    /// Parses an SF file. Clump offsets are absolute from the start of the
+    /// reader, which in standalone SF files is also the start of the file.
+    ///
+    /// On success the reader is left just past the furthest byte read, the
+    /// end of the last clump.
+    pub fn from_read<R>(read: R) -> Result<SceneTemplate, IOError>
+        where R: Read + Seek
+    {
+        SceneTemplate::read(read, 0, &mut |clump, placement| clump.placements.push(placement))
+    }
+
+    /// Like `from_read`, but also returns how many bytes the SF occupies
+    /// from the reader's starting position, up to the furthest byte read.
+    pub fn from_read_with_len<R>(mut read: R) -> Result<(SceneTemplate, u64), IOError>
         where R: Read + Seek
     {
+        let start = read.stream_position()?;
+        let mut read = ExtentReader::new(read);
+        let sf = SceneTemplate::from_read(&mut read)?;
+        Ok((sf, read.end().saturating_sub(start)))
+    }
+
+    /// Parses an SF embedded in a larger container, starting at
+    /// `base_offset`. Clump offsets are resolved relative to `base_offset`
+    /// instead of the start of the reader.
+    pub fn from_read_at<R>(mut read: R, base_offset: u64) -> Result<SceneTemplate, IOError>
+        where R: Read + Seek
+    {
+        read.seek(SeekFrom::Start(base_offset))?;
+        SceneTemplate::read(read, base_offset, &mut |clump, placement| clump.placements.push(placement))
+    }
+
+    /// Parses an SF file, calling `f` with each placement in file order
+    /// instead of keeping them. Only one placement is held in memory at a
+    /// time, for large scenes where the placements are only streamed
+    /// through.
+    pub fn for_each_placement<R, F>(read: R, mut f: F) -> Result<(), IOError>
+        where R: Read + Seek,
+              F: FnMut(&ScenePlacement),
+    {
+        SceneTemplate::read(read, 0, &mut |_, placement| f(&placement))?;
+        Ok(())
+    }
+
+    fn read<R, F>(read: R, base_offset: u64, placement: &mut F) -> Result<SceneTemplate, IOError>
+        where R: Read + Seek,
+              F: FnMut(&mut SceneClump, ScenePlacement),
+    {
+        let mut read = ExtentReader::new(read);
+        let start = read.stream_position()?;
+        let file_len = read.seek(SeekFrom::End(0))?;
+        read.seek(SeekFrom::Start(start))?;
+
         let header = read.read_u32::<BE>()?;
         let format = read.read_u32::<BE>()?;
         let version = read.read_f32::<BE>()?;
 
-        let mut name_bytes = [0; 0x20];
-        read.read_exact(&mut name_bytes)?;
-        let name_len = name_bytes
-            .iter()
-            .position(|x| *x == 0)
-            .unwrap_or(0x20);
-        let name = String::from_utf8(name_bytes[0..name_len].to_owned()).unwrap();
+        let name = read_name(&mut read, "template name")?;
 
         let x_cut_size = read.read_f32::<BE>()?;
         let z_cut_size = read.read_f32::<BE>()?;
@@ -321,16 +459,27 @@ impl SceneTemplate {
         let max_z = read.read_f32::<BE>()?;
 
         let num_clumps = read.read_u16::<BE>()?;
-        let _pad = read.read_u16::<BE>()?;
+        let pad = read.read_u16::<BE>()?;
+        if pad != 0 {
+            debug!("template has nonzero pad {:#x}", pad);
+        }
         let mut clumps = Vec::new();
-        for _ in 0..num_clumps {
+        for clump_idx in 0..num_clumps {
             let offset = read.read_u32::<BE>()?;
+            if base_offset + offset as u64 >= file_len {
+                return Err(IOError::new(
+                    ErrorKind::InvalidData,
+                    format!("clump {} has offset {:#x} past end of file (length {:#x})", clump_idx, offset, file_len),
+                ));
+            }
             let save = read.seek(SeekFrom::Current(0))?;
-            read.seek(SeekFrom::Start(offset as u64))?;
-            let clump = SceneClump::from_read(&mut read)?;
+            debug!("reading clump {} at {:#x}", clump_idx, base_offset + offset as u64);
+            read.seek(SeekFrom::Start(base_offset + offset as u64))?;
+            let clump = SceneClump::read(&mut read, placement)?;
             clumps.push(clump);
             read.seek(SeekFrom::Start(save as u64))?;
         }
+        read.seek(SeekFrom::Start(read.end()))?;
 
         Ok(
             SceneTemplate {
@@ -344,8 +493,414 @@ impl SceneTemplate {
                 max_x: max_x,
                 min_z: min_z,
                 max_z: max_z,
+                pad: pad,
                 clumps: clumps,
             }
         )
     }
-}
\ No newline at end of file
+
+    pub fn stats(&self) -> SceneStats {
+        let mut placements = 0;
+        let mut placements_by_type = BTreeMap::new();
+        let mut unknown_placements = 0;
+        for clump in self.clumps.iter() {
+            for placement in clump.placements.iter() {
+                placements += 1;
+                *placements_by_type.entry(placement.data.type_name()).or_insert(0) += 1;
+                if let ScenePlacementData::Unknown(..) = placement.data {
+                    unknown_placements += 1;
+                }
+            }
+        }
+
+        SceneStats {
+            clumps: self.clumps.len(),
+            placements: placements,
+            placements_by_type: placements_by_type,
+            unknown_placements: unknown_placements,
+        }
+    }
+
+    /// Lists each distinct `(main_type, sub_type)` that parsed as
+    /// `Unknown`, with how many placements have it, sorted by type.
+    pub fn unknown_types(&self) -> Vec<(u32, u32, usize)> {
+        let mut counts = BTreeMap::new();
+        for clump in self.clumps.iter() {
+            for placement in clump.placements.iter() {
+                if let ScenePlacementData::Unknown(main_type, sub_type, _) = placement.data {
+                    *counts.entry((main_type, sub_type)).or_insert(0) += 1;
+                }
+            }
+        }
+        counts.into_iter()
+            .map(|((main_type, sub_type), count)| (main_type, sub_type, count))
+            .collect()
+    }
+
+    /// Returns every placement, across all clumps, that matches `predicate`,
+    /// in file order.
+    pub fn find_placements<F>(&self, mut predicate: F) -> Vec<&ScenePlacement>
+        where F: FnMut(&ScenePlacement) -> bool
+    {
+        self.clumps.iter()
+            .flat_map(|clump| clump.placements.iter())
+            .filter(|placement| predicate(placement))
+            .collect()
+    }
+
+    /// Returns the placements whose `model_name` or `geom_name` is exactly
+    /// `name`.
+    pub fn find_by_name(&self, name: &str) -> Vec<&ScenePlacement> {
+        self.find_placements(|placement| placement.model_name == name || placement.geom_name == name)
+    }
+
+    /// Maps a world XZ position to the index of the clump covering it.
+    ///
+    /// The scene bounds are cut into a grid of `x_cut_size` by `z_cut_size`
+    /// cells, stored row by row along Z with X varying fastest; a position
+    /// on a cell edge belongs to the cell starting there. Returns `None` for
+    /// positions outside the bounds, past the last clump, or when a cut size
+    /// isn't positive.
+    pub fn clump_index_for(&self, x: f32, z: f32) -> Option<usize> {
+        if !(self.x_cut_size > 0.0 && self.z_cut_size > 0.0) {
+            return None;
+        }
+        if x < self.min_x || x >= self.max_x || z < self.min_z || z >= self.max_z {
+            return None;
+        }
+
+        let x_cells = ((self.max_x - self.min_x) / self.x_cut_size).ceil() as usize;
+        let x_cell = ((x - self.min_x) / self.x_cut_size) as usize;
+        let z_cell = ((z - self.min_z) / self.z_cut_size) as usize;
+
+        let index = z_cell * x_cells + x_cell;
+        if index < self.clumps.len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fixture::{self, ClumpFixture, PlacementFixture};
+
+    #[test]
+    fn len_stops_at_last_clump() {
+        let mut data = fixture::build("town", &[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![PlacementFixture::new(1, 0, "house")],
+        }]);
+        let len = data.len() as u64;
+        data.extend_from_slice(&[0xff; 0x20]);
+        let (_, read_len) = SceneTemplate::from_read_with_len(Cursor::new(&data)).unwrap();
+        assert_eq!(read_len, len);
+    }
+
+    /// Like the NXF golden test: the `{:#?}` dump of a checked in SF has to
+    /// match the one checked in beside it. Nothing in it varies between
+    /// runs; only line endings are normalized.
+    #[test]
+    fn debug_dump_matches_golden() {
+        let sf = SceneTemplate::from_read(Cursor::new(&include_bytes!("../testdata/town.sf")[..])).unwrap();
+        let golden = include_str!("../testdata/town.sf.txt").replace("\r\n", "\n");
+        assert_eq!(format!("{:#?}\n", sf), golden);
+    }
+
+    /// Parses a template built from `clumps`.
+    fn parse(clumps: &[ClumpFixture]) -> SceneTemplate {
+        SceneTemplate::from_read(Cursor::new(fixture::build("town", clumps))).unwrap()
+    }
+
+    #[test]
+    fn world_matrix_matches_hand_computed() {
+        let mut house = PlacementFixture::new(0, 9, "house");
+        house.pos = (1.0, 2.0, 3.0);
+        house.rot = (0.0, std::f32::consts::FRAC_PI_2, 0.0);
+        house.scale = (2.0, 2.0, 2.0);
+        let sf = parse(&[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![house],
+        }]);
+        // A quarter turn about Y sends x to -z and z to x, scaled by 2,
+        // with the position in the last column.
+        let expected = [
+             0.0, 0.0, 2.0, 1.0,
+             0.0, 2.0, 0.0, 2.0,
+            -2.0, 0.0, 0.0, 3.0,
+             0.0, 0.0, 0.0, 1.0,
+        ];
+        let matrix = sf.clumps[0].placements[0].world_matrix();
+        for (actual, expected) in matrix.0.iter().zip(expected.iter()) {
+            assert!((actual - expected).abs() < 1e-5, "{:?} != {:?}", matrix.0, expected);
+        }
+    }
+
+    #[test]
+    fn clump_offset_past_end_is_an_error() {
+        let mut data = fixture::build("town", &[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![PlacementFixture::new(0, 9, "house")],
+        }]);
+        let bad = data.len() as u32 + 0x100;
+        data[fixture::HEADER_SIZE..fixture::HEADER_SIZE + 4].copy_from_slice(&bad.to_be_bytes());
+        let err = SceneTemplate::from_read(Cursor::new(&data)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let message = err.to_string();
+        assert!(message.contains("clump 0"), "{}", message);
+        assert!(message.contains(&format!("{:#x}", bad)), "{}", message);
+        assert!(message.contains("past end of file"), "{}", message);
+    }
+
+    #[test]
+    fn clump_index_for_finds_grid_cell() {
+        // A 2x2 grid of 100 unit cells, the fixture's cut size, in row major
+        // order.
+        let clumps: Vec<ClumpFixture> = [(0.0, 0.0), (100.0, 0.0), (0.0, 100.0), (100.0, 100.0)].iter()
+            .map(|&(x, z)| ClumpFixture {
+                bounds: (x, x + 100.0, z, z + 100.0),
+                placements: Vec::new(),
+            })
+            .collect();
+        let sf = parse(&clumps);
+        assert_eq!((sf.x_cut_size, sf.z_cut_size), (100.0, 100.0));
+        assert_eq!(sf.clump_index_for(50.0, 50.0), Some(0));
+        assert_eq!(sf.clump_index_for(150.0, 50.0), Some(1));
+        assert_eq!(sf.clump_index_for(50.0, 150.0), Some(2));
+        assert_eq!(sf.clump_index_for(199.0, 101.0), Some(3));
+        // Edges belong to the cell starting there.
+        assert_eq!(sf.clump_index_for(100.0, 100.0), Some(3));
+        assert_eq!(sf.clump_index_for(-1.0, 50.0), None);
+        assert_eq!(sf.clump_index_for(200.0, 50.0), None);
+        assert_eq!(sf.clump_index_for(50.0, 250.0), None);
+
+        // Halving the cut sizes makes a 4x4 grid, which the four clumps
+        // only fill the first row of.
+        let mut data = fixture::build("town", &clumps);
+        data[0x2c..0x30].copy_from_slice(&50.0f32.to_be_bytes());
+        data[0x30..0x34].copy_from_slice(&50.0f32.to_be_bytes());
+        let sf = SceneTemplate::from_read(Cursor::new(&data)).unwrap();
+        assert_eq!(sf.clump_index_for(60.0, 10.0), Some(1));
+        assert_eq!(sf.clump_index_for(160.0, 10.0), Some(3));
+        assert_eq!(sf.clump_index_for(10.0, 60.0), None);
+
+        let mut sf = parse(&clumps);
+        sf.x_cut_size = 0.0;
+        assert_eq!(sf.clump_index_for(50.0, 50.0), None);
+    }
+
+    #[test]
+    fn stats_count_placements_by_type() {
+        let mut odd = PlacementFixture::new(99, 7, "odd");
+        odd.data = vec![1, 2, 3, 4];
+        let sf = parse(&[
+            ClumpFixture {
+                bounds: (0.0, 1.0, 0.0, 1.0),
+                placements: vec![
+                    PlacementFixture::new(0, 9, "house"),
+                    PlacementFixture::new(0, 9, "tree"),
+                    PlacementFixture::new(6, 3, "spawn"),
+                ],
+            },
+            ClumpFixture {
+                bounds: (1.0, 2.0, 0.0, 1.0),
+                placements: Vec::new(),
+            },
+            ClumpFixture {
+                bounds: (2.0, 3.0, 0.0, 1.0),
+                placements: vec![odd, PlacementFixture::new(6, 0, "waypoint")],
+            },
+        ]);
+        let stats = sf.stats();
+        assert_eq!(stats.clumps, 3);
+        assert_eq!(stats.placements, 5);
+        assert_eq!(stats.unknown_placements, 1);
+        let by_type: Vec<(&str, usize)> = stats.placements_by_type.into_iter().collect();
+        assert_eq!(by_type, [("Point", 2), ("Static", 2), ("Unknown", 1)]);
+    }
+
+    #[test]
+    fn unknown_types_are_counted_per_type() {
+        let unknown = |main_type, sub_type, name| {
+            let mut placement = PlacementFixture::new(main_type, sub_type, name);
+            placement.data = vec![0; 4];
+            placement
+        };
+        let sf = parse(&[
+            ClumpFixture {
+                bounds: (0.0, 1.0, 0.0, 1.0),
+                placements: vec![unknown(99, 7, "a"), PlacementFixture::new(0, 9, "house"), unknown(42, 1, "b")],
+            },
+            ClumpFixture {
+                bounds: (1.0, 2.0, 0.0, 1.0),
+                placements: vec![unknown(99, 7, "c")],
+            },
+        ]);
+        assert_eq!(sf.unknown_types(), [(42, 1, 1), (99, 7, 2)]);
+    }
+
+    #[test]
+    fn find_by_name_searches_every_clump() {
+        let mut gate = PlacementFixture::new(0, 9, "gate");
+        gate.model_name = "gate_model";
+        let sf = parse(&[
+            ClumpFixture {
+                bounds: (0.0, 1.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(0, 9, "house")],
+            },
+            ClumpFixture {
+                bounds: (1.0, 2.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(0, 9, "tree"), gate],
+            },
+        ]);
+        let found = sf.find_by_name("gate");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].model_name, "gate_model");
+        assert_eq!(sf.find_by_name("gate_model").len(), 1);
+        assert!(sf.find_by_name("gat").is_empty());
+    }
+
+    #[test]
+    fn from_read_at_resolves_clumps_from_base() {
+        let data = fixture::build("town", &[
+            ClumpFixture {
+                bounds: (0.0, 1.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(0, 9, "house")],
+            },
+            ClumpFixture {
+                bounds: (1.0, 2.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(0, 9, "tree"), PlacementFixture::new(6, 3, "spawn")],
+            },
+        ]);
+        let base = 0x30;
+        let mut container = vec![0xff; base];
+        container.extend_from_slice(&data);
+        container.extend_from_slice(&[0xff; 0x20]);
+
+        let sf = SceneTemplate::from_read_at(Cursor::new(&container), base as u64).unwrap();
+        let names: Vec<Vec<&str>> = sf.clumps.iter()
+            .map(|clump| clump.placements.iter().map(|placement| placement.geom_name.as_str()).collect())
+            .collect();
+        assert_eq!(names, [vec!["house"], vec!["tree", "spawn"]]);
+        assert_eq!(sf.clumps[1].min_x, 1.0);
+    }
+
+    #[test]
+    fn name_fields_keep_empty_and_full_length_names() {
+        let full = "abcdefghijklmnopqrstuvwxyz012345";
+        let mut placement = PlacementFixture::new(0, 9, "");
+        placement.model_name = full;
+        let clumps = [ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![placement],
+        }];
+        let sf = parse(&clumps);
+        let placement = &sf.clumps[0].placements[0];
+        assert_eq!(placement.model_name, full);
+        assert_eq!(placement.geom_name, "");
+
+        // Clump offset, clump count and pad, bounds, then the types.
+        let mut data = fixture::build("town", &clumps);
+        let model_name = fixture::HEADER_SIZE + 4 + 4 + 16 + 8;
+        data[model_name] = 0xff;
+        let err = SceneTemplate::from_read(Cursor::new(&data)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("model_name"), "{}", err);
+    }
+
+    #[test]
+    fn for_each_placement_visits_each_placement_in_order() {
+        let data = fixture::build("town", &[
+            ClumpFixture {
+                bounds: (0.0, 1.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(0, 9, "house"), PlacementFixture::new(6, 3, "spawn")],
+            },
+            ClumpFixture {
+                bounds: (1.0, 2.0, 0.0, 1.0),
+                placements: Vec::new(),
+            },
+            ClumpFixture {
+                bounds: (2.0, 3.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(0, 9, "tree")],
+            },
+        ]);
+        let mut names = Vec::new();
+        SceneTemplate::for_each_placement(Cursor::new(&data), |placement| names.push(placement.geom_name.clone())).unwrap();
+        assert_eq!(names, ["house", "spawn", "tree"]);
+    }
+
+    #[test]
+    fn reparsing_from_the_start_is_identical() {
+        let mut data = fixture::build("town", &[
+            ClumpFixture {
+                bounds: (0.0, 1.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(0, 9, "house")],
+            },
+            ClumpFixture {
+                bounds: (1.0, 2.0, 0.0, 1.0),
+                placements: vec![PlacementFixture::new(6, 0, "spawn")],
+            },
+        ]);
+        let len = data.len() as u64;
+        data.extend_from_slice(&[0xff; 0x10]);
+        let mut cursor = Cursor::new(&data);
+        let first = SceneTemplate::from_read(&mut cursor).unwrap();
+        assert_eq!(cursor.position(), len);
+
+        cursor.seek(SeekFrom::Start(0)).unwrap();
+        let second = SceneTemplate::from_read(&mut cursor).unwrap();
+        assert_eq!(format!("{:?}", first), format!("{:?}", second));
+        assert_eq!(cursor.position(), len);
+    }
+
+    #[test]
+    fn geom_format_only_for_geometry_types() {
+        let sf = parse(&[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![
+                PlacementFixture::new(0, 9, "house"),
+                PlacementFixture::new(20, 6, "sky"),
+                PlacementFixture::new(6, 0, "spawn"),
+            ],
+        }]);
+        let placements = &sf.clumps[0].placements;
+        match placements[0].data.geom_format() {
+            Some(SceneGeomFormat::Nxf) => {}
+            other => panic!("expected NXF, got {:?}", other),
+        }
+        match placements[1].data.geom_format() {
+            Some(SceneGeomFormat::Vu1) => {}
+            other => panic!("expected VU1, got {:?}", other),
+        }
+        assert!(placements[2].data.geom_format().is_none());
+    }
+
+    #[test]
+    fn nonzero_pads_are_kept() {
+        let mut data = fixture::build("town", &[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: vec![PlacementFixture::new(0, 9, "house")],
+        }]);
+        // The template's pad ends its header; the clump's follows its
+        // placement count.
+        data[fixture::HEADER_SIZE - 2..fixture::HEADER_SIZE].copy_from_slice(&0x1234u16.to_be_bytes());
+        let clump = fixture::HEADER_SIZE + 4;
+        data[clump + 2..clump + 4].copy_from_slice(&0xabcdu16.to_be_bytes());
+
+        let sf = SceneTemplate::from_read(Cursor::new(&data)).unwrap();
+        assert_eq!(sf.pad, 0x1234);
+        assert_eq!(sf.clumps[0].pad, 0xabcd);
+        assert_eq!(sf.clumps[0].placements.len(), 1);
+
+        let sf = parse(&[ClumpFixture {
+            bounds: (0.0, 1.0, 0.0, 1.0),
+            placements: Vec::new(),
+        }]);
+        assert_eq!(sf.pad, 0);
+        assert_eq!(sf.clumps[0].pad, 0);
+    }
+}
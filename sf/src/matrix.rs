@@ -0,0 +1,209 @@
+/// A 4x4 transform stored row major, for column vectors: the translation is
+/// in the last column. Each builder method right-multiplies, so the last one
+/// called is the first applied to a point.
+#[derive(Clone, Debug)]
+pub struct Matrix(pub [f32; 16]);
+
+impl Matrix {
+    pub fn new() -> Matrix {
+        Matrix(
+            [
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                0.0, 0.0, 1.0, 0.0,
+                0.0, 0.0, 0.0, 1.0,
+            ]
+        )
+    }
+
+    pub fn mult(self, other: &Matrix) -> Matrix {
+        let mut out = Matrix::new();
+
+        for row in 0..4 {
+            for col in 0..4 {
+                out.0[row * 4 + col] =
+                    self.0[row * 4 + 0] * other.0[(0 * 4) + col] +
+                    self.0[row * 4 + 1] * other.0[(1 * 4) + col] +
+                    self.0[row * 4 + 2] * other.0[(2 * 4) + col] +
+                    self.0[row * 4 + 3] * other.0[(3 * 4) + col];
+            }
+        }
+
+        out
+    }
+
+    pub fn rot_x(self, angle: f32) -> Matrix {
+        let cos = angle.cos();
+        let sin = angle.sin();
+        let rot = Matrix (
+            [
+                1.0, 0.0,  0.0, 0.0,
+                0.0, cos, -sin, 0.0,
+                0.0, sin,  cos, 0.0,
+                0.0, 0.0,  0.0, 1.0,
+            ]
+        );
+        self.mult(&rot)
+    }
+
+    pub fn rot_y(self, angle: f32) -> Matrix {
+        let cos = angle.cos();
+        let sin = angle.sin();
+        let rot = Matrix (
+            [
+                 cos, 0.0, sin, 0.0,
+                 0.0, 1.0, 0.0, 0.0,
+                -sin, 0.0, cos, 0.0,
+                 0.0, 0.0, 0.0, 1.0,
+            ]
+        );
+        self.mult(&rot)
+    }
+
+    pub fn rot_z(self, angle: f32) -> Matrix {
+        let cos = angle.cos();
+        let sin = angle.sin();
+        let rot = Matrix (
+            [
+                cos, -sin, 0.0, 0.0,
+                sin,  cos, 0.0, 0.0,
+                0.0,  0.0, 1.0, 0.0,
+                0.0,  0.0, 0.0, 1.0,
+            ]
+        );
+        self.mult(&rot)
+    }
+
+    /// Right-multiplies by a translation, like the other builders, so the
+    /// translation happens before anything already in the matrix.
+    pub fn translate(self, val: (f32, f32, f32)) -> Matrix {
+        let translate = Matrix (
+            [
+                1.0, 0.0, 0.0, val.0,
+                0.0, 1.0, 0.0, val.1,
+                0.0, 0.0, 1.0, val.2,
+                0.0, 0.0, 0.0, 1.0,
+            ]
+        );
+        self.mult(&translate)
+    }
+
+    pub fn scale(self, val: (f32, f32, f32)) -> Matrix {
+        let scale = Matrix (
+            [
+                val.0, 0.0,   0.0,   0.0,
+                0.0,   val.1, 0.0,   0.0,
+                0.0,   0.0,   val.2, 0.0,
+                0.0,   0.0,   0.0,   1.0,
+            ]
+        );
+        self.mult(&scale)
+    }
+
+    /// Transforms a position by the matrix, treating it as a column vector
+    /// with w = 1 and dividing the result by its w.
+    pub fn transform_point(&self, p: (f32, f32, f32)) -> (f32, f32, f32) {
+        let m = &self.0;
+        let x = m[0] * p.0 + m[1] * p.1 + m[2] * p.2 + m[3];
+        let y = m[4] * p.0 + m[5] * p.1 + m[6] * p.2 + m[7];
+        let z = m[8] * p.0 + m[9] * p.1 + m[10] * p.2 + m[11];
+        let w = m[12] * p.0 + m[13] * p.1 + m[14] * p.2 + m[15];
+        if w != 0.0 && w != 1.0 {
+            (x / w, y / w, z / w)
+        } else {
+            (x, y, z)
+        }
+    }
+
+    /// Transforms a direction by the matrix's upper 3x3, ignoring the
+    /// translation. Directions aren't normalized afterwards.
+    pub fn transform_direction(&self, d: (f32, f32, f32)) -> (f32, f32, f32) {
+        let m = &self.0;
+        (
+            m[0] * d.0 + m[1] * d.1 + m[2] * d.2,
+            m[4] * d.0 + m[5] * d.1 + m[6] * d.2,
+            m[8] * d.0 + m[9] * d.1 + m[10] * d.2,
+        )
+    }
+
+    /// Right-multiplies by Rz * Rx * Ry, so points are rotated about Y
+    /// first, then X, then Z.
+    pub fn rot_yxz(mut self, val: (f32, f32, f32)) -> Matrix {
+        self = self.rot_z(val.2);
+        self = self.rot_x(val.0);
+        self = self.rot_y(val.1);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Matrix;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_close(actual: (f32, f32, f32), expected: (f32, f32, f32)) {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        assert!(close(actual.0, expected.0) && close(actual.1, expected.1) && close(actual.2, expected.2),
+                "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn trs_scales_then_rotates_then_translates() {
+        let m = Matrix::new()
+            .translate((10.0, 0.0, 0.0))
+            .rot_z(FRAC_PI_2)
+            .scale((2.0, 1.0, 1.0));
+        // (1, 0, 0) scales to (2, 0, 0), rotates to (0, 2, 0), then moves.
+        assert_close(m.transform_point((1.0, 0.0, 0.0)), (10.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn translate_leaves_w_alone() {
+        let m = Matrix::new().translate((1.0, 2.0, 3.0));
+        assert_eq!(m.0[15], 1.0);
+        assert_close(m.transform_point((1.0, 1.0, 1.0)), (2.0, 3.0, 4.0));
+    }
+
+    #[test]
+    fn directions_ignore_translation() {
+        let m = Matrix::new()
+            .translate((5.0, 5.0, 5.0))
+            .scale((2.0, 2.0, 2.0));
+        assert_close(m.transform_direction((0.0, 1.0, 0.0)), (0.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn rot_yxz_applies_y_first() {
+        let yxz = Matrix::new().rot_yxz((FRAC_PI_2, FRAC_PI_2, 0.0));
+        let chained = Matrix::new().rot_x(FRAC_PI_2).rot_y(FRAC_PI_2);
+        let p = (1.0, 2.0, 3.0);
+        assert_close(yxz.transform_point(p), chained.transform_point(p));
+        // Y first sends (1, 0, 0) to (0, 0, -1); X then sends that to (0, 1, 0).
+        assert_close(yxz.transform_point((1.0, 0.0, 0.0)), (0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn transform_point_divides_by_w() {
+        let mut m = Matrix::new().translate((1.0, 2.0, 3.0));
+        m.0[15] = 2.0;
+        assert_close(m.transform_point((1.0, 0.0, 1.0)), (1.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn trs_matches_expected_elements() {
+        let m = Matrix::new()
+            .translate((1.0, 2.0, 3.0))
+            .rot_z(FRAC_PI_2)
+            .scale((2.0, 3.0, 4.0));
+        // Rz * S in the upper 3x3, the translation in the last column.
+        let expected = [
+            0.0, -3.0, 0.0, 1.0,
+            2.0,  0.0, 0.0, 2.0,
+            0.0,  0.0, 4.0, 3.0,
+            0.0,  0.0, 0.0, 1.0,
+        ];
+        for (actual, expected) in m.0.iter().zip(expected.iter()) {
+            assert!((actual - expected).abs() < 1e-5, "{:?} != {:?}", m.0, expected);
+        }
+    }
+}